@@ -1,6 +1,7 @@
-use binrw::{BinRead, BinResult, BinWrite, Endian};
+use binrw::{binread, BinRead, BinResult, BinWrite, Endian};
 use std::io::{Seek, Write};
 
+use crate::error::{slice_checked, Error, Result};
 use crate::utils::*;
 
 // Members that are commented out are part of the streaming data section, and need to be merged into the contents of the header data after extraction.
@@ -137,23 +138,26 @@ struct StreamingDataTreeFaceLeaf {
 // assert_eq!(std::mem::size_of<StreamingDataTreeFaceLeaf>, 20);
 
 impl StreamingDataTreeFaceLeaf {
-  pub fn to_tree_face_leaf(&self, global_vertices: &[Vector3]) -> TreeFaceLeaf {
-    let vertex = global_vertices.get(self.vertices[0] as usize).unwrap();
+  pub fn to_tree_face_leaf(&self, global_vertices: &[Vector3]) -> Result<TreeFaceLeaf> {
+    let vertex = global_vertices
+      .get(self.vertices[0] as usize)
+      .ok_or(Error::OutOfBounds {
+        context: "tree face leaf vertex",
+        offset: self.vertices[0] as usize,
+        size: 1,
+        available: global_vertices.len(),
+      })?;
     let normal = Vector3 {
       x: self.vector.x as f32 / 16384.0_f32,
       y: self.vector.y as f32 / 16384.0_f32,
       z: self.vector.z as f32 / 16384.0_f32,
     };
-    let dvalue = -1.0_f32 * (vertex.x * normal.x + vertex.y * normal.y + vertex.z * normal.z);
-    TreeFaceLeaf {
+    let dvalue = -(vertex.x * normal.x + vertex.y * normal.y + vertex.z * normal.z);
+    Ok(TreeFaceLeaf {
       dvalue,
       vector: normal,
-      vertices: [
-        self.vertices[0] as i16,
-        self.vertices[1] as i16,
-        self.vertices[2] as i16,
-      ],
-    }
+      vertices: self.vertices,
+    })
   }
 }
 
@@ -188,6 +192,8 @@ struct SoultreeCollisionObject {
   // tree_face_leaves: Vec<TreeFaceLeaf>,
 }
 
+// not read yet, see `CollisionModel`
+#[allow(dead_code)]
 #[derive(BinRead, BinWrite, Debug)]
 struct FinitePlaneStruct {
   local_vertex_bl: Vector3,
@@ -203,8 +209,11 @@ struct StreamingHeirarchyEntry {
   object: SoultreeCollisionObject,
 }
 
-#[derive(BinRead, Debug)]
+#[binread]
+#[derive(Debug)]
 pub struct CollisionModel {
+  // written back as 1234
+  #[br(temp)]
   magic: u32,
   col_type: [u8; 4],
   version: i32,
@@ -244,7 +253,7 @@ impl std::fmt::Display for StreamingCollisionModel {
       write!(
         f,
         "COL={}.col\nPosition={}\nLookVector={}\nUpVector={}\nZone={}\n",
-        clean_string(&self.model_info.name),
+        clean_string_lossy(&self.model_info.name),
         self.model_info.position,
         self.model_info.look_vector,
         self.model_info.up_vector,
@@ -254,29 +263,45 @@ impl std::fmt::Display for StreamingCollisionModel {
       write!(
         f,
         "COL={}.col\nPosition={}\nLookVector={}\nUpVector={}\n",
-        clean_string(&self.model_info.name),
+        clean_string_lossy(&self.model_info.name),
         self.model_info.position,
         self.model_info.look_vector,
         self.model_info.up_vector,
       )?;
     }
-    Ok(for param in self.parameters.iter() {
-      write!(f, "{}\n", param)?;
-    })
+    for param in self.parameters.iter() {
+      writeln!(f, "{}", param)?;
+    }
+    Ok(())
   }
 }
 
 // Derive BinrwNamedArgs
 #[derive(Clone, Debug)]
-pub struct CollisionModelArgs {
+pub struct CollisionModelArgs<'a> {
   pub ror: bool,
-  pub streaming_data: Vec<u8>,
+  pub streaming_data: &'a [u8],
+}
+
+fn check_streaming_data_size(consumed: usize, len: usize) -> BinResult<()> {
+  if consumed != len {
+    return Err(
+      Error::SizeMismatch {
+        context: "collision streaming data",
+        expected: consumed,
+        actual: len,
+      }
+      .into_binrw(consumed as u64),
+    );
+  }
+
+  Ok(())
 }
 
 // This BinWrite implementation actually restructures the streaming component data plus the header data in the SOI to form a proper GOL file.
 // As such, the streaming data must be passed to write_options.
 impl BinWrite for CollisionModel {
-  type Args<'a> = &'a CollisionModelArgs;
+  type Args<'a> = &'a CollisionModelArgs<'a>;
 
   fn write_options<W: Write + Seek>(
     &self,
@@ -290,18 +315,26 @@ impl BinWrite for CollisionModel {
 
     let mut offset_in_data: usize = 0;
 
-    let mut cursor = std::io::Cursor::new(&args.streaming_data);
+    let mut cursor = std::io::Cursor::new(args.streaming_data);
 
     match self.collision_type {
-      CollisionType::Soultree => panic!("Unsupported collision type!"),
-      CollisionType::SoultreeHeirarchy => panic!("Unsupported collision type!"),
-      CollisionType::Rays => panic!("Unsupported collision type!"),
-      CollisionType::DynamicRays => panic!("Unsupported collision type!"),
-      CollisionType::RadiusedLine => panic!("Unsupported collision type!"),
-      CollisionType::Sphere => panic!("Unsupported collision type!"),
-      CollisionType::Box => panic!("Unsupported collision type!"),
-      CollisionType::Ecosystem => panic!("Unsupported collision type!"),
-      CollisionType::FinitePlane => panic!("Unsupported collision type!"),
+      CollisionType::Soultree
+      | CollisionType::SoultreeHeirarchy
+      | CollisionType::Rays
+      | CollisionType::DynamicRays
+      | CollisionType::RadiusedLine
+      | CollisionType::Sphere
+      | CollisionType::Box
+      | CollisionType::Ecosystem
+      | CollisionType::FinitePlane => {
+        warn!(collision_type = %self.collision_type, "collision type can't be converted");
+        return Err(
+          Error::UnsupportedCollisionType {
+            collision_type: self.collision_type.to_string(),
+          }
+          .into_binrw(writer.stream_position()?),
+        );
+      }
       CollisionType::StreamingSoultree => {
         CollisionType::write_options(&CollisionType::Soultree, writer, endian, ())?;
 
@@ -326,8 +359,7 @@ impl BinWrite for CollisionModel {
               binrw::VecArgs::builder()
                 .count(self.object.vertex_count as usize)
                 .finalize(),
-            )
-            .unwrap();
+            )?;
 
             let mut truncated_vertices = Vec::new();
             for vec in vertices.iter() {
@@ -348,8 +380,7 @@ impl BinWrite for CollisionModel {
               binrw::VecArgs::builder()
                 .count(self.object.vertex_count as usize)
                 .finalize(),
-            )
-            .unwrap();
+            )?;
 
             Vec::<Vector3>::write_options(&global_vertices, writer, endian, ())?;
             offset_in_data += 12 * self.object.vertex_count as usize;
@@ -369,8 +400,7 @@ impl BinWrite for CollisionModel {
               binrw::VecArgs::builder()
                 .count(self.object.vertex_count as usize)
                 .finalize(),
-            )
-            .unwrap();
+            )?;
 
             let mut truncated_vertices = Vec::new();
             for vec in vertices.iter() {
@@ -391,8 +421,7 @@ impl BinWrite for CollisionModel {
               binrw::VecArgs::builder()
                 .count(self.object.vertex_count as usize)
                 .finalize(),
-            )
-            .unwrap();
+            )?;
 
             Vec::<Vector3i16>::write_options(&quantized_vertices, writer, endian, ())?;
             offset_in_data += 6 * self.object.vertex_count as usize;
@@ -422,8 +451,7 @@ impl BinWrite for CollisionModel {
                 binrw::VecArgs::builder()
                   .count(self.object.vertex_count as usize)
                   .finalize(),
-              )
-              .unwrap();
+              )?;
 
               let mut truncated_normals = Vec::new();
               for vec in normals.iter() {
@@ -437,10 +465,14 @@ impl BinWrite for CollisionModel {
               Vec::<Vector3>::write_options(&truncated_normals, writer, endian, ())?;
               offset_in_data += 16 * self.object.vertex_count as usize;
             } else {
-              let data = (&args.streaming_data
-                [offset_in_data..offset_in_data + 12 * self.object.vertex_count as usize])
-                .to_vec();
-              Vec::<u8>::write_options(&data, writer, endian, ())?;
+              let data = slice_checked(
+                args.streaming_data,
+                offset_in_data,
+                12 * self.object.vertex_count as usize,
+                "collision normals",
+              )
+              .map_err(|err| err.into_binrw(offset_in_data as u64))?;
+              data.write_options(writer, endian, ())?;
               offset_in_data += 12 * self.object.vertex_count as usize;
             }
           }
@@ -454,8 +486,7 @@ impl BinWrite for CollisionModel {
                 binrw::VecArgs::builder()
                   .count(self.object.vertex_count as usize)
                   .finalize(),
-              )
-              .unwrap();
+              )?;
 
               let mut truncated_normals = Vec::new();
               for vec in normals.iter() {
@@ -469,10 +500,14 @@ impl BinWrite for CollisionModel {
               Vec::<Vector3i16>::write_options(&truncated_normals, writer, endian, ())?;
               offset_in_data += 8 * self.object.vertex_count as usize;
             } else {
-              let data = (&args.streaming_data
-                [offset_in_data..offset_in_data + 6 * self.object.vertex_count as usize])
-                .to_vec();
-              Vec::<u8>::write_options(&data, writer, endian, ())?;
+              let data = slice_checked(
+                args.streaming_data,
+                offset_in_data,
+                6 * self.object.vertex_count as usize,
+                "collision normals",
+              )
+              .map_err(|err| err.into_binrw(offset_in_data as u64))?;
+              data.write_options(writer, endian, ())?;
               offset_in_data += 6 * self.object.vertex_count as usize;
             }
           }
@@ -490,8 +525,7 @@ impl BinWrite for CollisionModel {
             binrw::VecArgs::builder()
               .count(self.object.tree_face_count as usize)
               .finalize(),
-          )
-          .unwrap();
+          )?;
 
           let mut tree_faces = Vec::new();
           for vec in ror_tree_faces.iter() {
@@ -509,8 +543,7 @@ impl BinWrite for CollisionModel {
             binrw::VecArgs::builder()
               .count(self.object.tree_face_count as usize)
               .finalize(),
-          )
-          .unwrap();
+          )?;
 
           let mut tree_faces = Vec::new();
           for vec in mn_tree_faces.iter() {
@@ -529,8 +562,7 @@ impl BinWrite for CollisionModel {
             binrw::VecArgs::builder()
               .count(self.object.tree_face_leaf_count as usize)
               .finalize(),
-          )
-          .unwrap();
+          )?;
 
           let mut tree_face_leaves = Vec::new();
           for vec in ror_face_leaves.iter() {
@@ -548,18 +580,21 @@ impl BinWrite for CollisionModel {
             binrw::VecArgs::builder()
               .count(self.object.tree_face_leaf_count as usize)
               .finalize(),
-          )
-          .unwrap();
+          )?;
 
           let mut tree_face_leaves = Vec::new();
           for vec in mn_face_leaves.iter() {
-            tree_face_leaves.push(vec.to_tree_face_leaf(&global_vertices));
+            tree_face_leaves.push(
+              vec
+                .to_tree_face_leaf(&global_vertices)
+                .map_err(|err| err.into_binrw(offset_in_data as u64))?,
+            );
           }
 
           Vec::<TreeFaceLeaf>::write_options(&tree_face_leaves, writer, endian, ())?;
           offset_in_data += 20 * self.object.tree_face_leaf_count as usize;
         }
-        assert_eq!(offset_in_data, args.streaming_data.len());
+        check_streaming_data_size(offset_in_data, args.streaming_data.len())?;
       }
       CollisionType::StreamingHeirarchy => {
         CollisionType::write_options(&CollisionType::SoultreeHeirarchy, writer, endian, ())?;
@@ -588,8 +623,7 @@ impl BinWrite for CollisionModel {
                 binrw::VecArgs::builder()
                   .count(object.object.vertex_count as usize)
                   .finalize(),
-              )
-              .unwrap();
+              )?;
 
               let mut truncated_vertices = Vec::new();
               for vec in vertices.iter() {
@@ -610,8 +644,7 @@ impl BinWrite for CollisionModel {
                 binrw::VecArgs::builder()
                   .count(object.object.vertex_count as usize)
                   .finalize(),
-              )
-              .unwrap();
+              )?;
 
               Vec::<Vector3>::write_options(&global_vertices, writer, endian, ())?;
               offset_in_data += 12 * object.object.vertex_count as usize;
@@ -631,8 +664,7 @@ impl BinWrite for CollisionModel {
                 binrw::VecArgs::builder()
                   .count(object.object.vertex_count as usize)
                   .finalize(),
-              )
-              .unwrap();
+              )?;
 
               let mut truncated_vertices = Vec::new();
               for vec in vertices.iter() {
@@ -653,8 +685,7 @@ impl BinWrite for CollisionModel {
                 binrw::VecArgs::builder()
                   .count(object.object.vertex_count as usize)
                   .finalize(),
-              )
-              .unwrap();
+              )?;
 
               Vec::<Vector3i16>::write_options(&quantized_vertices, writer, endian, ())?;
               offset_in_data += 6 * object.object.vertex_count as usize;
@@ -684,8 +715,7 @@ impl BinWrite for CollisionModel {
                   binrw::VecArgs::builder()
                     .count(object.object.vertex_count as usize)
                     .finalize(),
-                )
-                .unwrap();
+                )?;
 
                 let mut truncated_normals = Vec::new();
                 for vec in normals.iter() {
@@ -699,10 +729,14 @@ impl BinWrite for CollisionModel {
                 Vec::<Vector3>::write_options(&truncated_normals, writer, endian, ())?;
                 offset_in_data += 16 * object.object.vertex_count as usize;
               } else {
-                let data = (&args.streaming_data
-                  [offset_in_data..offset_in_data + 12 * object.object.vertex_count as usize])
-                  .to_vec();
-                Vec::<u8>::write_options(&data, writer, endian, ())?;
+                let data = slice_checked(
+                  args.streaming_data,
+                  offset_in_data,
+                  12 * object.object.vertex_count as usize,
+                  "collision normals",
+                )
+                .map_err(|err| err.into_binrw(offset_in_data as u64))?;
+                data.write_options(writer, endian, ())?;
                 offset_in_data += 12 * object.object.vertex_count as usize;
               }
             }
@@ -716,8 +750,7 @@ impl BinWrite for CollisionModel {
                   binrw::VecArgs::builder()
                     .count(object.object.vertex_count as usize)
                     .finalize(),
-                )
-                .unwrap();
+                )?;
 
                 let mut truncated_normals = Vec::new();
                 for vec in normals.iter() {
@@ -731,10 +764,14 @@ impl BinWrite for CollisionModel {
                 Vec::<Vector3i16>::write_options(&truncated_normals, writer, endian, ())?;
                 offset_in_data += 8 * object.object.vertex_count as usize;
               } else {
-                let data = (&args.streaming_data
-                  [offset_in_data..offset_in_data + 6 * object.object.vertex_count as usize])
-                  .to_vec();
-                Vec::<u8>::write_options(&data, writer, endian, ())?;
+                let data = slice_checked(
+                  args.streaming_data,
+                  offset_in_data,
+                  6 * object.object.vertex_count as usize,
+                  "collision normals",
+                )
+                .map_err(|err| err.into_binrw(offset_in_data as u64))?;
+                data.write_options(writer, endian, ())?;
                 offset_in_data += 6 * object.object.vertex_count as usize;
               }
             }
@@ -752,8 +789,7 @@ impl BinWrite for CollisionModel {
               binrw::VecArgs::builder()
                 .count(object.object.tree_face_count as usize)
                 .finalize(),
-            )
-            .unwrap();
+            )?;
 
             let mut tree_faces = Vec::new();
             for vec in ror_tree_faces.iter() {
@@ -771,8 +807,7 @@ impl BinWrite for CollisionModel {
               binrw::VecArgs::builder()
                 .count(object.object.tree_face_count as usize)
                 .finalize(),
-            )
-            .unwrap();
+            )?;
 
             let mut tree_faces = Vec::new();
             for vec in mn_tree_faces.iter() {
@@ -791,8 +826,7 @@ impl BinWrite for CollisionModel {
               binrw::VecArgs::builder()
                 .count(object.object.tree_face_leaf_count as usize)
                 .finalize(),
-            )
-            .unwrap();
+            )?;
 
             let mut tree_face_leaves = Vec::new();
             for vec in ror_face_leaves.iter() {
@@ -810,19 +844,22 @@ impl BinWrite for CollisionModel {
               binrw::VecArgs::builder()
                 .count(object.object.tree_face_leaf_count as usize)
                 .finalize(),
-            )
-            .unwrap();
+            )?;
 
             let mut tree_face_leaves = Vec::new();
             for vec in mn_face_leaves.iter() {
-              tree_face_leaves.push(vec.to_tree_face_leaf(&global_vertices));
+              tree_face_leaves.push(
+                vec
+                  .to_tree_face_leaf(&global_vertices)
+                  .map_err(|err| err.into_binrw(offset_in_data as u64))?,
+              );
             }
 
             Vec::<TreeFaceLeaf>::write_options(&tree_face_leaves, writer, endian, ())?;
             offset_in_data += 20 * object.object.tree_face_leaf_count as usize;
           }
-          assert_eq!(offset_in_data, args.streaming_data.len());
         }
+        check_streaming_data_size(offset_in_data, args.streaming_data.len())?;
       }
       CollisionType::StreamingFinitePlane => {
         CollisionType::write_options(&CollisionType::FinitePlane, writer, endian, ())?;
@@ -830,7 +867,7 @@ impl BinWrite for CollisionModel {
         i32::write_options(&self.plane_count, writer, endian, ())?;
         Vector3::write_options(&self.half, writer, endian, ())?;
 
-        Vec::<u8>::write_options(&args.streaming_data, writer, endian, ())?;
+        args.streaming_data.write_options(writer, endian, ())?;
         // assert_eq!(self.plane_count as usize * 60, args.streaming_data.len());
       }
     }
@@ -0,0 +1,312 @@
+use std::io::{Seek, Write};
+
+use binrw::{BinRead, BinResult, BinWrite, Endian};
+
+use crate::error::{Error, Result};
+use crate::{
+  CollisionModelArgs, DXGHeader, DXGHeaderArgs, DXTStaticTextureHeader, DXTTextureHeader,
+  GCGHeader, GCGHeaderArgs, GCNStaticTextureHeader, GCNTextureHeader, GCTFormat, GXPaletteFormat,
+  MipChain, ResolvedComponent, RgbaImage, TextureFormat, X360StaticTextureHeader,
+  X360TextureHeader, XNGHeader, XNGHeaderArgs,
+};
+
+/// Streaming model headers that can be rebuilt into a standalone model file. The model header type
+/// identifies the platform, so it also carries the conventions used for the other component kinds.
+pub trait ModelConvert: BinRead<Args<'static> = ()> + 'static {
+  const ENDIAN: Endian;
+  /// Whether collision models are written with `ror` set.
+  const COLLISION_ROR: bool;
+  const MODEL_EXTENSION: &'static str;
+  const COLLISION_EXTENSION: &'static str;
+  const MOTION_PACK_EXTENSION: &'static str;
+
+  fn write_model<W: Write + Seek>(&self, writer: &mut W, streaming_data: &[u8]) -> BinResult<()>;
+}
+
+impl ModelConvert for DXGHeader {
+  const ENDIAN: Endian = Endian::Little;
+  const COLLISION_ROR: bool = false;
+  const MODEL_EXTENSION: &'static str = "dxg";
+  const COLLISION_EXTENSION: &'static str = "col";
+  const MOTION_PACK_EXTENSION: &'static str = "mot";
+
+  fn write_model<W: Write + Seek>(&self, writer: &mut W, streaming_data: &[u8]) -> BinResult<()> {
+    let args = DXGHeaderArgs { streaming_data };
+    self.write_options(writer, Self::ENDIAN, &args)
+  }
+}
+
+impl ModelConvert for XNGHeader {
+  const ENDIAN: Endian = Endian::Big;
+  const COLLISION_ROR: bool = false;
+  const MODEL_EXTENSION: &'static str = "xng";
+  const COLLISION_EXTENSION: &'static str = "gol";
+  const MOTION_PACK_EXTENSION: &'static str = "got";
+
+  fn write_model<W: Write + Seek>(&self, writer: &mut W, streaming_data: &[u8]) -> BinResult<()> {
+    let args = XNGHeaderArgs { streaming_data };
+    self.write_options(writer, Self::ENDIAN, &args)
+  }
+}
+
+impl ModelConvert for GCGHeader {
+  const ENDIAN: Endian = Endian::Big;
+  const COLLISION_ROR: bool = true;
+  const MODEL_EXTENSION: &'static str = "gcg";
+  const COLLISION_EXTENSION: &'static str = "gol";
+  const MOTION_PACK_EXTENSION: &'static str = "got";
+
+  fn write_model<W: Write + Seek>(&self, writer: &mut W, streaming_data: &[u8]) -> BinResult<()> {
+    let args = GCGHeaderArgs { streaming_data };
+    self.write_options(writer, Self::ENDIAN, &args)
+  }
+}
+
+/// Streaming texture headers that can be rebuilt into a standalone texture file.
+pub trait TextureConvert: BinRead<Args<'static> = ()> + 'static {
+  const EXTENSION: &'static str;
+
+  fn write_texture<W: Write + Seek>(
+    &self,
+    writer: &mut W,
+    endian: Endian,
+    streaming_data: &[u8],
+  ) -> BinResult<()>;
+
+  /// Decodes every mip level, biggest first.
+  fn decode_rgba(&self, streaming_data: &[u8]) -> Result<Vec<RgbaImage>>;
+}
+
+/// Static texture headers already hold the whole texture.
+pub trait StaticTextureConvert: BinRead<Args<'static> = ()> + 'static {
+  const EXTENSION: &'static str;
+
+  fn write_texture<W: Write + Seek>(&self, writer: &mut W, endian: Endian) -> BinResult<()>;
+
+  /// Decodes every mip level, biggest first.
+  fn decode_rgba(&self) -> Result<Vec<RgbaImage>>;
+}
+
+/// Joins the mips of a standalone texture into streaming data, biggest first. The mips have to be
+/// the `MipChain` of the texture's size.
+pub(crate) fn join_mips(
+  mut mips: Vec<(usize, usize, &[u8])>,
+  format: &impl TextureFormat,
+  (width, height): (u32, u32),
+) -> Result<Vec<u8>> {
+  if mips.is_empty() {
+    return Err(Error::MissingField { field: "mips" });
+  }
+
+  // standalone files store the smallest mip first
+  mips.sort_by_key(|&(width, height, _)| std::cmp::Reverse(width * height));
+
+  let mut streaming_data = Vec::new();
+  for (&(width, height, data), expected) in
+    mips
+      .iter()
+      .zip(MipChain::new(format, width, height, mips.len() as u32))
+  {
+    if (width, height) != (expected.width, expected.height) {
+      return Err(Error::InvalidTextureDimensions {
+        width: width as u32,
+        height: height as u32,
+        mip_count: mips.len() as u32,
+      });
+    }
+    if data.len() != expected.range.len() {
+      return Err(Error::SizeMismatch {
+        context: "texture mip data",
+        expected: expected.range.len(),
+        actual: data.len(),
+      });
+    }
+    streaming_data.extend_from_slice(data);
+  }
+
+  Ok(streaming_data)
+}
+
+impl TextureConvert for DXTTextureHeader {
+  const EXTENSION: &'static str = "dxt";
+
+  fn write_texture<W: Write + Seek>(
+    &self,
+    writer: &mut W,
+    endian: Endian,
+    streaming_data: &[u8],
+  ) -> BinResult<()> {
+    let pos = writer.stream_position()?;
+    let texture = self
+      .to_static(streaming_data)
+      .map_err(|err| err.into_binrw(pos))?;
+    texture.write_options(writer, endian, ())
+  }
+
+  fn decode_rgba(&self, streaming_data: &[u8]) -> Result<Vec<RgbaImage>> {
+    self.decode(streaming_data)
+  }
+}
+
+impl TextureConvert for GCNTextureHeader {
+  const EXTENSION: &'static str = "gct";
+
+  fn write_texture<W: Write + Seek>(
+    &self,
+    writer: &mut W,
+    endian: Endian,
+    streaming_data: &[u8],
+  ) -> BinResult<()> {
+    let pos = writer.stream_position()?;
+    let texture = self
+      .to_static(streaming_data)
+      .map_err(|err| err.into_binrw(pos))?;
+    texture.write_options(writer, endian, ())
+  }
+
+  fn decode_rgba(&self, streaming_data: &[u8]) -> Result<Vec<RgbaImage>> {
+    self.decode(streaming_data, untagged_palette_format(&self.format)?)
+  }
+}
+
+impl TextureConvert for X360TextureHeader {
+  const EXTENSION: &'static str = "dds";
+
+  fn write_texture<W: Write + Seek>(
+    &self,
+    writer: &mut W,
+    _endian: Endian,
+    streaming_data: &[u8],
+  ) -> BinResult<()> {
+    // the GPU layout has no standalone format of its own, so it's untiled into a DDS file
+    let pos = writer.stream_position()?;
+    self
+      .write_dds(writer, streaming_data)
+      .map_err(|err| err.into_binrw(pos))
+  }
+
+  fn decode_rgba(&self, streaming_data: &[u8]) -> Result<Vec<RgbaImage>> {
+    self.decode(streaming_data)
+  }
+}
+
+impl StaticTextureConvert for DXTStaticTextureHeader {
+  const EXTENSION: &'static str = "dxt";
+
+  fn write_texture<W: Write + Seek>(&self, writer: &mut W, endian: Endian) -> BinResult<()> {
+    self.write_options(writer, endian, ())
+  }
+
+  fn decode_rgba(&self) -> Result<Vec<RgbaImage>> {
+    self.decode()
+  }
+}
+
+impl StaticTextureConvert for GCNStaticTextureHeader {
+  const EXTENSION: &'static str = "gct";
+
+  fn write_texture<W: Write + Seek>(&self, writer: &mut W, endian: Endian) -> BinResult<()> {
+    self.write_options(writer, endian, ())
+  }
+
+  fn decode_rgba(&self) -> Result<Vec<RgbaImage>> {
+    self.decode(untagged_palette_format(&self.format)?)
+  }
+}
+
+// Nothing records the format of a Ci8 palette, not the texture header nor the palette itself, so
+// those textures are left undecoded and extraction reports the fallback. The other formats ignore
+// it.
+fn untagged_palette_format(format: &GCTFormat) -> Result<GXPaletteFormat> {
+  match format {
+    GCTFormat::Ci8 | GCTFormat::Ci8Mm => Err(Error::UnsupportedTextureFormat {
+      format: format!("{:?} with an unknown palette format", format),
+    }),
+    _ => Ok(GXPaletteFormat::RGB5A3),
+  }
+}
+
+impl StaticTextureConvert for X360StaticTextureHeader {
+  const EXTENSION: &'static str = "dds";
+
+  fn write_texture<W: Write + Seek>(&self, writer: &mut W, _endian: Endian) -> BinResult<()> {
+    // the SOI stores the complete DDS file
+    writer.write_all(&self.header_file)?;
+    Ok(())
+  }
+
+  fn decode_rgba(&self) -> Result<Vec<RgbaImage>> {
+    self.decode()
+  }
+}
+
+impl<StreamingTH: TextureConvert, StaticTH: StaticTextureConvert, MH: ModelConvert>
+  ResolvedComponent<'_, StreamingTH, StaticTH, MH>
+{
+  /// Extension of the standalone file `write` produces, `None` when there's nothing to convert.
+  pub fn extension(&self) -> Option<&'static str> {
+    match self {
+      ResolvedComponent::Model(_) => Some(MH::MODEL_EXTENSION),
+      ResolvedComponent::StreamingTexture(_) => Some(StreamingTH::EXTENSION),
+      ResolvedComponent::StaticTexture(_) => Some(StaticTH::EXTENSION),
+      ResolvedComponent::Collision(_) => Some(MH::COLLISION_EXTENSION),
+      ResolvedComponent::MotionPack(_) => Some(MH::MOTION_PACK_EXTENSION),
+      // there's no standalone format for these, they're written as is
+      ResolvedComponent::CollisionGrid | ResolvedComponent::UserData => Some("bin"),
+      ResolvedComponent::Missing { .. } => None,
+    }
+  }
+
+  /// Combines the SOI record with the component's streaming data into a standalone file.
+  pub fn write<W: Write + Seek>(&self, writer: &mut W, streaming_data: &[u8]) -> Result<()> {
+    let endian = MH::ENDIAN;
+
+    match self {
+      ResolvedComponent::Model(model) => model
+        .streaming_model_header
+        .write_model(writer, streaming_data)?,
+      ResolvedComponent::StreamingTexture(texture) => {
+        texture
+          .header
+          .write_texture(writer, endian, streaming_data)?
+      }
+      ResolvedComponent::StaticTexture(texture) => texture
+        .static_texture_header
+        .write_texture(writer, endian)?,
+      ResolvedComponent::Collision(collision_model) => {
+        collision_model.collision_model.write_options(
+          writer,
+          endian,
+          &CollisionModelArgs {
+            ror: MH::COLLISION_ROR,
+            streaming_data,
+          },
+        )?
+      }
+      ResolvedComponent::MotionPack(motion_pack) => {
+        motion_pack.header.write_options(writer, endian, ())?;
+        writer.write_all(streaming_data)?;
+      }
+      ResolvedComponent::CollisionGrid | ResolvedComponent::UserData => {
+        writer.write_all(streaming_data)?
+      }
+      ResolvedComponent::Missing { reason } => return Err(Error::MissingRecord { reason }),
+    }
+
+    Ok(())
+  }
+
+  /// Decodes a texture's mip levels, biggest first. `None` for components that aren't textures.
+  pub fn decode_texture(&self, streaming_data: &[u8]) -> Result<Option<Vec<RgbaImage>>> {
+    match self {
+      ResolvedComponent::StreamingTexture(texture) => {
+        texture.header.decode_rgba(streaming_data).map(Some)
+      }
+      ResolvedComponent::StaticTexture(texture) => {
+        texture.static_texture_header.decode_rgba().map(Some)
+      }
+      ResolvedComponent::Missing { reason } => Err(Error::MissingRecord { reason }),
+      _ => Ok(None),
+    }
+  }
+}
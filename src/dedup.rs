@@ -0,0 +1,274 @@
+use std::collections::HashMap;
+use std::io::Cursor;
+
+use crate::error::{Error, Result};
+use crate::extract::{convert_component, ConvertedComponent};
+use crate::sha256::Sha256;
+use crate::str::component_slice;
+use crate::{
+  ComponentKind, ComponentSource, ExtractOptions, ExtractedFile, ModelConvert, ResolvedComponent,
+  SoiSoup, StaticTextureConvert, Str, TextureConvert,
+};
+
+// where `add_level_with_sink` writes unique textures
+type Sink<'a> = &'a mut dyn FnMut(ExtractedFile) -> Result<()>;
+
+/// What `TextureReport` compares to find identical textures.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TextureHash {
+  /// The platform's own texture file, header included.
+  #[default]
+  Native,
+  /// The decoded pixels of every mip level, so the same image stored in different formats matches.
+  /// Textures the decoders don't support fall back to their native file.
+  Decoded,
+}
+
+/// One stored copy of a texture.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextureUsage {
+  /// The name the level was added under.
+  pub level: String,
+  pub section_id: u32,
+  pub section: String,
+  pub path: String,
+  /// Size of the texture's native file.
+  pub size: usize,
+}
+
+/// Textures with identical contents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextureGroup {
+  /// SHA-256 of the compared contents. Textures are grouped by it alone.
+  pub hash: [u8; 32],
+  /// In the order they were added.
+  pub usages: Vec<TextureUsage>,
+}
+
+impl TextureGroup {
+  pub fn is_duplicate(&self) -> bool {
+    self.usages.len() > 1
+  }
+
+  /// Bytes saved by keeping only the smallest copy.
+  pub fn redundant_bytes(&self) -> usize {
+    let total: usize = self.usages.iter().map(|usage| usage.size).sum();
+    let smallest = self.usages.iter().map(|usage| usage.size).min();
+    total - smallest.unwrap_or(0)
+  }
+}
+
+/// Groups the textures of several levels by the SHA-256 of their contents.
+#[derive(Debug, Default)]
+pub struct TextureReport {
+  hash: TextureHash,
+  skip_shared: bool,
+  groups: Vec<TextureGroup>,
+  // hash to the group with that hash
+  index: HashMap<[u8; 32], usize>,
+}
+
+impl TextureReport {
+  pub fn new(hash: TextureHash) -> Self {
+    Self {
+      hash,
+      ..Default::default()
+    }
+  }
+
+  /// Leaves out textures a section references from a shared one, which are stored once. Like
+  /// `ExtractOptions::skip_shared` it relies on an unverified TOC field and is off by default.
+  pub fn skip_shared(mut self, skip_shared: bool) -> Self {
+    self.skip_shared = skip_shared;
+    self
+  }
+
+  pub fn groups(&self) -> &[TextureGroup] {
+    &self.groups
+  }
+
+  /// The groups with more than one copy, biggest savings first.
+  pub fn duplicates(&self) -> Vec<&TextureGroup> {
+    let mut duplicates: Vec<_> = self
+      .groups
+      .iter()
+      .filter(|group| group.is_duplicate())
+      .collect();
+    duplicates.sort_by_key(|group| std::cmp::Reverse(group.redundant_bytes()));
+    duplicates
+  }
+
+  pub fn redundant_bytes(&self) -> usize {
+    self.groups.iter().map(TextureGroup::redundant_bytes).sum()
+  }
+
+  /// Adds every texture a level stores.
+  pub fn add_level<StreamingTH, StaticTH, MH>(
+    &mut self,
+    level: &str,
+    soup: &SoiSoup<StreamingTH, StaticTH, MH>,
+    str: &Str,
+  ) -> Result<()>
+  where
+    StreamingTH: TextureConvert,
+    StaticTH: StaticTextureConvert,
+    MH: ModelConvert,
+  {
+    self.add(level, soup, str, None)
+  }
+
+  /// Like `add_level`, converting each texture that wasn't seen before according to `options` and
+  /// handing the files to `sink`. Adding every level this way extracts each unique texture once.
+  pub fn add_level_with_sink<StreamingTH, StaticTH, MH, F>(
+    &mut self,
+    level: &str,
+    soup: &SoiSoup<StreamingTH, StaticTH, MH>,
+    str: &Str,
+    options: &ExtractOptions,
+    mut sink: F,
+  ) -> Result<()>
+  where
+    StreamingTH: TextureConvert,
+    StaticTH: StaticTextureConvert,
+    MH: ModelConvert,
+    F: FnMut(ExtractedFile) -> Result<()>,
+  {
+    self.add(level, soup, str, Some((options, &mut sink)))
+  }
+
+  fn add<StreamingTH, StaticTH, MH>(
+    &mut self,
+    level: &str,
+    soup: &SoiSoup<StreamingTH, StaticTH, MH>,
+    str: &Str,
+    mut output: Option<(&ExtractOptions, Sink<'_>)>,
+  ) -> Result<()>
+  where
+    StreamingTH: TextureConvert,
+    StaticTH: StaticTextureConvert,
+    MH: ModelConvert,
+  {
+    for (section_id, section) in soup.find_sections().iter().enumerate() {
+      let section_id = section_id as u32;
+      let (uncached_data, cached_data) = str.read_section_blocks_at(section)?;
+      let blocks = [
+        (&section.uncached_components, uncached_data),
+        (&section.cached_components, cached_data),
+      ];
+
+      for (components, data) in blocks {
+        for component in components {
+          if component.kind != ComponentKind::Texture {
+            continue;
+          }
+          if self.skip_shared {
+            if let ComponentSource::Shared {
+              section_id: shared_section_id,
+              ..
+            } = soup.toc().component_source(section_id, component)
+            {
+              debug!(
+                section_id,
+                shared_section_id,
+                path = %component.path(),
+                "skipping shared texture"
+              );
+              continue;
+            }
+          }
+
+          let resolved = soup.resolve(section_id, component);
+          if let ResolvedComponent::Missing { .. } = resolved {
+            debug!(path = %component.path(), "skipping texture without an SOI record");
+            continue;
+          }
+
+          let path = component.path();
+          let streaming_data =
+            component_slice(component, &data).map_err(|err| err.with_component(path.clone()))?;
+
+          let mut native = Cursor::new(Vec::new());
+          resolved
+            .write(&mut native, streaming_data)
+            .map_err(|err| err.with_component(path.clone()))?;
+          let native = native.into_inner();
+
+          let hash = self
+            .hash_contents(&resolved, streaming_data, &native)
+            .map_err(|err| err.with_component(path.clone()))?;
+          let usage = TextureUsage {
+            level: level.to_string(),
+            section_id,
+            section: section.header.name(),
+            path: path.clone(),
+            size: native.len(),
+          };
+
+          if let Some(&group) = self.index.get(&hash) {
+            self.groups[group].usages.push(usage);
+            continue;
+          }
+
+          self.index.insert(hash, self.groups.len());
+          self.groups.push(TextureGroup {
+            hash,
+            usages: vec![usage],
+          });
+
+          if let Some((options, sink)) = output.as_mut() {
+            let extension = resolved.extension().unwrap_or("bin");
+            let ConvertedComponent { files, fallback } =
+              convert_component(&resolved, &path, extension, streaming_data, options)
+                .map_err(|err| err.with_component(path.clone()))?;
+            for (file_path, data) in files {
+              sink(ExtractedFile {
+                section_id,
+                path: file_path,
+                kind: component.kind,
+                data,
+                fallback: fallback.clone(),
+              })?;
+            }
+          }
+        }
+      }
+    }
+
+    Ok(())
+  }
+
+  // hash of the bytes compared, tagged with what they are
+  fn hash_contents<StreamingTH, StaticTH, MH>(
+    &self,
+    resolved: &ResolvedComponent<StreamingTH, StaticTH, MH>,
+    streaming_data: &[u8],
+    native: &[u8],
+  ) -> Result<[u8; 32]>
+  where
+    StreamingTH: TextureConvert,
+    StaticTH: StaticTextureConvert,
+    MH: ModelConvert,
+  {
+    if self.hash == TextureHash::Decoded {
+      match resolved.decode_texture(streaming_data) {
+        Ok(Some(mips)) => {
+          let mut hasher = Sha256::new();
+          hasher.update(&[TextureHash::Decoded as u8]);
+          for mip in mips {
+            hasher.update(&mip.width.to_le_bytes());
+            hasher.update(&mip.height.to_le_bytes());
+            hasher.update(&mip.data);
+          }
+          return Ok(hasher.finalize());
+        }
+        Ok(None) | Err(Error::UnsupportedTextureFormat { .. }) => {}
+        Err(err) => return Err(err),
+      }
+    }
+
+    let mut hasher = Sha256::new();
+    hasher.update(&[TextureHash::Native as u8]);
+    hasher.update(native);
+    Ok(hasher.finalize())
+  }
+}
@@ -0,0 +1,195 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Error {
+  Io(std::io::Error),
+  BinRw(binrw::Error),
+  InvalidString(std::str::Utf8Error),
+  /// The collision model type has no standalone (non-streaming) counterpart we can write.
+  UnsupportedCollisionType {
+    collision_type: String,
+  },
+  /// A mesh in a streaming model header isn't flagged as streaming.
+  NotStreaming {
+    lod: usize,
+    mesh: usize,
+  },
+  /// A field that the header flags say is present wasn't read.
+  MissingField {
+    field: &'static str,
+  },
+  /// A range reaches past the end of the data it indexes.
+  OutOfBounds {
+    context: &'static str,
+    offset: usize,
+    size: usize,
+    available: usize,
+  },
+  /// The amount of data differs from what the headers describe.
+  SizeMismatch {
+    context: &'static str,
+    expected: usize,
+    actual: usize,
+  },
+  /// A header field holds a value no valid file has, like a negative size.
+  InvalidField {
+    field: &'static str,
+    value: i64,
+  },
+  SectionNotFound {
+    section_id: u32,
+  },
+  /// The SOI has no record describing a component, so it can't be converted.
+  MissingRecord {
+    reason: &'static str,
+  },
+  FileNotFound {
+    path: String,
+  },
+  /// The texture is stored in a pixel format that can't be decoded.
+  UnsupportedTextureFormat {
+    format: String,
+  },
+  /// The texture's size can't be used by the game.
+  InvalidTextureDimensions {
+    width: u32,
+    height: u32,
+    mip_count: u32,
+  },
+  /// A `Progress` observer asked to stop.
+  Cancelled,
+  /// Attaches the path of the component that was being processed to another error.
+  Component {
+    path: String,
+    source: Box<Error>,
+  },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+  pub fn with_component(self, path: impl Into<String>) -> Self {
+    Error::Component {
+      path: path.into(),
+      source: Box::new(self),
+    }
+  }
+
+  /// Wraps the error so it can be returned from binrw read and write implementations.
+  pub(crate) fn into_binrw(self, pos: u64) -> binrw::Error {
+    binrw::Error::Custom {
+      pos,
+      err: Box::new(self),
+    }
+  }
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Error::Io(err) => write!(f, "io error: {}", err),
+      Error::BinRw(err) => write!(f, "{}", err),
+      Error::InvalidString(err) => write!(f, "invalid string: {}", err),
+      Error::UnsupportedCollisionType { collision_type } => {
+        write!(f, "unsupported collision type {}", collision_type)
+      }
+      Error::NotStreaming { lod, mesh } => {
+        write!(f, "mesh {} of lod {} is not a streaming mesh", mesh, lod)
+      }
+      Error::MissingField { field } => write!(f, "missing field {}", field),
+      Error::OutOfBounds {
+        context,
+        offset,
+        size,
+        available,
+      } => write!(
+        f,
+        "{}: {} bytes at offset {} are out of bounds ({} bytes available)",
+        context, size, offset, available
+      ),
+      Error::SizeMismatch {
+        context,
+        expected,
+        actual,
+      } => write!(
+        f,
+        "{}: expected {} bytes, got {}",
+        context, expected, actual
+      ),
+      Error::InvalidField { field, value } => write!(f, "invalid {} {}", field, value),
+      Error::SectionNotFound { section_id } => write!(f, "section {} does not exist", section_id),
+      Error::MissingRecord { reason } => write!(f, "{}", reason),
+      Error::FileNotFound { path } => write!(f, "{} does not exist", path),
+      Error::UnsupportedTextureFormat { format } => {
+        write!(f, "unsupported texture format {}", format)
+      }
+      Error::InvalidTextureDimensions {
+        width,
+        height,
+        mip_count,
+      } => write!(
+        f,
+        "invalid texture dimensions {}x{} with {} mips",
+        width, height, mip_count
+      ),
+      Error::Cancelled => write!(f, "cancelled"),
+      Error::Component { path, source } => write!(f, "{}: {}", path, source),
+    }
+  }
+}
+
+impl std::error::Error for Error {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      Error::Io(err) => Some(err),
+      Error::BinRw(err) => Some(err),
+      Error::InvalidString(err) => Some(err),
+      Error::Component { source, .. } => Some(source.as_ref()),
+      _ => None,
+    }
+  }
+}
+
+impl From<std::io::Error> for Error {
+  fn from(err: std::io::Error) -> Self {
+    Error::Io(err)
+  }
+}
+
+impl From<binrw::Error> for Error {
+  fn from(err: binrw::Error) -> Self {
+    Error::BinRw(err)
+  }
+}
+
+impl From<std::str::Utf8Error> for Error {
+  fn from(err: std::str::Utf8Error) -> Self {
+    Error::InvalidString(err)
+  }
+}
+
+/// Bounds checked `&data[offset..offset + size]`.
+pub(crate) fn slice_checked<'a>(
+  data: &'a [u8],
+  offset: usize,
+  size: usize,
+  context: &'static str,
+) -> Result<&'a [u8]> {
+  offset
+    .checked_add(size)
+    .and_then(|end| data.get(offset..end))
+    .ok_or(Error::OutOfBounds {
+      context,
+      offset,
+      size,
+      available: data.len(),
+    })
+}
+
+/// A size or offset read from a header, which can't be negative.
+pub(crate) fn non_negative(value: i32, field: &'static str) -> Result<usize> {
+  usize::try_from(value).map_err(|_| Error::InvalidField {
+    field,
+    value: value as i64,
+  })
+}
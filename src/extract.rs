@@ -0,0 +1,266 @@
+use std::io::Cursor;
+
+use crate::error::{Error, Result};
+use crate::str::component_slice;
+use crate::{
+  ComponentKind, ComponentSource, ModelConvert, Progress, ResolvedComponent, Section, SoiSoup,
+  StaticTextureConvert, Str, TextureConvert,
+};
+
+/// A component converted to its standalone file.
+#[derive(Debug)]
+pub struct ExtractedFile {
+  pub section_id: u32,
+  /// The component path with the extension of the converted file appended.
+  pub path: String,
+  pub kind: ComponentKind,
+  pub data: Vec<u8>,
+  /// Why a texture was written in its native format when `TextureOutput` asked for PNGs, e.g. a
+  /// Ci8 texture whose palette format isn't known.
+  pub fallback: Option<String>,
+}
+
+/// How textures are written by `extract_all_with_options`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TextureOutput {
+  /// The platform's own texture file, e.g. `.dxt` or `.gct`.
+  #[default]
+  Native,
+  /// A PNG of the biggest mip level.
+  Png,
+  /// A PNG per mip level, named `<path>.mip<level>.png` with level 0 the biggest.
+  PngMips,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ExtractOptions {
+  texture_output: TextureOutput,
+  skip_shared: bool,
+}
+
+impl ExtractOptions {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Textures the decoders don't support are still written in their native form.
+  pub fn texture_output(mut self, texture_output: TextureOutput) -> Self {
+    self.texture_output = texture_output;
+    self
+  }
+
+  /// Extracts shared components only from the section storing them, instead of from every section
+  /// referencing them. Which sections are shared comes from an undocumented TOC field whose reading
+  /// isn't verified against game files, so this is off by default.
+  pub fn skip_shared(mut self, skip_shared: bool) -> Self {
+    self.skip_shared = skip_shared;
+    self
+  }
+}
+
+impl<
+    StreamingTH: TextureConvert + Sync,
+    StaticTH: StaticTextureConvert + Sync,
+    MH: ModelConvert + Sync,
+  > SoiSoup<StreamingTH, StaticTH, MH>
+{
+  /// Converts every component of every section and hands the files to `sink`. Components without
+  /// an SOI record are skipped, see `ExtractOptions::skip_shared` for shared ones. With the `rayon` feature sections are processed in parallel, so `sink` can be
+  /// called from several threads at once.
+  pub fn extract_all<F>(&self, str: &Str, sink: F) -> Result<()>
+  where
+    F: Fn(ExtractedFile) -> Result<()> + Sync,
+  {
+    self.extract_all_with_progress(str, &(), sink)
+  }
+
+  /// Like `extract_all`, reporting every section and extracted component to `progress`. Cancelling
+  /// stops before the next component and returns `Error::Cancelled`.
+  pub fn extract_all_with_progress<F>(
+    &self,
+    str: &Str,
+    progress: &(dyn Progress + Sync),
+    sink: F,
+  ) -> Result<()>
+  where
+    F: Fn(ExtractedFile) -> Result<()> + Sync,
+  {
+    self.extract_all_with_options(str, &ExtractOptions::default(), progress, sink)
+  }
+
+  /// Like `extract_all_with_progress`, with control over the output formats.
+  pub fn extract_all_with_options<F>(
+    &self,
+    str: &Str,
+    options: &ExtractOptions,
+    progress: &(dyn Progress + Sync),
+    sink: F,
+  ) -> Result<()>
+  where
+    F: Fn(ExtractedFile) -> Result<()> + Sync,
+  {
+    let extract_section = |(section_id, section): (usize, &Section)| {
+      self.extract_section(str, section_id as u32, section, options, progress, &sink)
+    };
+
+    #[cfg(feature = "rayon")]
+    {
+      use rayon::prelude::*;
+      self
+        .find_sections()
+        .par_iter()
+        .enumerate()
+        .try_for_each(extract_section)
+    }
+    #[cfg(not(feature = "rayon"))]
+    {
+      self
+        .find_sections()
+        .iter()
+        .enumerate()
+        .try_for_each(extract_section)
+    }
+  }
+
+  fn extract_section<F>(
+    &self,
+    str: &Str,
+    section_id: u32,
+    section: &Section,
+    options: &ExtractOptions,
+    progress: &dyn Progress,
+    sink: &F,
+  ) -> Result<()>
+  where
+    F: Fn(ExtractedFile) -> Result<()>,
+  {
+    let header = &section.header;
+    let bytes = header.uncached_data_size.max(0) as usize + header.cached_data_size.max(0) as usize;
+    if progress
+      .section(section_id, &header.name(), bytes)
+      .is_break()
+    {
+      return Err(Error::Cancelled);
+    }
+    let (uncached_data, cached_data) = str.read_section_blocks_at(section)?;
+
+    let blocks = [
+      (&section.uncached_components, uncached_data),
+      (&section.cached_components, cached_data),
+    ];
+
+    for (components, data) in blocks {
+      for component in components {
+        if options.skip_shared {
+          if let ComponentSource::Shared {
+            section_id: shared_section_id,
+            ..
+          } = self.toc().component_source(section_id, component)
+          {
+            debug!(
+              section_id,
+              shared_section_id,
+              path = %component.path(),
+              "skipping shared component"
+            );
+            continue;
+          }
+        }
+
+        let resolved = self.resolve(section_id, component);
+        let Some(extension) = resolved.extension() else {
+          debug!(path = %component.path(), "skipping component without an SOI record");
+          continue;
+        };
+
+        let path = component.path();
+        let ConvertedComponent { files, fallback } = component_slice(component, &data)
+          .and_then(|streaming_data| {
+            convert_component(&resolved, &path, extension, streaming_data, options)
+          })
+          .map_err(|err| err.with_component(path.clone()))?;
+
+        let mut bytes = 0;
+        for (file_path, data) in files {
+          bytes += data.len();
+          sink(ExtractedFile {
+            section_id,
+            path: file_path,
+            kind: component.kind,
+            data,
+            fallback: fallback.clone(),
+          })?;
+        }
+
+        if progress.component(section_id, &path, bytes).is_break() {
+          return Err(Error::Cancelled);
+        }
+      }
+    }
+
+    Ok(())
+  }
+}
+
+// the files a component is extracted to
+pub(crate) struct ConvertedComponent {
+  // (path, contents)
+  pub(crate) files: Vec<(String, Vec<u8>)>,
+  // why a texture that should have been decoded was written in its native format
+  pub(crate) fallback: Option<String>,
+}
+
+pub(crate) fn convert_component<
+  StreamingTH: TextureConvert,
+  StaticTH: StaticTextureConvert,
+  MH: ModelConvert,
+>(
+  resolved: &ResolvedComponent<StreamingTH, StaticTH, MH>,
+  path: &str,
+  extension: &str,
+  streaming_data: &[u8],
+  options: &ExtractOptions,
+) -> Result<ConvertedComponent> {
+  let mut fallback = None;
+  if options.texture_output != TextureOutput::Native {
+    match resolved.decode_texture(streaming_data) {
+      Ok(Some(mut mips)) => {
+        if options.texture_output == TextureOutput::Png {
+          mips.truncate(1);
+        }
+
+        let files = mips
+          .iter()
+          .enumerate()
+          .map(|(level, mip)| {
+            let mut data = Vec::new();
+            mip.write_png(&mut data)?;
+
+            let path = match options.texture_output {
+              TextureOutput::PngMips => format!("{}.mip{}.png", path, level),
+              _ => format!("{}.png", path),
+            };
+            Ok((path, data))
+          })
+          .collect::<Result<_>>()?;
+        return Ok(ConvertedComponent {
+          files,
+          fallback: None,
+        });
+      }
+      Ok(None) => {}
+      Err(Error::UnsupportedTextureFormat { format }) => {
+        warn!(path, format = %format, "writing undecodable texture in its native format");
+        fallback = Some(format!("unsupported texture format {}", format));
+      }
+      Err(err) => return Err(err),
+    }
+  }
+
+  let mut output = Cursor::new(Vec::new());
+  resolved.write(&mut output, streaming_data)?;
+  Ok(ConvertedComponent {
+    files: vec![(format!("{}.{}", path, extension), output.into_inner())],
+    fallback,
+  })
+}
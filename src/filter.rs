@@ -0,0 +1,43 @@
+use crate::utils::glob_match;
+use crate::{ComponentHeader, ComponentKind};
+
+/// Selects components by path glob and kind. A component has to match one of the globs (if any are
+/// given) and one of the kinds (if any are given).
+#[derive(Debug, Default, Clone)]
+pub struct ComponentFilter {
+  patterns: Vec<String>,
+  kinds: Vec<ComponentKind>,
+}
+
+impl ComponentFilter {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Adds a glob such as `textures/cars/**`, see `glob_match`.
+  pub fn glob(mut self, pattern: impl Into<String>) -> Self {
+    self.patterns.push(pattern.into());
+    self
+  }
+
+  pub fn kind(mut self, kind: ComponentKind) -> Self {
+    self.kinds.push(kind);
+    self
+  }
+
+  pub fn matches(&self, component: &ComponentHeader) -> bool {
+    if !self.kinds.is_empty() && !self.kinds.contains(&component.kind) {
+      return false;
+    }
+
+    if self.patterns.is_empty() {
+      return true;
+    }
+
+    let path = component.path();
+    self
+      .patterns
+      .iter()
+      .any(|pattern| glob_match(pattern, &path))
+  }
+}
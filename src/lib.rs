@@ -1,24 +1,49 @@
 pub use crate::collision::*;
+pub use crate::convert::*;
+pub use crate::dedup::*;
+pub use crate::error::{Error, Result};
+pub use crate::extract::*;
+pub use crate::filter::*;
 pub use crate::models::*;
 pub use crate::motion::*;
+pub use crate::progress::*;
 pub use crate::res::*;
+// `res` and `soi` both define `Header`, the root one is the SOI header and the RES one is `ResHeader`
+pub use crate::res::Header as ResHeader;
+pub use crate::section_graph::*;
+pub use crate::soi::Header;
 pub use crate::soi::*;
 pub use crate::soi_soup::*;
 pub use crate::str::*;
 pub use crate::textures::*;
 pub use crate::toc::*;
 pub use crate::utils::*;
+pub use crate::validate::*;
+pub use crate::vfs::*;
+
+#[macro_use]
+mod trace;
 
 mod collision;
+mod convert;
+mod dedup;
+mod error;
+mod extract;
+mod filter;
 mod models;
 mod motion;
+mod progress;
 mod res;
+mod section_graph;
+mod sha256;
 mod soi;
 mod soi_soup;
 mod str;
 mod textures;
 mod toc;
 mod utils;
+mod validate;
+mod vfs;
 
 #[cfg(test)]
 mod test;
@@ -1,6 +1,7 @@
 use binrw::{BinRead, BinResult, BinWrite, Endian};
 use std::io::{Seek, Write};
 
+use crate::error::{slice_checked, Error};
 use crate::{utils::*, Bone, MeshName};
 
 // https://github.com/leeao/carsraceorama/blob/master/carsraceorama/CarsTypes.h#L43
@@ -11,29 +12,39 @@ const D3DVSDT_FLOAT4: u8 = 0x42; // 4D float
 const D3DVSDT_D3DCOLOR: u8 = 0x40; // 4D packed unsigned bytes mapped to 0. to 1. range
                                    // Input is in D3DCOLOR format (ARGB) expanded to (R, G, B, A)
 const D3DVSDT_SHORT2: u8 = 0x25; // 2D signed short expanded to (value, value, 0., 1.)
+#[allow(dead_code)]
 const D3DVSDT_SHORT4: u8 = 0x45; // 4D signed short
 
 // The following are Xbox extensions
+#[allow(dead_code)]
 const D3DVSDT_NORMSHORT1: u8 = 0x11; // 1D signed, normalized short expanded to (value, 0, 0., 1.)
 
 // (signed, normalized short maps from -1.0 to 1.0)
+#[allow(dead_code)]
 const D3DVSDT_NORMSHORT2: u8 = 0x21; // 2D signed, normalized short expanded to (value, value, 0., 1.)
+#[allow(dead_code)]
 const D3DVSDT_NORMSHORT3: u8 = 0x31; // 3D signed, normalized short expanded to (value, value, value, 1.)
+#[allow(dead_code)]
 const D3DVSDT_NORMSHORT4: u8 = 0x41; // 4D signed, normalized short expanded to (value, value, value, value)
+#[allow(dead_code)]
 const D3DVSDT_NORMPACKED3: u8 = 0x16; // 3 signed, normalized components packed in 32-bits.  (11,11,10).
 
 // Each component ranges from -1.0 to 1.0.
 // Expanded to (value, value, value, 1.)
+#[allow(dead_code)]
 const D3DVSDT_SHORT1: u8 = 0x15; // 1D signed short expanded to (value, 0., 0., 1.)
 
 // Signed shorts map to the range [-32768, 32767]
 const D3DVSDT_SHORT3: u8 = 0x35; // 3D signed short expanded to (value, value, value, 1.)
+#[allow(dead_code)]
 const D3DVSDT_PBYTE1: u8 = 0x14; // 1D packed byte expanded to (value, 0., 0., 1.)
 
 // Packed bytes map to the range [0, 1]
 const D3DVSDT_PBYTE2: u8 = 0x24; // 2D packed byte expanded to (value, value, 0., 1.)
 const D3DVSDT_PBYTE3: u8 = 0x34; // 3D packed byte expanded to (value, value, value, 1.)
+#[allow(dead_code)]
 const D3DVSDT_PBYTE4: u8 = 0x44; // 4D packed byte expanded to (value, value, value, value)
+#[allow(dead_code)]
 const D3DVSDT_FLOAT2H: u8 = 0x72; // 2D homogeneous float expanded to (value, value,0., value.)
 
 // Useful for projective texture coordinates.
@@ -109,7 +120,7 @@ pub struct StreamingDXGMesh {
   pub unk2: u8,
   pub unk3: u8,
 
-  #[br(count = num_texture_coordinate_sets)]
+  #[br(count = usize::from(num_texture_coordinate_sets))]
   pub texture_coordinate_sets: Vec<f32>,
 
   pub num_vertices: u16,
@@ -119,6 +130,66 @@ pub struct StreamingDXGMesh {
   pub delta_block: Option<DXGDeltaBlock>,
 }
 
+impl StreamingDXGMesh {
+  /// Size of this mesh's face indices and vertex attributes in the streaming data.
+  pub fn streaming_data_size(&self) -> usize {
+    let vertex_formats = self.vertex_formats.unwrap_or(DEFAULT_VERTEX_FORMATS);
+
+    let ty = self.vertex_type;
+    let mut offset: usize = self.num_face_indices as usize * 2;
+
+    if (ty & 0x01) == 0x01 {
+      let attribute_size = if vertex_formats[0] == D3DVSDT_FLOAT3 {
+        self.num_vertices as usize * 12
+      } else if vertex_formats[0] == D3DVSDT_SHORT3 {
+        self.num_vertices as usize * 6
+      } else if vertex_formats[0] == D3DVSDT_PBYTE3 {
+        self.num_vertices as usize * 3
+      } else {
+        0
+      };
+      offset += attribute_size;
+    }
+    if (ty & 0x02) == 0x02 {
+      let attribute_size = if vertex_formats[1] == D3DVSDT_FLOAT3 {
+        self.num_vertices as usize * 12
+      } else if vertex_formats[1] == D3DVSDT_SHORT3 {
+        self.num_vertices as usize * 6
+      } else if vertex_formats[1] == D3DVSDT_PBYTE3 {
+        self.num_vertices as usize * 3
+      } else {
+        0
+      };
+      offset += attribute_size;
+    }
+    if (ty & 0x08) == 0x08 {
+      offset += self.num_vertices as usize * 4;
+    }
+    if (ty & 0x04) == 0x04 {
+      let attribute_size = if vertex_formats[3] == D3DVSDT_FLOAT2 {
+        self.num_vertices as usize * 8
+      } else if vertex_formats[3] == D3DVSDT_SHORT2 {
+        self.num_vertices as usize * 4
+      } else if vertex_formats[3] == D3DVSDT_PBYTE2 {
+        self.num_vertices as usize * 2
+      } else {
+        0
+      };
+      offset += attribute_size;
+    }
+    if (ty & 0x10) == 0x10 {
+      offset += self.num_vertices as usize * 8;
+    }
+    if (ty & 0x40) == 0x40 {
+      offset += self.num_vertices as usize * 4;
+    }
+    if (ty & 0x1000) == 0x1000 {
+      offset += self.num_vertices as usize * 32;
+    }
+    offset
+  }
+}
+
 #[derive(BinRead, BinWrite, Debug)]
 #[brw(little)]
 pub struct DXGLod {
@@ -149,20 +220,32 @@ pub struct DXGHeader {
   has_weight: u8,
   unused: u8,
 
-  #[br(count = num_lod)]
+  #[br(count = usize::from(num_lod))]
   pub lods: Vec<DXGLod>,
 }
 
+impl DXGHeader {
+  /// Expected length of the streaming component data that goes with this header.
+  pub fn streaming_data_size(&self) -> usize {
+    self
+      .lods
+      .iter()
+      .flat_map(|lod| &lod.meshes)
+      .map(|mesh| mesh.streaming_data_size())
+      .sum()
+  }
+}
+
 // BinrwNamedArgs
 #[derive(Clone, Debug)]
-pub struct DXGHeaderArgs {
-  pub streaming_data: Vec<u8>,
+pub struct DXGHeaderArgs<'a> {
+  pub streaming_data: &'a [u8],
 }
 
 // This BinWrite implementation actually restructures the streaming component data plus the header data in the SOI to form a proper DXG file.
 // As such, the streaming data must be passed to write_options.
 impl BinWrite for DXGHeader {
-  type Args<'a> = &'a DXGHeaderArgs;
+  type Args<'a> = &'a DXGHeaderArgs<'a>;
 
   fn write_options<W: Write + Seek>(
     &self,
@@ -184,10 +267,11 @@ impl BinWrite for DXGHeader {
 
     let mut offset_in_data: usize = 0;
 
-    for lod in &self.lods {
+    debug_span!("write dxg", lods = self.lods.len());
+    for (lod_index, lod) in self.lods.iter().enumerate() {
       f32::write_options(&lod.auto_lod_value, writer, endian, ())?;
       u32::write_options(&lod.num_meshes, writer, endian, ())?;
-      for mesh in &lod.meshes {
+      for (mesh_index, mesh) in lod.meshes.iter().enumerate() {
         u32::write_options(&mesh.surface_index, writer, endian, ())?;
         u32::write_options(&mesh.vertex_type, writer, endian, ())?;
         if let Some(compression_stuff) = mesh.compression_stuff {
@@ -197,7 +281,7 @@ impl BinWrite for DXGHeader {
         let vertex_formats = if let Some(vertex_formats) = mesh.vertex_formats {
           vertex_formats
         } else {
-          DEFAULT_VERTEX_FORMATS.clone()
+          DEFAULT_VERTEX_FORMATS
         };
         if mesh.vertex_formats.is_some() {
           vertex_formats.write_options(writer, endian, ())?;
@@ -214,62 +298,26 @@ impl BinWrite for DXGHeader {
         u16::write_options(&mesh.num_vertices, writer, endian, ())?;
         u16::write_options(&mesh.num_face_indices, writer, endian, ())?;
 
-        assert!(mesh.streaming == 1);
-
-        let ty = mesh.vertex_type;
-        let mut offset: usize = mesh.num_face_indices as usize * 2;
-
-        if (ty & 0x01) == 0x01 {
-          let attribute_size = if vertex_formats[0] == D3DVSDT_FLOAT3 {
-            mesh.num_vertices as usize * 12
-          } else if vertex_formats[0] == D3DVSDT_SHORT3 {
-            mesh.num_vertices as usize * 6
-          } else if vertex_formats[0] == D3DVSDT_PBYTE3 {
-            mesh.num_vertices as usize * 3
-          } else {
-            0
-          };
-          offset += attribute_size;
-        }
-        if (ty & 0x02) == 0x02 {
-          let attribute_size = if vertex_formats[1] == D3DVSDT_FLOAT3 {
-            mesh.num_vertices as usize * 12
-          } else if vertex_formats[1] == D3DVSDT_SHORT3 {
-            mesh.num_vertices as usize * 6
-          } else if vertex_formats[1] == D3DVSDT_PBYTE3 {
-            mesh.num_vertices as usize * 3
-          } else {
-            0
-          };
-          offset += attribute_size;
-        }
-        if (ty & 0x08) == 0x08 {
-          offset += mesh.num_vertices as usize * 4;
-        }
-        if (ty & 0x04) == 0x04 {
-          let attribute_size = if vertex_formats[3] == D3DVSDT_FLOAT2 {
-            mesh.num_vertices as usize * 8
-          } else if vertex_formats[3] == D3DVSDT_SHORT2 {
-            mesh.num_vertices as usize * 4
-          } else if vertex_formats[3] == D3DVSDT_PBYTE2 {
-            mesh.num_vertices as usize * 2
-          } else {
-            0
-          };
-          offset += attribute_size;
-        }
-        if (ty & 0x10) == 0x10 {
-          offset += mesh.num_vertices as usize * 8;
-        }
-        if (ty & 0x40) == 0x40 {
-          offset += mesh.num_vertices as usize * 4;
-        }
-        if (ty & 0x1000) == 0x1000 {
-          offset += mesh.num_vertices as usize * 32;
+        if mesh.streaming != 1 {
+          return Err(
+            Error::NotStreaming {
+              lod: lod_index,
+              mesh: mesh_index,
+            }
+            .into_binrw(writer.stream_position()?),
+          );
         }
 
-        let data = (&args.streaming_data[offset_in_data..offset_in_data + offset]).to_vec();
-        Vec::<u8>::write_options(&data, writer, endian, ())?;
+        let offset = mesh.streaming_data_size();
+
+        let data = slice_checked(
+          args.streaming_data,
+          offset_in_data,
+          offset,
+          "DXG streaming data",
+        )
+        .map_err(|err| err.into_binrw(offset_in_data as u64))?;
+        data.write_options(writer, endian, ())?;
 
         offset_in_data += offset;
 
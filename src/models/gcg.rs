@@ -2,6 +2,7 @@ use std::io::{Seek, Write};
 
 use binrw::{BinRead, BinResult, BinWrite, Endian};
 
+use crate::error::{slice_checked, Error};
 use crate::{Bone, MeshName};
 
 #[derive(BinRead, BinWrite, PartialEq, Debug, Clone, Copy)]
@@ -66,6 +67,50 @@ pub struct StreamingGCGMesh {
   pub face_chunk_size: u32,
 }
 
+impl StreamingGCGMesh {
+  /// Size of the vertex block (positions, normals or vertex colors, uvs) in the streaming data, without the padding that follows it.
+  pub fn vertex_block_size(&self) -> usize {
+    let mut vertex_block_size = 0;
+
+    vertex_block_size += match self.vertex_data_type {
+      GXCompType::GxU8 => self.vertex_count as usize * 3,
+      GXCompType::GxS8 => self.vertex_count as usize * 3,
+      GXCompType::GxU16 => self.vertex_count as usize * 6,
+      GXCompType::GxS16 => self.vertex_count as usize * 6,
+      GXCompType::GxF32 => self.vertex_count as usize * 12,
+      GXCompType::GxU32 => self.vertex_count as usize * 12,
+    };
+
+    if (self.vertex_type & 0x1) == 0x1 && (self.vertex_type & 0x8) == 0x8 {
+      vertex_block_size += match self.normal_data_type {
+        Some(GXCompType::GxU8) => self.normal_count as usize * 3,
+        Some(GXCompType::GxS8) => self.normal_count as usize * 3,
+        Some(GXCompType::GxU16) => self.normal_count as usize * 6,
+        Some(GXCompType::GxS16) => self.normal_count as usize * 6,
+        Some(GXCompType::GxF32) => self.normal_count as usize * 12,
+        Some(GXCompType::GxU32) => self.normal_count as usize * 12,
+        None => 0,
+      };
+    }
+
+    if (self.vertex_type & 0x1) == 0x0 {
+      vertex_block_size += 4 * self.color_count as usize;
+    }
+
+    if (self.vertex_type & 0x2) == 0x2 {
+      vertex_block_size += match self.uv_data_type {
+        GXCompType::GxU8 => self.uv_count as usize * 2,
+        GXCompType::GxS8 => self.uv_count as usize * 2,
+        GXCompType::GxU16 => self.uv_count as usize * 4,
+        GXCompType::GxS16 => self.uv_count as usize * 4,
+        GXCompType::GxF32 => self.uv_count as usize * 8,
+        GXCompType::GxU32 => self.uv_count as usize * 8,
+      };
+    }
+    vertex_block_size
+  }
+}
+
 #[derive(BinRead, BinWrite, Debug)]
 #[brw(big)]
 pub struct GCGLod {
@@ -106,23 +151,44 @@ pub struct GCGHeader {
   #[br(if(has_weight != 0))]
   weight_count: u16,
 
-  #[br(if(has_weight != 0), count = weight_count)]
+  #[br(if(has_weight != 0), count = usize::from(weight_count))]
   pub weights: Option<Vec<GCGWeight>>,
 
-  #[br(count = num_lod)]
+  #[br(count = usize::from(num_lod))]
   pub lods: Vec<GCGLod>,
 }
 
+impl GCGHeader {
+  /// Expected length of the streaming component data that goes with this header.
+  pub fn streaming_data_size(&self) -> usize {
+    // every vertex block is padded so the face chunk after it starts 32 byte aligned
+    self
+      .lods
+      .iter()
+      .flat_map(|lod| &lod.meshes)
+      .fold(0, |offset, mesh| {
+        crate::round_up(offset + mesh.vertex_block_size(), 32) + mesh.face_chunk_size as usize
+      })
+  }
+}
+
+fn required<T, W: Seek>(value: Option<T>, field: &'static str, writer: &mut W) -> BinResult<T> {
+  match value {
+    Some(value) => Ok(value),
+    None => Err(Error::MissingField { field }.into_binrw(writer.stream_position()?)),
+  }
+}
+
 // BinrwNamedArgs
 #[derive(Clone, Debug)]
-pub struct GCGHeaderArgs {
-  pub streaming_data: Vec<u8>,
+pub struct GCGHeaderArgs<'a> {
+  pub streaming_data: &'a [u8],
 }
 
 // This BinWrite implementation actually restructures the streaming component data plus the header data in the SOI to form a proper XNG file.
 // As such, the streaming data must be passed to write_options.
 impl BinWrite for GCGHeader {
-  type Args<'a> = &'a GCGHeaderArgs;
+  type Args<'a> = &'a GCGHeaderArgs<'a>;
 
   fn write_options<W: Write + Seek>(
     &self,
@@ -143,11 +209,13 @@ impl BinWrite for GCGHeader {
     u8::write_options(&self.unused, writer, endian, ())?;
     if self.has_weight != 0 {
       u16::write_options(&self.weight_count, writer, endian, ())?;
-      Vec::<GCGWeight>::write_options(&self.weights.clone().unwrap(), writer, endian, ())?;
+      let weights = required(self.weights.as_ref(), "weights", writer)?;
+      Vec::<GCGWeight>::write_options(weights, writer, endian, ())?;
     }
 
     let mut offset_in_data: usize = 0;
 
+    debug_span!("write gcg", lods = self.lods.len());
     for lod in &self.lods {
       f32::write_options(&lod.auto_lod_value, writer, endian, ())?;
       u32::write_options(&lod.num_meshes, writer, endian, ())?;
@@ -159,13 +227,17 @@ impl BinWrite for GCGHeader {
         u8::write_options(&mesh.xyz_frac_bits, writer, endian, ())?;
 
         if (mesh.vertex_type & 0x1) == 0x1 && (mesh.vertex_type & 0x8) == 0x8 {
-          GXAttrType::write_options(&mesh.normal_attr_type.unwrap(), writer, endian, ())?;
-          GXCompType::write_options(&mesh.normal_data_type.unwrap(), writer, endian, ())?;
+          let normal_attr_type = required(mesh.normal_attr_type, "normal_attr_type", writer)?;
+          GXAttrType::write_options(&normal_attr_type, writer, endian, ())?;
+          let normal_data_type = required(mesh.normal_data_type, "normal_data_type", writer)?;
+          GXCompType::write_options(&normal_data_type, writer, endian, ())?;
         }
 
         if (mesh.vertex_type & 0x1) == 0x0 {
-          GXAttrType::write_options(&mesh.color_attr_type.unwrap(), writer, endian, ())?;
-          GXCompType::write_options(&mesh.color_data_type.unwrap(), writer, endian, ())?;
+          let color_attr_type = required(mesh.color_attr_type, "color_attr_type", writer)?;
+          GXAttrType::write_options(&color_attr_type, writer, endian, ())?;
+          let color_data_type = required(mesh.color_data_type, "color_data_type", writer)?;
+          GXCompType::write_options(&color_data_type, writer, endian, ())?;
         }
 
         GXAttrType::write_options(&mesh.uv_attr_type, writer, endian, ())?;
@@ -189,57 +261,40 @@ impl BinWrite for GCGHeader {
         u8::write_options(&mesh.uv_stride, writer, endian, ())?;
 
         u32::write_options(&mesh.face_chunk_size, writer, endian, ())?;
-        let mut vertex_block_size = 0;
-
-        vertex_block_size += match mesh.vertex_data_type {
-          GXCompType::GxU8 => mesh.vertex_count as usize * 3,
-          GXCompType::GxS8 => mesh.vertex_count as usize * 3,
-          GXCompType::GxU16 => mesh.vertex_count as usize * 6,
-          GXCompType::GxS16 => mesh.vertex_count as usize * 6,
-          GXCompType::GxF32 => mesh.vertex_count as usize * 12,
-          GXCompType::GxU32 => mesh.vertex_count as usize * 12,
-        };
-
-        if (mesh.vertex_type & 0x1) == 0x1 && (mesh.vertex_type & 0x8) == 0x8 {
-          vertex_block_size += match mesh.normal_data_type {
-            Some(GXCompType::GxU8) => mesh.normal_count as usize * 3,
-            Some(GXCompType::GxS8) => mesh.normal_count as usize * 3,
-            Some(GXCompType::GxU16) => mesh.normal_count as usize * 6,
-            Some(GXCompType::GxS16) => mesh.normal_count as usize * 6,
-            Some(GXCompType::GxF32) => mesh.normal_count as usize * 12,
-            Some(GXCompType::GxU32) => mesh.normal_count as usize * 12,
-            None => 0,
-          };
-        }
-
-        if (mesh.vertex_type & 0x1) == 0x0 {
-          vertex_block_size += 4 * mesh.color_count as usize;
-        }
-
-        if (mesh.vertex_type & 0x2) == 0x2 {
-          vertex_block_size += match mesh.uv_data_type {
-            GXCompType::GxU8 => mesh.uv_count as usize * 2,
-            GXCompType::GxS8 => mesh.uv_count as usize * 2,
-            GXCompType::GxU16 => mesh.uv_count as usize * 4,
-            GXCompType::GxS16 => mesh.uv_count as usize * 4,
-            GXCompType::GxF32 => mesh.uv_count as usize * 8,
-            GXCompType::GxU32 => mesh.uv_count as usize * 8,
-          };
-        }
+        let vertex_block_size = mesh.vertex_block_size();
 
         // write the vertex block (positions, normals or vertex colors, uvs)
-        let vertex_block =
-          (&args.streaming_data[offset_in_data..offset_in_data + vertex_block_size]).to_vec();
-        Vec::<u8>::write_options(&vertex_block, writer, endian, ())?;
+        let vertex_block = slice_checked(
+          args.streaming_data,
+          offset_in_data,
+          vertex_block_size,
+          "GCG vertex block",
+        )
+        .map_err(|err| err.into_binrw(offset_in_data as u64))?;
+        vertex_block.write_options(writer, endian, ())?;
         offset_in_data = crate::round_up(offset_in_data + vertex_block_size, 32);
 
-        let face_chunk: Vec<u8> = (&args.streaming_data
-          [offset_in_data..offset_in_data + mesh.face_chunk_size as usize])
-          .to_vec();
-        Vec::<u8>::write_options(&face_chunk, writer, endian, ())?;
+        let face_chunk = slice_checked(
+          args.streaming_data,
+          offset_in_data,
+          mesh.face_chunk_size as usize,
+          "GCG face chunk",
+        )
+        .map_err(|err| err.into_binrw(offset_in_data as u64))?;
+        face_chunk.write_options(writer, endian, ())?;
         offset_in_data += mesh.face_chunk_size as usize;
       }
-      assert_eq!(offset_in_data, args.streaming_data.len());
+    }
+
+    if offset_in_data != args.streaming_data.len() {
+      return Err(
+        Error::SizeMismatch {
+          context: "GCG streaming data",
+          expected: offset_in_data,
+          actual: args.streaming_data.len(),
+        }
+        .into_binrw(offset_in_data as u64),
+      );
     }
     Ok(())
   }
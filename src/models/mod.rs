@@ -4,7 +4,7 @@ mod xng;
 use binrw::BinRead;
 use binrw::BinWrite;
 
-use crate::clean_string;
+use crate::clean_string_lossy;
 
 pub use self::dxg::*;
 pub use self::gcg::*;
@@ -26,7 +26,7 @@ impl<MH: BinRead<Args<'static> = ()>> std::fmt::Display for StreamingRenderableM
       write!(
         f,
         "SLT={}\nPosition={}\nLookVector={}\nUpVector={}\nZone={}\n",
-        clean_string(&self.model_info.name),
+        clean_string_lossy(&self.model_info.name),
         self.model_info.position,
         self.model_info.look_vector,
         self.model_info.up_vector,
@@ -36,15 +36,16 @@ impl<MH: BinRead<Args<'static> = ()>> std::fmt::Display for StreamingRenderableM
       write!(
         f,
         "SLT={}\nPosition={}\nLookVector={}\nUpVector={}\n",
-        clean_string(&self.model_info.name),
+        clean_string_lossy(&self.model_info.name),
         self.model_info.position,
         self.model_info.look_vector,
         self.model_info.up_vector,
       )?;
     }
-    Ok(for param in self.parameters.iter() {
-      write!(f, "{}\n", param)?;
-    })
+    for param in self.parameters.iter() {
+      writeln!(f, "{}", param)?;
+    }
+    Ok(())
   }
 }
 
@@ -1,6 +1,7 @@
 use binrw::{BinRead, BinResult, BinWrite, Endian};
 use std::io::{Seek, Write};
 
+use crate::error::{slice_checked, Error};
 use crate::{utils::*, Bone, MeshName};
 
 #[derive(Default, BinRead, BinWrite, Debug)]
@@ -51,7 +52,7 @@ pub struct StreamingXNGMesh {
   pub unk2: u8,
   pub unk3: u8,
 
-  #[br(count = num_texture_coordinate_sets)]
+  #[br(count = usize::from(num_texture_coordinate_sets))]
   pub texture_coordinate_sets: Vec<f32>,
 
   pub num_vertices: u16,
@@ -61,6 +62,48 @@ pub struct StreamingXNGMesh {
   pub delta_block: Option<XNGDeltaBlock>,
 }
 
+impl StreamingXNGMesh {
+  /// Size of this mesh's face indices and vertex attributes in the streaming data.
+  pub fn streaming_data_size(&self) -> usize {
+    let ty = self.vertex_type;
+    let mut offset: usize = self.num_face_indices as usize * 2;
+    if self.num_face_indices % 2 == 1 {
+      offset += 2;
+    }
+    if (ty & 0x01) == 0x01 {
+      offset += self.num_vertices as usize * 12;
+    }
+    if (ty & 0x02) == 0x02 {
+      offset += self.num_vertices as usize * 12;
+    }
+    if (ty & 0x08) == 0x08 {
+      offset += self.num_vertices as usize * 4;
+    }
+    if (ty & 0x04) == 0x04 {
+      offset += self.num_vertices as usize * 8;
+    }
+    if (ty & 0x40) == 0x40 {
+      offset += self.num_vertices as usize * 4;
+    }
+    if (ty & 0x1000) == 0x1000 {
+      offset += self.num_vertices as usize * 32;
+    }
+    if (ty & 0x10) == 0x10 {
+      offset += self.num_vertices as usize * 8;
+    }
+    if (ty & 0x4000) == 0x4000 {
+      offset += self.num_vertices as usize * 8;
+    }
+    if (ty & 0x8000) == 0x8000 {
+      offset += self.num_vertices as usize * 8;
+    }
+    if (ty & 0x20) == 0x20 {
+      offset += self.num_vertices as usize * 12;
+    }
+    offset
+  }
+}
+
 #[derive(BinRead, BinWrite, Debug)]
 #[brw(big)]
 pub struct XNGLod {
@@ -91,20 +134,32 @@ pub struct XNGHeader {
   has_weight: u8,
   unused: u8,
 
-  #[br(count = num_lod)]
+  #[br(count = usize::from(num_lod))]
   pub lods: Vec<XNGLod>,
 }
 
+impl XNGHeader {
+  /// Expected length of the streaming component data that goes with this header.
+  pub fn streaming_data_size(&self) -> usize {
+    self
+      .lods
+      .iter()
+      .flat_map(|lod| &lod.meshes)
+      .map(|mesh| mesh.streaming_data_size())
+      .sum()
+  }
+}
+
 // BinrwNamedArgs
 #[derive(Clone, Debug)]
-pub struct XNGHeaderArgs {
-  pub streaming_data: Vec<u8>,
+pub struct XNGHeaderArgs<'a> {
+  pub streaming_data: &'a [u8],
 }
 
 // This BinWrite implementation actually restructures the streaming component data plus the header data in the SOI to form a proper XNG file.
 // As such, the streaming data must be passed to write_options.
 impl BinWrite for XNGHeader {
-  type Args<'a> = &'a XNGHeaderArgs;
+  type Args<'a> = &'a XNGHeaderArgs<'a>;
 
   fn write_options<W: Write + Seek>(
     &self,
@@ -126,10 +181,11 @@ impl BinWrite for XNGHeader {
 
     let mut offset_in_data: usize = 0;
 
-    for lod in &self.lods {
+    debug_span!("write xng", lods = self.lods.len());
+    for (lod_index, lod) in self.lods.iter().enumerate() {
       f32::write_options(&lod.auto_lod_value, writer, endian, ())?;
       u32::write_options(&lod.num_meshes, writer, endian, ())?;
-      for mesh in &lod.meshes {
+      for (mesh_index, mesh) in lod.meshes.iter().enumerate() {
         u32::write_options(&mesh.surface_index, writer, endian, ())?;
         u32::write_options(&mesh.vertex_type, writer, endian, ())?;
         if let Some(compression_stuff) = mesh.compression_stuff {
@@ -146,46 +202,26 @@ impl BinWrite for XNGHeader {
         u16::write_options(&mesh.num_vertices, writer, endian, ())?;
         u16::write_options(&mesh.num_face_indices, writer, endian, ())?;
 
-        assert!(mesh.streaming == 1);
-
-        let ty = mesh.vertex_type;
-        let mut offset: usize = mesh.num_face_indices as usize * 2;
-        if mesh.num_face_indices % 2 == 1 {
-          offset += 2;
-        }
-        if (ty & 0x01) == 0x01 {
-          offset += mesh.num_vertices as usize * 12;
-        }
-        if (ty & 0x02) == 0x02 {
-          offset += mesh.num_vertices as usize * 12;
-        }
-        if (ty & 0x08) == 0x08 {
-          offset += mesh.num_vertices as usize * 4;
-        }
-        if (ty & 0x04) == 0x04 {
-          offset += mesh.num_vertices as usize * 8;
-        }
-        if (ty & 0x40) == 0x40 {
-          offset += mesh.num_vertices as usize * 4;
-        }
-        if (ty & 0x1000) == 0x1000 {
-          offset += mesh.num_vertices as usize * 32;
-        }
-        if (ty & 0x10) == 0x10 {
-          offset += mesh.num_vertices as usize * 8;
-        }
-        if (ty & 0x4000) == 0x4000 {
-          offset += mesh.num_vertices as usize * 8;
-        }
-        if (ty & 0x8000) == 0x8000 {
-          offset += mesh.num_vertices as usize * 8;
-        }
-        if (ty & 0x20) == 0x20 {
-          offset += mesh.num_vertices as usize * 12;
+        if mesh.streaming != 1 {
+          return Err(
+            Error::NotStreaming {
+              lod: lod_index,
+              mesh: mesh_index,
+            }
+            .into_binrw(writer.stream_position()?),
+          );
         }
 
-        let data = (&args.streaming_data[offset_in_data..offset_in_data + offset]).to_vec();
-        Vec::<u8>::write_options(&data, writer, endian, ())?;
+        let offset = mesh.streaming_data_size();
+
+        let data = slice_checked(
+          args.streaming_data,
+          offset_in_data,
+          offset,
+          "XNG streaming data",
+        )
+        .map_err(|err| err.into_binrw(offset_in_data as u64))?;
+        data.write_options(writer, endian, ())?;
 
         offset_in_data += offset;
 
@@ -0,0 +1,22 @@
+use std::ops::ControlFlow;
+
+/// Observer for long running operations such as reading or extracting a whole level. Both callbacks
+/// default to doing nothing. Returning `ControlFlow::Break` stops the operation before the next
+/// component, which then fails with `Error::Cancelled`.
+///
+/// Parallel operations call the observer from several threads at once.
+pub trait Progress {
+  /// Called before a section's data is read, with the size of both of its blocks. Breaking skips
+  /// the read.
+  fn section(&self, _section_id: u32, _name: &str, _bytes: usize) -> ControlFlow<()> {
+    ControlFlow::Continue(())
+  }
+
+  /// Called after each component, with the size of its data.
+  fn component(&self, _section_id: u32, _path: &str, _bytes: usize) -> ControlFlow<()> {
+    ControlFlow::Continue(())
+  }
+}
+
+/// No progress reporting.
+impl Progress for () {}
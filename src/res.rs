@@ -1,9 +1,15 @@
-use std::{collections::HashMap, fs::File, io::Read, path::Path};
+use std::{
+  collections::HashMap,
+  fs::File,
+  io::{Read, Seek},
+  path::Path,
+};
 
 use binrw::{BinRead, BinReaderExt, BinResult};
 use flate2::read::ZlibDecoder;
 
 use crate::clean_path;
+use crate::error::Error;
 
 #[derive(BinRead)]
 pub struct OffsetEntry {
@@ -57,30 +63,48 @@ impl Res {
 
   pub fn read_file(file: &mut File) -> BinResult<Self> {
     let res_file_header: Header = file.read_le()?;
+    debug_span!(
+      "res",
+      version = res_file_header.version,
+      files = res_file_header.offset_table.len()
+    );
+    let data_start = file.stream_position()?;
     let mut files = HashMap::new();
     let mut compressed_data = Vec::new();
-    file.read_to_end(&mut compressed_data).unwrap();
+    file.read_to_end(&mut compressed_data)?;
 
     let mut current_offset = 0;
-    while compressed_data[current_offset] == 0 {
+    while compressed_data.get(current_offset) == Some(&0) {
       current_offset += 1;
     }
 
     for offset_entry in res_file_header.offset_table.iter() {
       let path = clean_path(&offset_entry.name);
 
+      let remaining = compressed_data.get(current_offset..).ok_or_else(|| {
+        Error::OutOfBounds {
+          context: "RES compressed data",
+          offset: current_offset,
+          size: 0,
+          available: compressed_data.len(),
+        }
+        .with_component(path.clone())
+        .into_binrw(data_start + current_offset as u64)
+      })?;
+
       let mut decompressed_data = Vec::new();
-      let mut decoder = ZlibDecoder::new(&compressed_data[current_offset..]);
+      let mut decoder = ZlibDecoder::new(remaining);
       decoder.read_to_end(&mut decompressed_data)?;
 
       // advance the offset in the compressed data buffer by the size that the decoder read while decompressing this specific file.
       current_offset += decoder.total_in() as usize;
 
       // a dummy 0xFF byte is sometimes placed so the game knows when a file has been read, so we check for it and advance the offset accordingly.
-      if compressed_data[current_offset] == 0xFF {
+      if compressed_data.get(current_offset) == Some(&0xFF) {
         current_offset += 1;
       }
 
+      trace!(path = %path, size = decompressed_data.len(), "decompressed file");
       files.insert(path, decompressed_data);
     }
     Ok(Self {
@@ -89,6 +113,10 @@ impl Res {
     })
   }
 
+  pub fn header(&self) -> &Header {
+    &self.res_file_header
+  }
+
   pub fn get_file(&self, path: String) -> Option<&[u8]> {
     if self.files.contains_key(&path) {
       Some(&self.files[&path])
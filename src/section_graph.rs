@@ -0,0 +1,165 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use crate::Toc;
+
+/// A link table entry that doesn't point at a section in the TOC.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BrokenLink {
+  pub section_id: u32,
+  pub slot: usize,
+  pub target: i32,
+}
+
+/// Adjacency graph built from each section's `link_table`.
+#[derive(Debug)]
+pub struct SectionGraph {
+  names: Vec<String>,
+  outgoing: Vec<Vec<u32>>,
+  incoming: Vec<Vec<u32>>,
+  broken_links: Vec<BrokenLink>,
+}
+
+impl SectionGraph {
+  pub fn new(toc: &Toc) -> Self {
+    let count = toc.sections.len();
+    let mut names = Vec::with_capacity(count);
+    let mut outgoing = vec![Vec::new(); count];
+    let mut incoming = vec![Vec::new(); count];
+    let mut broken_links = Vec::new();
+
+    for (id, section) in toc.sections.iter().enumerate() {
+      names.push(section.header.name());
+
+      for (slot, target) in section.header.links() {
+        if target as usize >= count {
+          broken_links.push(BrokenLink {
+            section_id: id as u32,
+            slot,
+            target,
+          });
+          continue;
+        }
+
+        if !outgoing[id].contains(&(target as u32)) {
+          outgoing[id].push(target as u32);
+          incoming[target as usize].push(id as u32);
+        }
+      }
+    }
+
+    Self {
+      names,
+      outgoing,
+      incoming,
+      broken_links,
+    }
+  }
+
+  pub fn section_count(&self) -> usize {
+    self.outgoing.len()
+  }
+
+  /// Sections this section links to.
+  pub fn neighbors(&self, section_id: u32) -> &[u32] {
+    self
+      .outgoing
+      .get(section_id as usize)
+      .map(|links| links.as_slice())
+      .unwrap_or(&[])
+  }
+
+  /// Sections that link to this section.
+  pub fn incoming(&self, section_id: u32) -> &[u32] {
+    self
+      .incoming
+      .get(section_id as usize)
+      .map(|links| links.as_slice())
+      .unwrap_or(&[])
+  }
+
+  pub fn broken_links(&self) -> &[BrokenLink] {
+    &self.broken_links
+  }
+
+  /// Sections that neither link to nor are linked from any other section.
+  pub fn orphans(&self) -> Vec<u32> {
+    (0..self.section_count() as u32)
+      .filter(|&id| {
+        let links_elsewhere = |links: &[u32]| links.iter().any(|&other| other != id);
+        !links_elsewhere(self.neighbors(id)) && !links_elsewhere(self.incoming(id))
+      })
+      .collect()
+  }
+
+  /// Groups sections that are reachable from each other, ignoring link direction.
+  pub fn connected_components(&self) -> Vec<Vec<u32>> {
+    let mut visited = vec![false; self.section_count()];
+    let mut components = Vec::new();
+
+    for start in 0..self.section_count() {
+      if visited[start] {
+        continue;
+      }
+
+      let mut component = Vec::new();
+      let mut stack = vec![start as u32];
+      visited[start] = true;
+
+      while let Some(id) = stack.pop() {
+        component.push(id);
+
+        for &next in self.neighbors(id).iter().chain(self.incoming(id)) {
+          if !visited[next as usize] {
+            visited[next as usize] = true;
+            stack.push(next);
+          }
+        }
+      }
+
+      component.sort_unstable();
+      components.push(component);
+    }
+
+    components
+  }
+
+  /// Renders the graph in Graphviz DOT format. Broken links point at red placeholder nodes.
+  pub fn to_dot(&self) -> String {
+    let mut dot = String::from("digraph sections {\n");
+
+    for (id, name) in self.names.iter().enumerate() {
+      let label = name.replace('\\', "\\\\").replace('"', "\\\"");
+      writeln!(dot, "  s{} [label=\"{}: {}\"];", id, id, label).unwrap();
+    }
+
+    for (id, links) in self.outgoing.iter().enumerate() {
+      for target in links {
+        writeln!(dot, "  s{} -> s{};", id, target).unwrap();
+      }
+    }
+
+    // several links can point at the same missing section, declare its node once
+    let missing: BTreeSet<i32> = self.broken_links.iter().map(|link| link.target).collect();
+    for target in missing {
+      writeln!(
+        dot,
+        "  missing{} [label=\"missing {}\", color=red];",
+        target, target
+      )
+      .unwrap();
+    }
+
+    for link in &self.broken_links {
+      writeln!(
+        dot,
+        "  s{} -> missing{} [color=red];",
+        link.section_id, link.target
+      )
+      .unwrap();
+    }
+
+    dot.push_str("}\n");
+    dot
+  }
+}
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::fs::File;
 use std::path::Path;
 
@@ -22,29 +23,29 @@ enum StreamingMode {
 pub struct Header {
   pub version: i32,
 
-  flags: i32,
-  sections: i32,
-  collision_models: i32,
-  renderable_models: i32,
-  motion_packs: i32,
-  streaming_textures: i32,
-  static_textures: i32,
-  uncached_pages: i32,
-  cached_pages: i32,
-
-  motion_packs_offset: i32,
-  renderable_models_offset: i32,
-  collision_models_offset: i32,
-  textures_offset: i32,
-  collision_grids_offset: i32,
+  pub flags: i32,
+  pub sections: i32,
+  pub collision_models: i32,
+  pub renderable_models: i32,
+  pub motion_packs: i32,
+  pub streaming_textures: i32,
+  pub static_textures: i32,
+  pub uncached_pages: i32,
+  pub cached_pages: i32,
+
+  pub motion_packs_offset: i32,
+  pub renderable_models_offset: i32,
+  pub collision_models_offset: i32,
+  pub textures_offset: i32,
+  pub collision_grids_offset: i32,
 
   streaming_mode: StreamingMode,
-  reserved: [u8; 16],
+  pub reserved: [u8; 16],
 }
 
-#[derive(BinRead, Debug)]
+#[derive(BinRead, Debug, Clone)]
 pub struct ModelInfo {
-  flags: i32,
+  pub flags: i32,
   pub position: Vector4,
   pub look_vector: Vector4,
   pub up_vector: Vector4,
@@ -69,8 +70,8 @@ impl std::fmt::Display for StreamingParameter {
     write!(
       f,
       "{}={}",
-      clean_string(&self.name),
-      clean_string(&self.value)
+      clean_string_lossy(&self.name),
+      clean_string_lossy(&self.value)
     )
   }
 }
@@ -119,6 +120,37 @@ pub struct Soi<
 
   #[br(count = header.collision_models)]
   collision_models: Vec<StreamingCollisionModel>,
+
+  #[br(calc = SoiIndex {
+    streaming_textures: index_by_ids(streaming_textures.iter().map(|texture| &texture.model_info)),
+    static_textures: index_by_ids(static_textures.iter().map(|texture| &texture.model_info)),
+    motion_packs: index_by_ids(motion_packs.iter().map(|motion_pack| &motion_pack.model_info)),
+    renderable_models: index_by_ids(renderable_models.iter().map(|model| &model.model_info)),
+    collision_models: index_by_ids(collision_models.iter().map(|collision_model| &collision_model.model_info)),
+  })]
+  index: SoiIndex,
+}
+
+/// Positions of the records in each list, keyed by (section id, component id).
+#[derive(Debug, Default)]
+struct SoiIndex {
+  streaming_textures: HashMap<(u32, u32), usize>,
+  static_textures: HashMap<(u32, u32), usize>,
+  motion_packs: HashMap<(u32, u32), usize>,
+  renderable_models: HashMap<(u32, u32), usize>,
+  collision_models: HashMap<(u32, u32), usize>,
+}
+
+fn index_by_ids<'a>(infos: impl Iterator<Item = &'a ModelInfo>) -> HashMap<(u32, u32), usize> {
+  let mut index = HashMap::new();
+
+  for (position, model_info) in infos.enumerate() {
+    let ids = (model_info.section_id as u32, model_info.component_id as u32);
+    // keep the first record like a front to back scan would
+    index.entry(ids).or_insert(position);
+  }
+
+  index
 }
 
 impl<
@@ -133,27 +165,50 @@ impl<
   }
 
   pub fn read_file(file: &mut File, endian: Endian) -> BinResult<Self> {
-    file.read_type(endian)
+    let soi: Self = file.read_type(endian)?;
+
+    debug!(
+      version = soi.header.version,
+      streaming_textures = soi.streaming_textures.len(),
+      static_textures = soi.static_textures.len(),
+      motion_packs = soi.motion_packs.len(),
+      renderable_models = soi.renderable_models.len(),
+      collision_models = soi.collision_models.len(),
+      "read soi"
+    );
+    if soi.header.streaming_mode == StreamingMode::Unknown {
+      warn!("unknown streaming mode");
+    }
+
+    Ok(soi)
+  }
+
+  pub fn get_uncached_page_sizes(&self) -> &[i32] {
+    &self.uncached_page_sizes
+  }
+
+  pub fn get_cached_page_sizes(&self) -> &[i32] {
+    &self.cached_page_sizes
   }
 
   pub fn get_streaming_textures(&self) -> &[StreamingTexture<StreamingTH>] {
-    return &self.streaming_textures;
+    &self.streaming_textures
   }
 
   pub fn get_static_textures(&self) -> &[StaticTexture<StaticTH>] {
-    return &self.static_textures;
+    &self.static_textures
   }
 
   pub fn get_motion_packs(&self) -> &[StreamingMotionPack] {
-    return &self.motion_packs;
+    &self.motion_packs
   }
 
   pub fn get_renderable_models(&self) -> &[StreamingRenderableModel<MH>] {
-    return &self.renderable_models;
+    &self.renderable_models
   }
 
   pub fn get_collision_models(&self) -> &[StreamingCollisionModel] {
-    return &self.collision_models;
+    &self.collision_models
   }
 
   pub fn find_static_texture(
@@ -161,16 +216,11 @@ impl<
     section_id: u32,
     component_id: u32,
   ) -> Option<&StaticTexture<StaticTH>> {
-    for texture in &self.static_textures {
-      let model_info = &texture.model_info;
-      if model_info.section_id == section_id as i32
-        && model_info.component_id == component_id as i32
-      {
-        return Some(&texture);
-      }
-    }
-
-    None
+    self
+      .index
+      .static_textures
+      .get(&(section_id, component_id))
+      .map(|&position| &self.static_textures[position])
   }
 
   pub fn find_streaming_texture(
@@ -178,16 +228,11 @@ impl<
     section_id: u32,
     component_id: u32,
   ) -> Option<&StreamingTexture<StreamingTH>> {
-    for texture in &self.streaming_textures {
-      let model_info = &texture.model_info;
-      if model_info.section_id == section_id as i32
-        && model_info.component_id == component_id as i32
-      {
-        return Some(&texture);
-      }
-    }
-
-    None
+    self
+      .index
+      .streaming_textures
+      .get(&(section_id, component_id))
+      .map(|&position| &self.streaming_textures[position])
   }
 
   pub fn find_motion_pack(
@@ -195,16 +240,11 @@ impl<
     section_id: u32,
     component_id: u32,
   ) -> Option<&StreamingMotionPack> {
-    for motion_pack in &self.motion_packs {
-      let model_info = &motion_pack.model_info;
-      if model_info.section_id == section_id as i32
-        && model_info.component_id == component_id as i32
-      {
-        return Some(&motion_pack);
-      }
-    }
-
-    None
+    self
+      .index
+      .motion_packs
+      .get(&(section_id, component_id))
+      .map(|&position| &self.motion_packs[position])
   }
 
   pub fn find_collision_model(
@@ -212,16 +252,11 @@ impl<
     section_id: u32,
     component_id: u32,
   ) -> Option<&StreamingCollisionModel> {
-    for collision_model in &self.collision_models {
-      let model_info = &collision_model.model_info;
-      if model_info.section_id == section_id as i32
-        && model_info.component_id == component_id as i32
-      {
-        return Some(&collision_model);
-      }
-    }
-
-    None
+    self
+      .index
+      .collision_models
+      .get(&(section_id, component_id))
+      .map(|&position| &self.collision_models[position])
   }
 
   pub fn find_model(
@@ -229,15 +264,10 @@ impl<
     section_id: u32,
     component_id: u32,
   ) -> Option<&StreamingRenderableModel<MH>> {
-    for model in &self.renderable_models {
-      let model_info = &model.model_info;
-      if model_info.section_id == section_id as i32
-        && model_info.component_id == component_id as i32
-      {
-        return Some(&model);
-      }
-    }
-
-    None
+    self
+      .index
+      .renderable_models
+      .get(&(section_id, component_id))
+      .map(|&position| &self.renderable_models[position])
   }
 }
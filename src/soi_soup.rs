@@ -3,10 +3,30 @@ use std::path::Path;
 use binrw::{BinRead, BinResult, Endian};
 
 use crate::{
-  ComponentHeader, Section, Soi, StaticTexture, StreamingCollisionModel, StreamingMotionPack,
-  StreamingRenderableModel, StreamingTexture, Toc, X360StaticTextureHeader, XNGHeader,
+  Bounding, ComponentFilter, ComponentHeader, ComponentKind, ComponentSource, Section,
+  SectionGraph, Soi, StaticTexture, StreamingCollisionModel, StreamingMotionPack,
+  StreamingRenderableModel, StreamingTexture, Toc, Vector3,
 };
 
+/// The SOI record that describes a component, picked according to the component's kind.
+#[derive(Debug)]
+pub enum ResolvedComponent<
+  'a,
+  StreamingTH: BinRead<Args<'static> = ()> + 'static,
+  StaticTH: BinRead<Args<'static> = ()> + 'static,
+  MH: BinRead<Args<'static> = ()> + 'static,
+> {
+  Model(&'a StreamingRenderableModel<MH>),
+  StreamingTexture(&'a StreamingTexture<StreamingTH>),
+  StaticTexture(&'a StaticTexture<StaticTH>),
+  Collision(&'a StreamingCollisionModel),
+  MotionPack(&'a StreamingMotionPack),
+  // collision grids and user data aren't described by the SOI
+  CollisionGrid,
+  UserData,
+  Missing { reason: &'static str },
+}
+
 pub struct SoiSoup<
   StreamingTH: BinRead<Args<'static> = ()> + 'static,
   StaticTH: BinRead<Args<'static> = ()> + 'static,
@@ -23,12 +43,17 @@ impl<
   > SoiSoup<StreamingTH, StaticTH, MH>
 {
   pub fn cook(toc_path: &Path, soi_path: &Path, endian: Endian) -> BinResult<Self> {
+    debug_span!("cook", toc = %toc_path.display(), soi = %soi_path.display());
     let soi = Soi::read(soi_path, endian)?;
     let toc = Toc::read(toc_path, endian, soi.header.version == 0x101)?;
 
     Ok(Self { toc, soi })
   }
 
+  pub fn new(toc: Toc, soi: Soi<StreamingTH, StaticTH, MH>) -> Self {
+    Self { toc, soi }
+  }
+
   pub fn find_sections(&self) -> &Vec<Section> {
     &self.toc.sections
   }
@@ -39,11 +64,7 @@ impl<
     for (id, section) in self.toc.sections.iter().enumerate() {
       let id = id as u32;
 
-      for component in &section.uncached_components {
-        components.push((id, section, component));
-      }
-
-      for component in &section.cached_components {
+      for component in section.components() {
         components.push((id, section, component));
       }
     }
@@ -51,6 +72,67 @@ impl<
     components
   }
 
+  /// Like `find_components`, also telling whether each component is stored in its own section or
+  /// referenced from a shared one.
+  pub fn find_components_with_source(
+    &self,
+  ) -> Vec<(u32, &Section, &ComponentHeader, ComponentSource)> {
+    self
+      .find_components()
+      .into_iter()
+      .map(|(id, section, component)| {
+        let source = self.toc.component_source(id, component);
+        (id, section, component, source)
+      })
+      .collect()
+  }
+
+  /// Like `find_components`, but leaves out references to shared components so each one is listed once.
+  pub fn find_owned_components(&self) -> Vec<(u32, &Section, &ComponentHeader)> {
+    self
+      .find_components_with_source()
+      .into_iter()
+      .filter(|(_, _, _, source)| *source == ComponentSource::Owned)
+      .map(|(id, section, component, _)| (id, section, component))
+      .collect()
+  }
+
+  /// Every occurrence of a component path, compared case-insensitively.
+  pub fn find_by_path(
+    &self,
+    path: &str,
+  ) -> Vec<(u32, &Section, &ComponentHeader, ComponentSource)> {
+    self
+      .toc
+      .find_by_path(path)
+      .into_iter()
+      .map(|(id, section, component)| {
+        let source = self.toc.component_source(id, component);
+        (id, section, component, source)
+      })
+      .collect()
+  }
+
+  /// Like `find_components_with_source`, but only returns the components the filter matches.
+  pub fn filter_components(
+    &self,
+    filter: &ComponentFilter,
+  ) -> Vec<(u32, &Section, &ComponentHeader, ComponentSource)> {
+    self
+      .find_components_with_source()
+      .into_iter()
+      .filter(|(_, _, component, _)| filter.matches(component))
+      .collect()
+  }
+
+  pub fn toc(&self) -> &Toc {
+    &self.toc
+  }
+
+  pub fn soi(&self) -> &Soi<StreamingTH, StaticTH, MH> {
+    &self.soi
+  }
+
   pub fn streaming_textures(&self) -> &[StreamingTexture<StreamingTH>] {
     self.soi.get_streaming_textures()
   }
@@ -81,18 +163,42 @@ impl<
     sum as u32
   }
 
+  pub fn link_graph(&self) -> SectionGraph {
+    self.toc.link_graph()
+  }
+
+  pub fn sections_containing(&self, point: &Vector3) -> Vec<u32> {
+    self.toc.sections_containing(point)
+  }
+
+  pub fn sections_intersecting(&self, bounds: &Bounding) -> Vec<u32> {
+    self.toc.sections_intersecting(bounds)
+  }
+
+  pub fn sections_intersecting_ray(
+    &self,
+    origin: &Vector3,
+    direction: &Vector3,
+  ) -> Vec<(u32, f32)> {
+    self.toc.sections_intersecting_ray(origin, direction)
+  }
+
+  pub fn nearest_section(&self, point: &Vector3) -> Option<u32> {
+    self.toc.nearest_section(point)
+  }
+
   pub fn find_static_texture(
     &self,
     section_id: u32,
     component_id: u32,
     instance_id: u32,
   ) -> Option<&StaticTexture<StaticTH>> {
-    if let Some(header) = self.soi.find_static_texture(section_id, component_id) {
-      return Some(header);
-    }
-
-    let (section_id, component_id) = self.toc.find_ids(instance_id)?;
-    self.soi.find_static_texture(section_id, component_id)
+    self.find_with_fallback(
+      section_id,
+      component_id,
+      instance_id,
+      |section_id, component_id| self.soi.find_static_texture(section_id, component_id),
+    )
   }
 
   pub fn find_streaming_texture(
@@ -101,12 +207,12 @@ impl<
     component_id: u32,
     instance_id: u32,
   ) -> Option<&StreamingTexture<StreamingTH>> {
-    if let Some(header) = self.soi.find_streaming_texture(section_id, component_id) {
-      return Some(header);
-    }
-
-    let (section_id, component_id) = self.toc.find_ids(instance_id)?;
-    self.soi.find_streaming_texture(section_id, component_id)
+    self.find_with_fallback(
+      section_id,
+      component_id,
+      instance_id,
+      |section_id, component_id| self.soi.find_streaming_texture(section_id, component_id),
+    )
   }
 
   pub fn find_motion_pack(
@@ -115,12 +221,12 @@ impl<
     component_id: u32,
     instance_id: u32,
   ) -> Option<&StreamingMotionPack> {
-    if let Some(header) = self.soi.find_motion_pack(section_id, component_id) {
-      return Some(header);
-    }
-
-    let (section_id, component_id) = self.toc.find_ids(instance_id)?;
-    self.soi.find_motion_pack(section_id, component_id)
+    self.find_with_fallback(
+      section_id,
+      component_id,
+      instance_id,
+      |section_id, component_id| self.soi.find_motion_pack(section_id, component_id),
+    )
   }
 
   pub fn find_collision_model(
@@ -129,12 +235,12 @@ impl<
     component_id: u32,
     instance_id: u32,
   ) -> Option<&StreamingCollisionModel> {
-    if let Some(header) = self.soi.find_collision_model(section_id, component_id) {
-      return Some(header);
-    }
-
-    let (section_id, component_id) = self.toc.find_ids(instance_id)?;
-    self.soi.find_collision_model(section_id, component_id)
+    self.find_with_fallback(
+      section_id,
+      component_id,
+      instance_id,
+      |section_id, component_id| self.soi.find_collision_model(section_id, component_id),
+    )
   }
 
   pub fn find_model(
@@ -143,11 +249,101 @@ impl<
     component_id: u32,
     instance_id: u32,
   ) -> Option<&StreamingRenderableModel<MH>> {
-    if let Some(header) = self.soi.find_model(section_id, component_id) {
-      return Some(header);
+    self.find_with_fallback(
+      section_id,
+      component_id,
+      instance_id,
+      |section_id, component_id| self.soi.find_model(section_id, component_id),
+    )
+  }
+
+  pub fn resolve(
+    &self,
+    section_id: u32,
+    component: &ComponentHeader,
+  ) -> ResolvedComponent<'_, StreamingTH, StaticTH, MH> {
+    let (component_id, instance_id) = (component.id as u32, component.instance_id as u32);
+
+    match component.kind {
+      ComponentKind::RenderableModel => self
+        .find_model(section_id, component_id, instance_id)
+        .map(ResolvedComponent::Model)
+        .unwrap_or(ResolvedComponent::Missing {
+          reason: "no renderable model record",
+        }),
+      ComponentKind::Texture => {
+        // textures are either streaming or static, the kind doesn't say which
+        if let Some(texture) = self.find_streaming_texture(section_id, component_id, instance_id) {
+          ResolvedComponent::StreamingTexture(texture)
+        } else if let Some(texture) =
+          self.find_static_texture(section_id, component_id, instance_id)
+        {
+          ResolvedComponent::StaticTexture(texture)
+        } else {
+          ResolvedComponent::Missing {
+            reason: "no streaming or static texture record",
+          }
+        }
+      }
+      ComponentKind::CollisionModel => self
+        .find_collision_model(section_id, component_id, instance_id)
+        .map(ResolvedComponent::Collision)
+        .unwrap_or(ResolvedComponent::Missing {
+          reason: "no collision model record",
+        }),
+      ComponentKind::MotionPack => self
+        .find_motion_pack(section_id, component_id, instance_id)
+        .map(ResolvedComponent::MotionPack)
+        .unwrap_or(ResolvedComponent::Missing {
+          reason: "no motion pack record",
+        }),
+      ComponentKind::CollisionGrid => ResolvedComponent::CollisionGrid,
+      ComponentKind::UserData => ResolvedComponent::UserData,
     }
+  }
+
+  /// Resolves every component of every section, in the same order as `find_components`.
+  pub fn resolve_all(
+    &self,
+  ) -> impl Iterator<
+    Item = (
+      u32,
+      &ComponentHeader,
+      ResolvedComponent<'_, StreamingTH, StaticTH, MH>,
+    ),
+  > {
+    self
+      .toc
+      .sections
+      .iter()
+      .enumerate()
+      .flat_map(move |(id, section)| {
+        section
+          .components()
+          .map(move |component| (id as u32, component, self.resolve(id as u32, component)))
+      })
+  }
 
-    let (section_id, component_id) = self.toc.find_ids(instance_id)?;
-    self.soi.find_model(section_id, component_id)
+  /// Looks a record up by its own ids first, then under every other section the instance appears in.
+  fn find_with_fallback<'a, T>(
+    &'a self,
+    section_id: u32,
+    component_id: u32,
+    instance_id: u32,
+    find: impl Fn(u32, u32) -> Option<&'a T>,
+  ) -> Option<&'a T> {
+    find(section_id, component_id).or_else(|| {
+      debug!(
+        section_id,
+        component_id,
+        instance_id,
+        "no record under the component's own ids, trying its other sections"
+      );
+      self
+        .toc
+        .find_all_ids(instance_id)
+        .iter()
+        .find_map(|&(section_id, component_id)| find(section_id, component_id))
+    })
   }
 }
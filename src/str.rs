@@ -1,12 +1,70 @@
 use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
+use std::io::Read;
+use std::ops::{Deref, Range};
 use std::path::Path;
+use std::sync::Arc;
 
-use binrw::io;
 use flate2::read::ZlibDecoder;
 
+use crate::error::{non_negative, slice_checked, Error, Result};
 use crate::toc::ComponentKind;
-use crate::{ComponentHeader, Section};
+use crate::{ComponentHeader, ComponentSource, Progress, Section, Toc};
+
+/// A range of a section block. Every component of a section shares the same block, so cloning and
+/// slicing never copy the data.
+#[derive(Clone)]
+pub struct SharedBytes {
+  buffer: Arc<dyn AsRef<[u8]> + Send + Sync>,
+  range: Range<usize>,
+}
+
+impl SharedBytes {
+  pub fn new(buffer: impl AsRef<[u8]> + Send + Sync + 'static) -> Self {
+    let range = 0..buffer.as_ref().len();
+    Self {
+      buffer: Arc::new(buffer),
+      range,
+    }
+  }
+
+  /// Narrows the view further, `range` is relative to this view.
+  pub fn slice(&self, range: Range<usize>) -> Option<Self> {
+    if range.start > range.end || range.end > self.range.len() {
+      return None;
+    }
+
+    Some(Self {
+      buffer: self.buffer.clone(),
+      range: self.range.start + range.start..self.range.start + range.end,
+    })
+  }
+}
+
+impl Deref for SharedBytes {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    &(*self.buffer).as_ref()[self.range.clone()]
+  }
+}
+
+impl AsRef<[u8]> for SharedBytes {
+  fn as_ref(&self) -> &[u8] {
+    self
+  }
+}
+
+impl From<Vec<u8>> for SharedBytes {
+  fn from(data: Vec<u8>) -> Self {
+    Self::new(data)
+  }
+}
+
+impl std::fmt::Debug for SharedBytes {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "SharedBytes({} bytes)", self.len())
+  }
+}
 
 #[derive(Debug)]
 pub struct SectionData {
@@ -20,109 +78,515 @@ pub struct ComponentData {
   pub path: String,
   pub instance_id: u32,
   pub kind: ComponentKind,
-  pub data: Vec<u8>,
+  pub source: ComponentSource,
+  pub data: SharedBytes,
+}
+
+#[derive(Debug)]
+enum Backend {
+  File(File),
+  // the whole file, components of uncompressed sections point straight into it
+  #[cfg(feature = "mmap")]
+  Mmap(SharedBytes),
 }
 
 #[derive(Debug)]
 pub struct Str {
-  file: File,
+  backend: Backend,
+  // where the deprecated `decode_zlib_data` continues reading
+  position: u64,
 }
 
 impl Str {
-  pub fn read(path: &Path) -> io::Result<Self> {
+  pub fn read(path: &Path) -> Result<Self> {
     let file = File::open(path)?;
     Ok(Self::read_file(file))
   }
 
+  /// With the `mmap` feature the file is memory mapped, falling back to regular reads if mapping fails.
   pub fn read_file(file: File) -> Self {
-    Self { file }
+    #[cfg(feature = "mmap")]
+    {
+      // SAFETY: the mapping is read only. Like any mmap it assumes the file isn't truncated while mapped.
+      match unsafe { memmap2::Mmap::map(&file) } {
+        Ok(map) => {
+          return Self {
+            backend: Backend::Mmap(SharedBytes::new(map)),
+            position: 0,
+          }
+        }
+        Err(err) => warn!(error = %err, "failed to map str file, falling back to reads"),
+      }
+    }
+
+    Self {
+      backend: Backend::File(file),
+      position: 0,
+    }
   }
 
-  pub fn read_section_data(&mut self, section: &Section) -> io::Result<SectionData> {
-    let header = &section.header;
-    if let Some(zlib) = &header.zlib_header {
-      let section_offset = header.memory_entry.offset as u64;
-      self.file.seek(SeekFrom::Start(section_offset))?;
-
-      if !zlib.cached_sizes.is_empty() && !zlib.uncached_sizes.is_empty() {
-        let uncached = {
-          let data =
-            self.decode_zlib_data(header.uncached_data_size as usize, &zlib.uncached_sizes)?;
-          extract_components(&section.uncached_components, data)
-        };
+  /// Reads the sections of a TOC one at a time, as the iterator is advanced.
+  pub fn sections<'a>(&'a self, toc: &'a Toc) -> Sections<'a> {
+    Sections {
+      str: self,
+      toc,
+      progress: &(),
+      next: 0,
+    }
+  }
 
-        let cached = {
-          let data = self.decode_zlib_data(header.cached_data_size as usize, &zlib.cached_sizes)?;
-          extract_components(&section.cached_components, data)
+  /// Reads a section on its own, treating every component as owned.
+  #[deprecated(note = "tags shared components as owned, use `read_resolved_section_data`")]
+  pub fn read_section_data(&mut self, section: &Section) -> Result<SectionData> {
+    self.read_section_data_at(section)
+  }
+
+  /// Reads a section and takes the data of shared components from the section that stores them,
+  /// marking them as `ComponentSource::Shared`.
+  pub fn read_resolved_section_data(&self, toc: &Toc, section_id: u32) -> Result<SectionData> {
+    let section = toc
+      .find_section(section_id)
+      .ok_or(Error::SectionNotFound { section_id })?;
+
+    let (uncached_data, cached_data) = self.read_section_blocks_at(section)?;
+
+    let shared_section = section
+      .header
+      .shared_section()
+      .filter(|&id| id != section_id)
+      .and_then(|id| toc.find_section(id));
+    let shared_data = match shared_section {
+      Some(shared_section) => Some(self.read_section_blocks_at(shared_section)?),
+      None => None,
+    };
+
+    let resolve = |headers: &[ComponentHeader], data: &SharedBytes| -> Result<Vec<ComponentData>> {
+      let mut components = Vec::with_capacity(headers.len());
+
+      for header in headers {
+        let source = toc.component_source(section_id, header);
+        let shared = match source {
+          ComponentSource::Shared { .. } => shared_section.zip(shared_data.as_ref()).and_then(
+            |(shared_section, (uncached, cached))| {
+              let (shared_header, is_cached) = shared_section.find_instance(header.instance_id)?;
+              let shared_block = if is_cached { cached } else { uncached };
+              Some(component_bytes(shared_header, shared_block))
+            },
+          ),
+          ComponentSource::Owned => None,
         };
+        let data = shared
+          .unwrap_or_else(|| component_bytes(header, data))
+          .map_err(|err| err.with_component(header.path()))?;
 
-        Ok(SectionData { uncached, cached })
-      } else {
-        let mut uncached_data = vec![0u8; header.uncached_data_size as usize];
-        self.file.read(&mut uncached_data)?;
-        let uncached = extract_components(&section.uncached_components, uncached_data);
+        components.push(ComponentData {
+          id: header.id as u32,
+          path: header.path(),
+          instance_id: header.instance_id as u32,
+          kind: header.kind,
+          source,
+          data,
+        });
+      }
 
-        let mut cached_data = vec![0u8; header.cached_data_size as usize];
-        self.file.read(&mut cached_data)?;
-        let cached = extract_components(&section.cached_components, cached_data);
+      Ok(components)
+    };
+
+    let uncached = resolve(&section.uncached_components, &uncached_data)?;
+    let cached = resolve(&section.cached_components, &cached_data)?;
+
+    Ok(SectionData { uncached, cached })
+  }
+
+  /// Reads a section on its own without moving a shared cursor, so several threads can read
+  /// sections of the same `Str` at once. Every component is treated as owned, see
+  /// `read_resolved_section_data` for sections that reference a shared section.
+  pub fn read_section_data_at(&self, section: &Section) -> Result<SectionData> {
+    let (uncached_data, cached_data) = self.read_section_blocks_at(section)?;
+    let uncached = extract_components(&section.uncached_components, &uncached_data)?;
+    let cached = extract_components(&section.cached_components, &cached_data)?;
+
+    Ok(SectionData { uncached, cached })
+  }
+
+  /// Reads (and decompresses if needed) the uncached and cached data blocks of a section.
+  pub(crate) fn read_section_blocks_at(
+    &self,
+    section: &Section,
+  ) -> Result<(SharedBytes, SharedBytes)> {
+    let header = &section.header;
+    let (uncached_data, cached_data) = self.read_unchecked_section_blocks(section)?;
 
-        Ok(SectionData { uncached, cached })
+    let blocks = [
+      (
+        "uncached section data",
+        &uncached_data,
+        header.uncached_data_size,
+      ),
+      ("cached section data", &cached_data, header.cached_data_size),
+    ];
+    for (context, data, size) in blocks {
+      let expected = non_negative(size, "section data size")?;
+      if data.len() != expected {
+        return Err(Error::SizeMismatch {
+          context,
+          expected,
+          actual: data.len(),
+        });
       }
+    }
+
+    Ok((uncached_data, cached_data))
+  }
+
+  /// Reads both blocks without checking their length against the section header, for the validator.
+  pub(crate) fn read_unchecked_section_blocks(
+    &self,
+    section: &Section,
+  ) -> Result<(SharedBytes, SharedBytes)> {
+    let header = &section.header;
+    trace!(
+      name = %header.name(),
+      offset = header.memory_entry.offset,
+      uncached = header.uncached_data_size,
+      cached = header.cached_data_size,
+      "reading section"
+    );
+    let [uncached, cached] = block_layout(section)?;
+    let uncached_data = self.read_block_at(&uncached)?;
+    let cached_data = self.read_block_at(&cached)?;
+
+    Ok((uncached_data, cached_data))
+  }
+
+  /// Reads the component at `position` in `section.components()`. Only the part of the section
+  /// holding it is read, and when its block is compressed only the chunks up to the component are
+  /// inflated.
+  pub fn read_component_at(&self, section: &Section, position: usize) -> Result<SharedBytes> {
+    let component = section.component(position).ok_or(Error::OutOfBounds {
+      context: "component position",
+      offset: position,
+      size: 1,
+      available: section.components().count(),
+    })?;
+    let [uncached, cached] = block_layout(section)?;
+    let block = if position < section.uncached_components.len() {
+      uncached
     } else {
-      let section_offset = header.memory_entry.offset as u64;
-      self.file.seek(SeekFrom::Start(section_offset))?;
+      cached
+    };
+
+    let offset = non_negative(component.memory_entry.offset, "component offset")?;
+    let size = non_negative(component.memory_entry.size, "component size")?;
+    let out_of_bounds = Error::OutOfBounds {
+      context: "component data",
+      offset,
+      size,
+      available: block.size,
+    };
+    let end = offset
+      .checked_add(size)
+      .filter(|&end| end <= block.size)
+      .ok_or(out_of_bounds)?;
+
+    if block.chunk_sizes.is_empty() {
+      return self.read_at(block.offset + offset as u64, size);
+    }
+
+    let mut data = Vec::new();
+    let mut chunk_offset = block.offset;
+    for &chunk_size in block.chunk_sizes {
+      if data.len() >= end {
+        break;
+      }
 
-      let mut uncached_data = vec![0u8; header.uncached_data_size as usize];
-      self.file.read(&mut uncached_data)?;
-      let uncached = extract_components(&section.uncached_components, uncached_data);
+      // the sizes were checked by `block_layout`
+      let chunk = self.read_at(chunk_offset, chunk_size as usize)?;
+      ZlibDecoder::new(&chunk[..]).read_to_end(&mut data)?;
+      chunk_offset += chunk_size as u64;
+    }
 
-      let mut cached_data = vec![0u8; header.cached_data_size as usize];
-      self.file.read(&mut cached_data)?;
-      let cached = extract_components(&section.cached_components, cached_data);
+    let available = data.len();
+    SharedBytes::from(data)
+      .slice(offset..end)
+      .ok_or(Error::OutOfBounds {
+        context: "component data",
+        offset,
+        size,
+        available,
+      })
+  }
 
-      Ok(SectionData { uncached, cached })
+  /// Reads one block of a section, inflating it when it's stored as zlib chunks.
+  fn read_block_at(&self, block: &BlockLayout) -> Result<SharedBytes> {
+    if block.chunk_sizes.is_empty() {
+      return self.read_at(block.offset, block.size);
     }
+
+    trace!(
+      offset = block.offset,
+      chunks = block.chunk_sizes.len(),
+      "inflating block"
+    );
+    let data = self.decode_zlib_data_at(block.offset, block.size, block.chunk_sizes)?;
+    Ok(data.into())
+  }
+
+  /// Decodes the zlib chunks following the ones the previous call read, starting at the beginning
+  /// of the file. The other reads don't move that position.
+  #[deprecated(note = "reads at a position kept by the `Str`, use `decode_zlib_data_at`")]
+  pub fn decode_zlib_data(&mut self, output_size: usize, input_sizes: &[i32]) -> Result<Vec<u8>> {
+    let output = self.decode_zlib_data_at(self.position, output_size, input_sizes)?;
+    // the sizes were checked by `decode_zlib_data_at`
+    self.position += input_sizes.iter().map(|&size| size as u64).sum::<u64>();
+    Ok(output)
   }
 
-  pub fn decode_zlib_data(
-    &mut self,
+  /// Decodes the zlib chunks starting at `offset`. With the `rayon` feature the chunks are inflated in parallel.
+  pub fn decode_zlib_data_at(
+    &self,
+    offset: u64,
     output_size: usize,
     input_sizes: &[i32],
-  ) -> io::Result<Vec<u8>> {
-    let mut output = Vec::with_capacity(output_size);
+  ) -> Result<Vec<u8>> {
+    let mut chunks = Vec::with_capacity(input_sizes.len());
+    let mut chunk_offset = offset;
+    for &size in input_sizes {
+      let size = non_negative(size, "zlib chunk size")?;
+      chunks.push((chunk_offset, size));
+      chunk_offset += size as u64;
+    }
 
-    for size in input_sizes {
-      // reading compressed chunk
-      let mut buf = vec![0; *size as usize];
-      self.file.read_exact(&mut buf)?;
+    let inflate = |&(offset, size): &(u64, usize)| -> Result<Vec<u8>> {
+      let chunk = self.read_at(offset, size)?;
+      let mut output = Vec::new();
+      ZlibDecoder::new(&chunk[..]).read_to_end(&mut output)?;
+      Ok(output)
+    };
 
-      // decompressing chunk and appending to merged vector
-      let mut decoder = ZlibDecoder::new(&buf[..]);
-      decoder.read_to_end(&mut output)?;
+    #[cfg(feature = "rayon")]
+    let decoded = {
+      use rayon::prelude::*;
+      chunks.par_iter().map(inflate).collect::<Result<Vec<_>>>()?
+    };
+    #[cfg(not(feature = "rayon"))]
+    let decoded = chunks.iter().map(inflate).collect::<Result<Vec<_>>>()?;
+
+    let mut output = Vec::with_capacity(output_size);
+    for chunk in decoded {
+      output.extend_from_slice(&chunk);
     }
 
     Ok(output)
   }
+
+  fn read_at(&self, offset: u64, size: usize) -> Result<SharedBytes> {
+    match &self.backend {
+      Backend::File(file) => {
+        let mut data = vec![0u8; size];
+        read_exact_at(file, &mut data, offset)?;
+        Ok(data.into())
+      }
+      #[cfg(feature = "mmap")]
+      Backend::Mmap(map) => {
+        let offset = offset as usize;
+        offset
+          .checked_add(size)
+          .and_then(|end| map.slice(offset..end))
+          .ok_or(Error::OutOfBounds {
+            context: "str file",
+            offset,
+            size,
+            available: map.len(),
+          })
+      }
+    }
+  }
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+  std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+// no positional reads, so every read seeks the file while holding a lock
+#[cfg(not(any(unix, windows)))]
+fn read_exact_at(mut file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+  use std::io::{Seek, SeekFrom};
+  use std::sync::Mutex;
+
+  static LOCK: Mutex<()> = Mutex::new(());
+  let _guard = LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+  file.seek(SeekFrom::Start(offset))?;
+  file.read_exact(buf)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> std::io::Result<()> {
+  use std::os::windows::fs::FileExt;
+
+  // seek_read may return fewer bytes than asked for
+  while !buf.is_empty() {
+    match file.seek_read(buf, offset)? {
+      0 => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+      read => {
+        buf = &mut buf[read..];
+        offset += read as u64;
+      }
+    }
+  }
+
+  Ok(())
+}
+
+// Where a block of a section is stored.
+struct BlockLayout<'a> {
+  offset: u64,
+  // once read, and inflated if needed
+  size: usize,
+  // empty when the block isn't compressed
+  chunk_sizes: &'a [i32],
 }
 
-fn extract_components(headers: &[ComponentHeader], data: Vec<u8>) -> Vec<ComponentData> {
+// The uncached and cached blocks of a section. The cached block follows the uncached one, however
+// either of them is stored.
+fn block_layout(section: &Section) -> Result<[BlockLayout<'_>; 2]> {
+  let header = &section.header;
+  let (uncached_sizes, cached_sizes) = match &header.zlib_header {
+    Some(zlib) => (&zlib.uncached_sizes[..], &zlib.cached_sizes[..]),
+    None => (&[][..], &[][..]),
+  };
+
+  for &size in uncached_sizes.iter().chain(cached_sizes) {
+    non_negative(size, "zlib chunk size")?;
+  }
+
+  let uncached = BlockLayout {
+    offset: non_negative(header.memory_entry.offset, "section offset")? as u64,
+    size: non_negative(header.uncached_data_size, "section data size")?,
+    chunk_sizes: uncached_sizes,
+  };
+  let stored_size = if uncached_sizes.is_empty() {
+    uncached.size as u64
+  } else {
+    uncached_sizes.iter().map(|&size| size as u64).sum()
+  };
+
+  let cached = BlockLayout {
+    offset: uncached.offset + stored_size,
+    size: non_negative(header.cached_data_size, "section data size")?,
+    chunk_sizes: cached_sizes,
+  };
+
+  Ok([uncached, cached])
+}
+
+pub(crate) fn component_slice<'a>(header: &ComponentHeader, data: &'a [u8]) -> Result<&'a [u8]> {
+  let offset = non_negative(header.memory_entry.offset, "component offset")?;
+  let size = non_negative(header.memory_entry.size, "component size")?;
+
+  slice_checked(data, offset, size, "component data")
+}
+
+fn component_bytes(header: &ComponentHeader, data: &SharedBytes) -> Result<SharedBytes> {
+  let offset = non_negative(header.memory_entry.offset, "component offset")?;
+  let size = non_negative(header.memory_entry.size, "component size")?;
+
+  offset
+    .checked_add(size)
+    .and_then(|end| data.slice(offset..end))
+    .ok_or(Error::OutOfBounds {
+      context: "component data",
+      offset,
+      size,
+      available: data.len(),
+    })
+}
+
+fn extract_components(
+  headers: &[ComponentHeader],
+  data: &SharedBytes,
+) -> Result<Vec<ComponentData>> {
   let mut components = Vec::with_capacity(headers.len());
 
   for header in headers {
-    let start = header.memory_entry.offset as usize;
-    let end = (header.memory_entry.offset + header.memory_entry.size) as usize;
-
     let component = ComponentData {
       id: header.id as u32,
       path: header.path(),
       instance_id: header.instance_id as u32,
       kind: header.kind,
-      // copy data for each component
-      data: data[start..end].to_vec(),
+      source: ComponentSource::Owned,
+      data: component_bytes(header, data).map_err(|err| err.with_component(header.path()))?,
     };
 
     components.push(component);
   }
 
-  components
+  Ok(components)
+}
+
+/// Iterator returned by `Str::sections`, yields each section id with its data.
+pub struct Sections<'a> {
+  str: &'a Str,
+  toc: &'a Toc,
+  progress: &'a dyn Progress,
+  next: u32,
+}
+
+impl<'a> Sections<'a> {
+  /// Reports each section before reading it, and its components once it's read, to `progress`.
+  /// Cancelling yields `Error::Cancelled` and ends the iteration.
+  pub fn with_progress(self, progress: &'a dyn Progress) -> Self {
+    Self { progress, ..self }
+  }
+
+  fn read(&self, section_id: u32, section: &Section) -> Result<SectionData> {
+    let header = &section.header;
+    let bytes = header.uncached_data_size.max(0) as usize + header.cached_data_size.max(0) as usize;
+    if self
+      .progress
+      .section(section_id, &header.name(), bytes)
+      .is_break()
+    {
+      return Err(Error::Cancelled);
+    }
+
+    let data = self.str.read_resolved_section_data(self.toc, section_id)?;
+    for component in data.uncached.iter().chain(&data.cached) {
+      if self
+        .progress
+        .component(section_id, &component.path, component.data.len())
+        .is_break()
+      {
+        return Err(Error::Cancelled);
+      }
+    }
+
+    Ok(data)
+  }
+}
+
+impl Iterator for Sections<'_> {
+  type Item = Result<(u32, SectionData)>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let section_id = self.next;
+    let section = self.toc.find_section(section_id)?;
+    self.next += 1;
+
+    let result = self
+      .read(section_id, section)
+      .map(|data| (section_id, data));
+
+    if let Err(Error::Cancelled) = result {
+      self.next = self.toc.sections.len() as u32;
+    }
+
+    Some(result)
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    let remaining = self.toc.sections.len().saturating_sub(self.next as usize);
+    (remaining, Some(remaining))
+  }
 }
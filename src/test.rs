@@ -1,19 +1,36 @@
-use std::io::Write;
+use std::io::{Cursor, Read, Write};
+use std::ops::ControlFlow;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
 
-use binrw::BinWrite;
-use x_flipper_360::*;
+use binrw::{BinRead, BinWrite};
 
-use crate::textures::{GCNTextureHeader, GCTSurfaceHeader};
+use crate::str::component_slice;
+use crate::textures::{
+  GCNTextureHeader, GCTFormat, GXPaletteFormat, GpuTextureFetch, XenosFormat, DDPF_LUMINANCE,
+};
+use crate::toc::{MemoryEntry, ZlibHeader};
 use crate::ComponentKind::{self, *};
 use crate::{
-  utils, DXGHeader, DXGHeaderArgs, DXTStaticTextureHeader, DXTTextureHeader, GCGHeader,
-  GCGHeaderArgs, GCNStaticTextureHeader, Res, X360StaticTextureHeader, XNGHeader,
+  utils, DXGHeader, DXGHeaderArgs, DXTFormat, DXTStaticTextureHeader, DXTTextureHeader, GCGHeader,
+  GCGHeaderArgs, GCNStaticTextureHeader, Res, X360StaticTextureHeader, X360TextureHeader,
+  XNGHeader,
+};
+use crate::{
+  Block, Bounding, CollisionModelArgs, ComponentData, ComponentHeader, ComponentSource, DXTSurface,
+  DXTSurfaceHeader, DdsHeader, DdsPixelFormat, EncodeQuality, ExtractOptions, MipFilter, ModelInfo,
+  Progress, ResolvedComponent, RgbaImage, Section, SectionGraph, SectionHeader, SharedBytes, Soi,
+  SoiSoup, StaticTexture, Str, StreamingDataSize, StreamingTexture, Texture, TextureConvert,
+  TextureFormat, TextureHash, TextureOutput, TextureReport, Toc, ValidationIssue, Vector3, Vfs,
+  XNGHeaderArgs,
 };
-use crate::{CollisionModelArgs, ComponentData, SoiSoup, Str, XNGHeaderArgs};
 
 pub type XboxSoiSoup = SoiSoup<DXTTextureHeader, DXTStaticTextureHeader, DXGHeader>;
-pub type X360SoiSoup = SoiSoup<TextureHeader, X360StaticTextureHeader, XNGHeader>;
+// the other platforms' soups and handlers, for running `extract` against their files
+#[allow(dead_code)]
+pub type X360SoiSoup = SoiSoup<X360TextureHeader, X360StaticTextureHeader, XNGHeader>;
+#[allow(dead_code)]
 pub type WiiSoiSoup = SoiSoup<GCNTextureHeader, GCNStaticTextureHeader, GCGHeader>;
 
 #[test]
@@ -38,10 +55,12 @@ fn extract() {
     .unwrap();
 
   let soup = XboxSoiSoup::cook(toc_path, soi_path, binrw::Endian::Little).unwrap();
-  let mut str = Str::read(str_path).unwrap();
+  let str = Str::read(str_path).unwrap();
 
-  for (id, section) in soup.find_sections().iter().enumerate() {
-    let section_data = str.read_section_data(section).unwrap();
+  for id in 0..soup.find_sections().len() {
+    let section_data = str
+      .read_resolved_section_data(soup.toc(), id as u32)
+      .unwrap();
 
     for component in section_data.uncached {
       process_component_xbox(&soup, id as u32, component);
@@ -75,12 +94,14 @@ fn dump_scn() {
     .unwrap();
 
   let soup = XboxSoiSoup::cook(toc_path, soi_path, binrw::Endian::Little).unwrap();
-  let mut str = Str::read(str_path).unwrap();
+  let str = Str::read(str_path).unwrap();
   let mut num_anim_models = 1;
   let mut num_static_models = 1;
   let mut num_objects = 1;
-  for (id, section) in soup.find_sections().iter().enumerate() {
-    let section_data = str.read_section_data(section).unwrap();
+  for id in 0..soup.find_sections().len() {
+    let section_data = str
+      .read_resolved_section_data(soup.toc(), id as u32)
+      .unwrap();
 
     for component in section_data.uncached {
       print_component(
@@ -106,6 +127,52 @@ fn dump_scn() {
   }
 }
 
+// Checks the shared section and page readings of the TOC against a real level: a shared component
+// has to be stored in its shared section under the same path and kind, and page indices have to
+// exist in the SOI.
+#[test]
+fn shared_sections_in_real_level() {
+  let res = Res::read(Path::new("./data/FE.xbox.res")).unwrap();
+  let toc_path = Path::new("./data/FE.xbox.toc");
+  let soi_path = Path::new("./data/FE.xbox.soi");
+  std::fs::write(toc_path, res.get_file("FE.xbox.toc".to_owned()).unwrap()).unwrap();
+  std::fs::write(soi_path, res.get_file("FE.xbox.soi".to_owned()).unwrap()).unwrap();
+  let soup = XboxSoiSoup::cook(toc_path, soi_path, binrw::Endian::Little).unwrap();
+
+  let str = Str::read(Path::new("./data/FE.xbox.str")).unwrap();
+  for (section_id, section) in soup.find_sections().iter().enumerate() {
+    let section_id = section_id as u32;
+    if let Some(page) = section.header.uncached_page() {
+      assert!((page as usize) < soup.soi().get_uncached_page_sizes().len());
+    }
+    if let Some(page) = section.header.cached_page() {
+      assert!((page as usize) < soup.soi().get_cached_page_sizes().len());
+    }
+
+    let data = str
+      .read_resolved_section_data(soup.toc(), section_id)
+      .unwrap();
+    for component in data.uncached.iter().chain(&data.cached) {
+      let ComponentSource::Shared {
+        section_id: shared_id,
+        component_id,
+      } = component.source
+      else {
+        continue;
+      };
+      let shared = soup.toc().find_section(shared_id).unwrap();
+      let stored = shared
+        .components()
+        .find(|stored| stored.id as u32 == component_id)
+        .unwrap();
+      assert_eq!(
+        (stored.path(), stored.kind),
+        (component.path.clone(), component.kind)
+      );
+    }
+  }
+}
+
 fn print_component<
   StreamingTH: binrw::BinRead<Args<'static> = ()> + 'static,
   StaticTH: binrw::BinRead<Args<'static> = ()> + 'static,
@@ -125,10 +192,10 @@ fn print_component<
         .unwrap();
       if header.model_info.is_animated == 1 {
         println!("[AnimatedModel{}]\n{}", num_anim_models, header);
-        *num_anim_models = *num_anim_models + 1;
+        *num_anim_models += 1;
       } else {
         println!("[Model{}]\n{}", num_static_models, header);
-        *num_static_models = *num_static_models + 1;
+        *num_static_models += 1;
       }
     }
     Texture => {
@@ -139,7 +206,7 @@ fn print_component<
         .find_collision_model(section_id, component.id, component.instance_id)
         .unwrap();
       println!("[Object{}]\n{}", num_objects, header);
-      *num_objects = *num_objects + 1;
+      *num_objects += 1;
     }
     UserData => {
       // println!("found UserData component kind; skipping...");
@@ -153,6 +220,7 @@ fn print_component<
   }
 }
 
+#[allow(dead_code)]
 fn process_component_wii(soup: &WiiSoiSoup, section_id: u32, component: ComponentData) {
   if component.kind == ComponentKind::MotionPack {
     let header = soup
@@ -180,7 +248,7 @@ fn process_component_wii(soup: &WiiSoiSoup, section_id: u32, component: Componen
         &mut out,
         binrw::Endian::Big,
         &GCGHeaderArgs {
-          streaming_data: component.data.clone(),
+          streaming_data: &component.data,
         },
       )
       .unwrap();
@@ -200,7 +268,7 @@ fn process_component_wii(soup: &WiiSoiSoup, section_id: u32, component: Componen
         binrw::Endian::Big,
         &CollisionModelArgs {
           ror: true,
-          streaming_data: component.data.clone(),
+          streaming_data: &component.data,
         },
       )
       .unwrap();
@@ -212,55 +280,8 @@ fn process_component_wii(soup: &WiiSoiSoup, section_id: u32, component: Componen
         std::fs::create_dir_all(path.parent().unwrap()).unwrap();
         let mut out = std::fs::File::create(path).unwrap();
 
-        // Write the version out (streamed GCTs have a flag OR'd on the version, so we set it manually to 2 [otherwise we would use streaming_texture.version])
-        u32::write_options(&2, &mut out, binrw::Endian::Big, ()).unwrap();
-        streaming_texture.header.write_be(&mut out).unwrap();
-
-        // Keeps track of our position in the streaming data.
-        let mut offset = 0;
-        let mip_count = streaming_texture.header.mip_count;
-
-        // Holds the offsets and sizes (in a Range<usize>) for each mip so we can iterate backwards over this later
-        let mut mips = Vec::with_capacity(mip_count as usize);
-
-        // Since mips are stored in forwards order (biggest to smallest) in the streaming data we need to first collect them in the vec,
-        for i in 0..mip_count {
-          let mip_width = 1.max(streaming_texture.header.width as usize / (2 as usize).pow(i));
-          let mip_height = 1.max(streaming_texture.header.height as usize / (2 as usize).pow(i));
-
-          let mip_size = streaming_texture
-            .header
-            .format
-            .calculate_mip_size(mip_width, mip_height);
-
-          mips.push(offset..offset + mip_size);
-
-          offset += mip_size as usize;
-        }
-
-        // (At this point, we should have read through the entire streaming data)
-        assert_eq!(offset, streaming_texture.header.calculate_image_size());
-        assert_eq!(offset, component.data.len());
-
-        // and we can then terate over the mips in backwards order and write them to the GCT.
-        for i in (0..mip_count).rev() {
-          let mip_width = 1.max(streaming_texture.header.width as usize / (2 as usize).pow(i));
-          let mip_height = 1.max(streaming_texture.header.height as usize / (2 as usize).pow(i));
-
-          let surface_header = GCTSurfaceHeader {
-            width: mip_width as u32,
-            height: mip_height as u32,
-            size: streaming_texture
-              .header
-              .format
-              .calculate_mip_size(mip_width, mip_height) as u32,
-          };
-          surface_header.write_be(&mut out).unwrap();
-
-          component.data[mips.get(i as usize).unwrap().clone()]
-            .write(&mut out)
-            .unwrap();
-        }
+        let texture = streaming_texture.to_static(&component.data).unwrap();
+        texture.static_texture_header.write_be(&mut out).unwrap();
       }
       None => {
         panic!("Failed to find texture header.");
@@ -296,7 +317,7 @@ fn process_component_xbox(soup: &XboxSoiSoup, section_id: u32, component: Compon
         &mut out,
         binrw::Endian::Little,
         &DXGHeaderArgs {
-          streaming_data: component.data.clone(),
+          streaming_data: &component.data,
         },
       )
       .unwrap();
@@ -316,7 +337,7 @@ fn process_component_xbox(soup: &XboxSoiSoup, section_id: u32, component: Compon
         binrw::Endian::Little,
         &CollisionModelArgs {
           ror: false,
-          streaming_data: component.data.clone(),
+          streaming_data: &component.data,
         },
       )
       .unwrap();
@@ -328,55 +349,8 @@ fn process_component_xbox(soup: &XboxSoiSoup, section_id: u32, component: Compon
         std::fs::create_dir_all(path.parent().unwrap()).unwrap();
         let mut out = std::fs::File::create(path).unwrap();
 
-        // Write the version out (streamed GCTs have a flag OR'd on the version, so we set it manually to 2 [otherwise we would use streaming_texture.version])
-        u32::write_options(&2, &mut out, binrw::Endian::Little, ()).unwrap();
-        streaming_texture.header.write_le(&mut out).unwrap();
-
-        // Keeps track of our position in the streaming data.
-        let mut offset = 0;
-        let mip_count = streaming_texture.header.mip_count;
-
-        // Holds the offsets and sizes (in a Range<usize>) for each mip so we can iterate backwards over this later
-        let mut mips = Vec::with_capacity(mip_count as usize);
-
-        // Since mips are stored in forwards order (biggest to smallest) in the streaming data we need to first collect them in the vec,
-        for i in 0..mip_count {
-          let mip_width = 1.max(streaming_texture.header.width as usize / (2 as usize).pow(i));
-          let mip_height = 1.max(streaming_texture.header.height as usize / (2 as usize).pow(i));
-
-          let mip_size = streaming_texture
-            .header
-            .format
-            .calculate_mip_size(mip_width, mip_height);
-
-          mips.push(offset..offset + mip_size);
-
-          offset += mip_size as usize;
-        }
-
-        // (At this point, we should have read through the entire streaming data)
-        assert_eq!(offset, streaming_texture.header.calculate_image_size());
-        assert_eq!(offset, component.data.len());
-
-        // and we can then terate over the mips in backwards order and write them to the GCT.
-        for i in (0..mip_count).rev() {
-          let mip_width = 1.max(streaming_texture.header.width as usize / (2 as usize).pow(i));
-          let mip_height = 1.max(streaming_texture.header.height as usize / (2 as usize).pow(i));
-
-          let surface_header = GCTSurfaceHeader {
-            width: mip_width as u32,
-            height: mip_height as u32,
-            size: streaming_texture
-              .header
-              .format
-              .calculate_mip_size(mip_width, mip_height) as u32,
-          };
-          surface_header.write_le(&mut out).unwrap();
-
-          component.data[mips.get(i as usize).unwrap().clone()]
-            .write(&mut out)
-            .unwrap();
-        }
+        let texture = streaming_texture.to_static(&component.data).unwrap();
+        texture.static_texture_header.write_le(&mut out).unwrap();
       }
       None => {
         panic!("Failed to find texture header.");
@@ -385,6 +359,7 @@ fn process_component_xbox(soup: &XboxSoiSoup, section_id: u32, component: Compon
   }
 }
 
+#[allow(dead_code)]
 fn process_component_xbox360(soup: &X360SoiSoup, section_id: u32, component: ComponentData) {
   if component.kind == ComponentKind::MotionPack {
     let header = soup
@@ -412,7 +387,7 @@ fn process_component_xbox360(soup: &X360SoiSoup, section_id: u32, component: Com
         &mut out,
         binrw::Endian::Big,
         &XNGHeaderArgs {
-          streaming_data: component.data.clone(),
+          streaming_data: &component.data,
         },
       )
       .unwrap();
@@ -432,7 +407,7 @@ fn process_component_xbox360(soup: &X360SoiSoup, section_id: u32, component: Com
         binrw::Endian::Big,
         &CollisionModelArgs {
           ror: false,
-          streaming_data: component.data.clone(),
+          streaming_data: &component.data,
         },
       )
       .unwrap();
@@ -440,98 +415,10 @@ fn process_component_xbox360(soup: &X360SoiSoup, section_id: u32, component: Com
   if component.kind == ComponentKind::Texture {
     match soup.find_streaming_texture(section_id, component.id, component.instance_id) {
       Some(header) => {
-        let metadata = header.header.metadata();
-
-        match metadata.format() {
-          TextureFormat::Dxt1 => {
-            let texture_size: TextureSize2D =
-              TextureSize2D::from_bytes(metadata.texture_size().to_le_bytes());
-
-            let config = Config {
-              width: texture_size.width() as u32 + 1,
-              height: texture_size.height() as u32 + 1,
-              depth: None,
-              pitch: metadata.pitch() as u32,
-              tiled: metadata.tiled(),
-              packed_mips: metadata.packed_mips(),
-              format: Format::Dxt1,
-              mipmap_levels: Some(1.max(metadata.max_mip_level() - metadata.min_mip_level()) as u32),
-              base_address: metadata.base_address(),
-              mip_address: metadata.mip_address(),
-            };
-
-            let path = PathBuf::from(format!("./data/FE/{}.dds", component.path));
-            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
-            let mut out = std::fs::File::create(path).unwrap();
-            x_flipper_360::convert_to_dds(&config, &component.data, &mut out).unwrap();
-          }
-          TextureFormat::Dxt4_5 => {
-            let texture_size: TextureSize2D =
-              TextureSize2D::from_bytes(metadata.texture_size().to_le_bytes());
-
-            let config = Config {
-              width: texture_size.width() as u32 + 1,
-              height: texture_size.height() as u32 + 1,
-              depth: None,
-              pitch: metadata.pitch() as u32,
-              tiled: metadata.tiled(),
-              packed_mips: metadata.packed_mips(),
-              format: Format::Dxt5,
-              mipmap_levels: Some(1.max(metadata.max_mip_level() - metadata.min_mip_level()) as u32),
-              base_address: metadata.base_address(),
-              mip_address: metadata.mip_address(),
-            };
-
-            let path = PathBuf::from(format!("./data/FE/{}.dds", component.path));
-            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
-            let mut out = std::fs::File::create(path).unwrap();
-            x_flipper_360::convert_to_dds(&config, &component.data, &mut out).unwrap();
-          }
-          TextureFormat::Dxt2_3 => {
-            let texture_size: TextureSize2D =
-              TextureSize2D::from_bytes(metadata.texture_size().to_le_bytes());
-
-            let config = Config {
-              width: texture_size.width() as u32 + 1,
-              height: texture_size.height() as u32 + 1,
-              depth: None,
-              pitch: metadata.pitch() as u32,
-              tiled: metadata.tiled(),
-              packed_mips: metadata.packed_mips(),
-              format: Format::Dxt3,
-              mipmap_levels: Some(1.max(metadata.max_mip_level() - metadata.min_mip_level()) as u32),
-              base_address: metadata.base_address(),
-              mip_address: metadata.mip_address(),
-            };
-
-            let path = PathBuf::from(format!("./data/FE/{}.dds", component.path));
-            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
-            let mut out = std::fs::File::create(path).unwrap();
-            x_flipper_360::convert_to_dds(&config, &component.data, &mut out).unwrap();
-          }
-          _ => {
-            let texture_size: TextureSize2D =
-              TextureSize2D::from_bytes(metadata.texture_size().to_le_bytes());
-
-            let config = Config {
-              width: texture_size.width() as u32 + 1,
-              height: texture_size.height() as u32 + 1,
-              depth: None,
-              pitch: metadata.pitch() as u32,
-              tiled: metadata.tiled(),
-              packed_mips: metadata.packed_mips(),
-              format: Format::RGBA8,
-              mipmap_levels: Some(1.max(metadata.max_mip_level() - metadata.min_mip_level()) as u32),
-              base_address: metadata.base_address(),
-              mip_address: metadata.mip_address(),
-            };
-
-            let path = PathBuf::from(format!("./data/FE/{}.dds", component.path));
-            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
-            let mut out = std::fs::File::create(path).unwrap();
-            x_flipper_360::convert_to_dds(&config, &component.data, &mut out).unwrap();
-          }
-        }
+        let path = PathBuf::from(format!("./data/FE/{}.dds", component.path));
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let mut out = std::fs::File::create(path).unwrap();
+        header.write_dds(&mut out, &component.data).unwrap();
       }
       None => match soup.find_static_texture(section_id, component.id, component.instance_id) {
         Some(static_texture) => {
@@ -547,3 +434,1661 @@ fn process_component_xbox360(soup: &X360SoiSoup, section_id: u32, component: Com
     }
   }
 }
+
+#[test]
+fn bounding_queries() {
+  let vector = |x, y, z| Vector3 { x, y, z };
+  let bounding = Bounding::new(vector(0.0, 0.0, 0.0), vector(10.0, 10.0, 10.0));
+
+  assert!(bounding.contains(&vector(5.0, 5.0, 5.0)));
+  assert!(!bounding.contains(&vector(11.0, 5.0, 5.0)));
+  assert_eq!(bounding.distance_squared(&vector(13.0, 14.0, 5.0)), 25.0);
+
+  let origin = vector(-5.0, 5.0, 5.0);
+  let hit = bounding.intersect_ray(&origin, &vector(1.0, 0.0, 0.0));
+  assert_eq!(hit, Some(5.0));
+  let miss = bounding.intersect_ray(&origin, &vector(-1.0, 0.0, 0.0));
+  assert_eq!(miss, None);
+}
+
+// a section without components or data
+fn empty_section(name: &str, bounding: Bounding) -> Section {
+  let mut raw_name = [0; 260];
+  raw_name[..name.len()].copy_from_slice(name.as_bytes());
+
+  Section {
+    header: SectionHeader {
+      name: raw_name,
+      total_component_count: 0,
+      uncached_component_count: 0,
+      cached_component_count: 0,
+      shared_section_offset: -1,
+      uncached_page_offset: -1,
+      cached_page_offset: -1,
+      link_table: [-1; 8],
+      bounding,
+      memory_entry: MemoryEntry { offset: 0, size: 0 },
+      uncached_data_size: 0,
+      cached_data_size: 0,
+      zlib_header: None,
+    },
+    uncached_components: Vec::new(),
+    cached_components: Vec::new(),
+  }
+}
+
+fn component(
+  path: &str,
+  instance_id: i32,
+  id: i32,
+  kind: ComponentKind,
+  offset: i32,
+  size: i32,
+) -> ComponentHeader {
+  let mut raw_path = [0; 260];
+  raw_path[..path.len()].copy_from_slice(path.as_bytes());
+
+  ComponentHeader {
+    raw_path,
+    instance_id,
+    id,
+    memory_entry: MemoryEntry { offset, size },
+    kind,
+  }
+}
+
+// a file in the temp dir that no other test (or test run) writes to
+fn temp_file(name: &str, data: &[u8]) -> PathBuf {
+  static NEXT: AtomicUsize = AtomicUsize::new(0);
+  let id = NEXT.fetch_add(1, Ordering::Relaxed);
+  let path = std::env::temp_dir().join(format!("soiboy_{}_{}_{}", std::process::id(), id, name));
+  std::fs::write(&path, data).unwrap();
+  path
+}
+
+// SOI records are only ever read, so the tests write their bytes by hand
+fn model_info(section_id: i32, component_id: i32) -> Vec<u8> {
+  let mut info = Vec::new();
+  // flags, then the position, look and up vectors
+  info.extend([0; 4 + 48]);
+  for value in [0, section_id, component_id] {
+    info.extend(value.to_le_bytes());
+  }
+  info.extend([0; 260]);
+  // zone and parameter count
+  for value in [-1i32, 0] {
+    info.extend(value.to_le_bytes());
+  }
+  info
+}
+
+// an xbox SOI with no models, and textures keyed by (section id, component id)
+fn xbox_soup(
+  toc: Toc,
+  pages: (&[i32], &[i32]),
+  streaming_textures: &[(i32, i32, DXTTextureHeader)],
+  static_textures: &[(i32, i32, DXTStaticTextureHeader)],
+) -> XboxSoiSoup {
+  let mut soi = Cursor::new(Vec::new());
+  let counts = [
+    1,
+    0,
+    toc.sections.len() as i32,
+    0,
+    0,
+    0,
+    streaming_textures.len() as i32,
+    static_textures.len() as i32,
+    pages.0.len() as i32,
+    pages.1.len() as i32,
+  ];
+  // then the five record offsets and the streaming mode
+  for value in counts.into_iter().chain([0; 6]) {
+    value.write_le(&mut soi).unwrap();
+  }
+  soi.write_all(&[0; 16]).unwrap();
+  for &size in pages.0.iter().chain(pages.1) {
+    size.write_le(&mut soi).unwrap();
+  }
+
+  for (section_id, component_id, header) in streaming_textures {
+    soi
+      .write_all(&model_info(*section_id, *component_id))
+      .unwrap();
+    0u32.write_le(&mut soi).unwrap();
+    header.write_le(&mut soi).unwrap();
+  }
+  for (section_id, component_id, header) in static_textures {
+    soi
+      .write_all(&model_info(*section_id, *component_id))
+      .unwrap();
+    header.write_le(&mut soi).unwrap();
+  }
+
+  soi.set_position(0);
+  SoiSoup::new(toc, Soi::read_le(&mut soi).unwrap())
+}
+
+fn dxt1_header(width: u32, height: u32) -> DXTTextureHeader {
+  DXTTextureHeader {
+    format: DXTFormat::Dxt1,
+    palette_size: 0,
+    palette: Vec::new(),
+    mip_count: 1,
+    width,
+    height,
+  }
+}
+
+fn zlib(data: &[u8]) -> Vec<u8> {
+  let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+  encoder.write_all(data).unwrap();
+  encoder.finish().unwrap()
+}
+
+#[test]
+fn section_queries() {
+  let vector = |x, y, z| Vector3 { x, y, z };
+  let toc = Toc::new(vec![
+    empty_section(
+      "a",
+      Bounding::new(vector(0.0, 0.0, 0.0), vector(10.0, 10.0, 10.0)),
+    ),
+    empty_section(
+      "b",
+      Bounding::new(vector(5.0, 0.0, 0.0), vector(20.0, 10.0, 10.0)),
+    ),
+    empty_section(
+      "c",
+      Bounding::new(vector(30.0, 0.0, 0.0), vector(40.0, 10.0, 10.0)),
+    ),
+  ]);
+
+  assert_eq!(toc.sections_containing(&vector(7.0, 5.0, 5.0)), [0, 1]);
+  assert_eq!(
+    toc.sections_containing(&vector(25.0, 5.0, 5.0)),
+    [] as [u32; 0]
+  );
+
+  let bounds = Bounding::new(vector(15.0, 0.0, 0.0), vector(35.0, 1.0, 1.0));
+  assert_eq!(toc.sections_intersecting(&bounds), [1, 2]);
+
+  // fired from past the last section back towards the first
+  let hits = toc.sections_intersecting_ray(&vector(50.0, 5.0, 5.0), &vector(-1.0, 0.0, 0.0));
+  assert_eq!(hits, [(2, 10.0), (1, 30.0), (0, 40.0)]);
+
+  // inside both a and b, b's center is closer
+  assert_eq!(toc.nearest_section(&vector(9.0, 5.0, 5.0)), Some(1));
+  assert_eq!(toc.nearest_section(&vector(1.0, 5.0, 5.0)), Some(0));
+  // outside every section, c is 2 away and b 8
+  assert_eq!(toc.nearest_section(&vector(28.0, 5.0, 5.0)), Some(2));
+  assert_eq!(
+    Toc::new(Vec::new()).nearest_section(&vector(0.0, 0.0, 0.0)),
+    None
+  );
+}
+
+#[test]
+fn shared_section_components() {
+  let bounding = Bounding::new(Vector3::default(), Vector3::default());
+
+  let mut shared = empty_section("shared", bounding);
+  shared.uncached_components = vec![component("textures/sky.tex", 5, 0, Texture, 0, 6)];
+  shared.header.uncached_data_size = 6;
+
+  // references the sky in section 0 and stores the road itself
+  let mut level = empty_section("level", bounding);
+  level.uncached_components = vec![
+    component("textures/road.tex", 6, 0, Texture, 0, 4),
+    component("textures/sky.tex", 5, 1, Texture, 0, 0),
+  ];
+  level.header.shared_section_offset = 0;
+  level.header.memory_entry.offset = 6;
+  level.header.uncached_data_size = 4;
+
+  let toc = Toc::new(vec![shared, level]);
+  assert_eq!(toc.shared_sections(), [0]);
+
+  let sources: Vec<_> = toc.sections[1]
+    .components()
+    .map(|component| toc.component_source(1, component))
+    .collect();
+  assert_eq!(
+    sources,
+    [
+      ComponentSource::Owned,
+      ComponentSource::Shared {
+        section_id: 0,
+        component_id: 0
+      }
+    ]
+  );
+  // a shared section's own components aren't references
+  let sky = &toc.sections[0].uncached_components[0];
+  assert_eq!(toc.component_source(0, sky), ComponentSource::Owned);
+
+  let path = temp_file("shared.str", b"skyboxroad");
+  let str = Str::read(&path).unwrap();
+  let data = str.read_resolved_section_data(&toc, 1).unwrap();
+  assert_eq!(&data.uncached[0].data[..], b"road");
+  assert_eq!(&data.uncached[1].data[..], b"skybox");
+  assert_eq!(data.uncached[1].source, sources[1]);
+
+  drop(str);
+  std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn validate_level() {
+  let bounding = Bounding::new(Vector3::default(), Vector3::default());
+  let mut section = empty_section("level", bounding);
+  section.uncached_components = vec![
+    component("ok.tex", 1, 0, Texture, 0, 32),
+    component("short.tex", 2, 1, Texture, 32, 8),
+    component("static.tex", 3, 2, Texture, 40, 0),
+    component("past_end.dat", 4, 3, UserData, 40, 100),
+    component("unknown.tex", 5, 4, Texture, 0, 8),
+  ];
+  section.header.uncached_data_size = 40;
+  section.header.uncached_page_offset = 0;
+  section.header.cached_page_offset = 3;
+
+  // 4x4 DXT1 is a single 8 byte block
+  let static_texture = DXTStaticTextureHeader {
+    version: 2,
+    format: DXTFormat::Dxt1,
+    palette_size: 0,
+    palette: Vec::new(),
+    mip_count: 1,
+    width: 4,
+    height: 4,
+    mips: vec![DXTSurface {
+      header: DXTSurfaceHeader {
+        width: 4,
+        height: 4,
+        size: 4,
+      },
+      data: vec![0; 4],
+    }],
+  };
+  let soup = xbox_soup(
+    Toc::new(vec![section]),
+    (&[16], &[]),
+    &[(0, 0, dxt1_header(8, 8)), (0, 1, dxt1_header(8, 8))],
+    &[(0, 2, static_texture)],
+  );
+
+  let path = temp_file("validate.str", &[0; 40]);
+  let report = soup.validate(&Str::read(&path).unwrap());
+  std::fs::remove_file(path).unwrap();
+
+  assert_eq!(report.sections_checked, 1);
+  assert_eq!(report.components_checked, 5);
+  assert_eq!(
+    report.issues,
+    [
+      ValidationIssue::StreamingDataSizeMismatch {
+        section_id: 0,
+        path: "short.tex".to_string(),
+        kind: Texture,
+        expected: 32,
+        actual: 8,
+      },
+      ValidationIssue::StaticDataSizeMismatch {
+        section_id: 0,
+        path: "static.tex".to_string(),
+        expected: 8,
+        actual: 4,
+      },
+      ValidationIssue::ComponentOutOfBounds {
+        section_id: 0,
+        path: "past_end.dat".to_string(),
+        offset: 40,
+        size: 100,
+        block_size: 40,
+      },
+      ValidationIssue::MissingSoiRecord {
+        section_id: 0,
+        path: "unknown.tex".to_string(),
+        kind: Texture,
+      },
+    ]
+  );
+
+  // the page checks are opt-in
+  assert_eq!(
+    soup.validate_pages(),
+    [
+      ValidationIssue::PageTooSmall {
+        section_id: 0,
+        block: Block::Uncached,
+        page: 0,
+        page_size: 16,
+        block_size: 40,
+      },
+      ValidationIssue::PageOutOfRange {
+        section_id: 0,
+        block: Block::Cached,
+        page: 3,
+        page_count: 0,
+      },
+    ]
+  );
+}
+
+#[test]
+fn gcg_streaming_data_size() {
+  let mut gcg = Vec::new();
+  gcg.extend(b"ggs\0");
+  // version, no bones or mesh names, one lod without weights
+  gcg.extend([0; 12]);
+  gcg.extend([1, 0, 0, 0]);
+  gcg.extend(1.0f32.to_be_bytes());
+  gcg.extend(2u32.to_be_bytes());
+  for _ in 0..2 {
+    gcg.extend(0u32.to_be_bytes());
+    // no normals, colors or uvs, a single f32 position
+    gcg.extend([0x1, 1, 4, 0, 1, 4, 0]);
+    gcg.extend(1u16.to_be_bytes());
+    gcg.push(12);
+    gcg.extend(0u16.to_be_bytes());
+    gcg.push(0);
+    gcg.extend(8u32.to_be_bytes());
+  }
+
+  let header = GCGHeader::read(&mut Cursor::new(gcg)).unwrap();
+  // 12 bytes of vertices padded to 32, 8 of faces, then the second mesh continues from 40
+  assert_eq!(header.streaming_data_size(), 72);
+}
+
+#[test]
+fn negative_sizes() {
+  let data = [0; 16];
+  let negative_offset = component("a.dat", 1, 0, UserData, -4, 8);
+  assert!(matches!(
+    component_slice(&negative_offset, &data),
+    Err(crate::Error::InvalidField {
+      field: "component offset",
+      value: -4
+    })
+  ));
+  let negative_size = component("a.dat", 1, 0, UserData, 4, -8);
+  assert!(matches!(
+    component_slice(&negative_size, &data),
+    Err(crate::Error::InvalidField {
+      field: "component size",
+      value: -8
+    })
+  ));
+
+  let mut section = empty_section(
+    "level",
+    Bounding::new(Vector3::default(), Vector3::default()),
+  );
+  section.header.cached_data_size = -1;
+  let path = temp_file("negative.str", &data);
+  let result = Str::read(&path).unwrap().read_section_blocks_at(&section);
+  std::fs::remove_file(path).unwrap();
+  assert!(matches!(
+    result,
+    Err(crate::Error::InvalidField {
+      field: "section data size",
+      value: -1
+    })
+  ));
+}
+
+#[test]
+fn soi_index_lookups() {
+  let bounding = Bounding::new(Vector3::default(), Vector3::default());
+  let mut first = empty_section("first", bounding);
+  first.uncached_components = vec![component("sky.tex", 7, 0, Texture, 0, 0)];
+  // the sky again, without a record of its own
+  let mut second = empty_section("second", bounding);
+  second.cached_components = vec![
+    component("road.tex", 8, 0, Texture, 0, 0),
+    component("sky.tex", 7, 1, Texture, 0, 0),
+  ];
+
+  let soup = xbox_soup(
+    Toc::new(vec![first, second]),
+    (&[], &[]),
+    &[
+      (0, 0, dxt1_header(8, 8)),
+      (0, 0, dxt1_header(16, 16)),
+      (1, 0, dxt1_header(4, 4)),
+    ],
+    &[],
+  );
+
+  // the first of two records with the same ids wins
+  let sky = soup.soi().find_streaming_texture(0, 0).unwrap();
+  assert_eq!(sky.header.width, 8);
+  assert_eq!(
+    soup
+      .soi()
+      .find_streaming_texture(1, 0)
+      .unwrap()
+      .header
+      .width,
+    4
+  );
+  assert!(soup.soi().find_streaming_texture(1, 1).is_none());
+  assert!(soup.soi().find_static_texture(0, 0).is_none());
+
+  assert_eq!(soup.toc().find_all_ids(7), [(0, 0), (1, 1)]);
+  assert_eq!(soup.toc().find_ids(8), Some((1, 0)));
+  assert_eq!(soup.toc().find_all_ids(9), []);
+
+  // falls back to the record under the instance's other section
+  let fallback = soup.find_streaming_texture(1, 1, 7).unwrap();
+  assert!(std::ptr::eq(fallback, sky));
+  assert!(soup.find_streaming_texture(1, 1, 9).is_none());
+}
+
+#[test]
+fn resolve_components() {
+  let mut section = empty_section(
+    "level",
+    Bounding::new(Vector3::default(), Vector3::default()),
+  );
+  section.uncached_components = vec![
+    component("streamed.tex", 1, 0, Texture, 0, 0),
+    component("static.tex", 2, 1, Texture, 0, 0),
+    component("unknown.tex", 3, 2, Texture, 0, 0),
+    component("car.mdl", 4, 3, RenderableModel, 0, 0),
+    component("notes.dat", 5, 4, UserData, 0, 0),
+    component("grid.dat", 6, 5, CollisionGrid, 0, 0),
+  ];
+
+  let static_texture = DXTStaticTextureHeader {
+    version: 2,
+    format: DXTFormat::Dxt1,
+    palette_size: 0,
+    palette: Vec::new(),
+    mip_count: 0,
+    width: 4,
+    height: 4,
+    mips: Vec::new(),
+  };
+  let soup = xbox_soup(
+    Toc::new(vec![section]),
+    (&[], &[]),
+    &[(0, 0, dxt1_header(8, 8))],
+    &[(0, 1, static_texture)],
+  );
+
+  let resolved: Vec<_> = soup.resolve_all().collect();
+  assert_eq!(resolved.len(), 6);
+  assert!(matches!(
+    resolved[0].2,
+    ResolvedComponent::StreamingTexture(texture) if texture.header.width == 8
+  ));
+  assert!(matches!(
+    resolved[1].2,
+    ResolvedComponent::StaticTexture(texture) if texture.static_texture_header.width == 4
+  ));
+  assert!(matches!(
+    resolved[2].2,
+    ResolvedComponent::Missing {
+      reason: "no streaming or static texture record"
+    }
+  ));
+  assert!(matches!(
+    resolved[3].2,
+    ResolvedComponent::Missing {
+      reason: "no renderable model record"
+    }
+  ));
+  assert!(matches!(resolved[4].2, ResolvedComponent::UserData));
+  assert!(matches!(resolved[5].2, ResolvedComponent::CollisionGrid));
+
+  let (section_id, component, _) = &resolved[1];
+  assert_eq!(
+    (*section_id, component.path()),
+    (0, "static.tex".to_string())
+  );
+}
+
+#[test]
+fn vfs_open() {
+  let bounding = Bounding::new(Vector3::default(), Vector3::default());
+  let sky: Vec<u8> = (0..32).collect();
+  let road = [7; 8];
+
+  // the shared sky is in the first of two chunks, the second one is corrupt so inflating the
+  // whole block fails
+  let sky_chunk = zlib(&sky);
+  let broken_chunk = [0xff; 8];
+  let mut shared = empty_section("shared", bounding);
+  shared.cached_components = vec![
+    component("Textures/Sky", 1, 0, Texture, 0, 32),
+    component("textures/moon", 2, 1, Texture, 32, 8),
+  ];
+  shared.header.cached_data_size = 40;
+  shared.header.zlib_header = Some(ZlibHeader {
+    cached_sizes: vec![sky_chunk.len() as i32, broken_chunk.len() as i32],
+    ..Default::default()
+  });
+
+  let mut level = empty_section("level", bounding);
+  level.uncached_components = vec![
+    component("textures/road", 3, 0, Texture, 0, 8),
+    component("textures/sky", 1, 1, Texture, 0, 0),
+  ];
+  level.header.shared_section_offset = 0;
+  level.header.memory_entry.offset = (sky_chunk.len() + broken_chunk.len()) as i32;
+  level.header.uncached_data_size = 8;
+
+  let soup = xbox_soup(
+    Toc::new(vec![shared, level]),
+    (&[], &[]),
+    &[
+      (0, 0, dxt1_header(8, 8)),
+      (0, 1, dxt1_header(4, 4)),
+      (1, 0, dxt1_header(4, 4)),
+    ],
+    &[],
+  );
+  let path = temp_file("vfs.str", &[&sky_chunk[..], &broken_chunk, &road].concat());
+  let str = Str::read(&path).unwrap();
+  let vfs = Vfs::new(&soup);
+
+  let textures = vfs.read_dir("TEXTURES").unwrap();
+  assert_eq!(textures.name(), "Textures");
+  let names: Vec<_> = textures.files().map(|file| file.name()).collect();
+  assert_eq!(names, ["moon.dxt", "road.dxt", "Sky.dxt"]);
+
+  let file = vfs.file("textures\\sky.dxt").unwrap();
+  assert_eq!(file.occurrences().len(), 2);
+  let convert = |section_id: u32, component: &ComponentHeader, data: &[u8]| {
+    let mut output = Cursor::new(Vec::new());
+    soup
+      .resolve(section_id, component)
+      .write(&mut output, data)
+      .unwrap();
+    output.into_inner()
+  };
+  let sky_component = &soup.toc().sections[0].cached_components[0];
+  for occurrence in file.occurrences() {
+    let opened = vfs.open_occurrence(&str, occurrence).unwrap();
+    assert_eq!(opened.into_inner(), convert(0, sky_component, &sky));
+  }
+  let road_component = &soup.toc().sections[1].uncached_components[0];
+  assert_eq!(
+    vfs.open(&str, "textures/road.dxt").unwrap().into_inner(),
+    convert(1, road_component, &road)
+  );
+
+  assert!(str.read_section_blocks_at(&soup.toc().sections[0]).is_err());
+  let shared = &soup.toc().sections[0];
+  assert_eq!(&str.read_component_at(shared, 0).unwrap()[..], &sky[..]);
+  assert!(matches!(
+    str.read_component_at(shared, 2),
+    Err(crate::Error::OutOfBounds { .. })
+  ));
+  assert!(vfs.open(&str, "textures/moon.dxt").is_err());
+  assert!(matches!(
+    vfs.open(&str, "textures/sun.dxt"),
+    Err(crate::Error::FileNotFound { .. })
+  ));
+
+  drop(str);
+  std::fs::remove_file(path).unwrap();
+}
+
+// two sections holding "first" and "second"
+fn two_section_level() -> (Toc, PathBuf) {
+  let bounding = Bounding::new(Vector3::default(), Vector3::default());
+  let mut sections = Vec::new();
+  for (id, (name, offset)) in [("first", 0), ("second", 5)].into_iter().enumerate() {
+    let mut section = empty_section(name, bounding);
+    section.uncached_components = vec![
+      component(&format!("{}/a.dat", name), id as i32 * 2, 0, UserData, 0, 2),
+      component(
+        &format!("{}/b.dat", name),
+        id as i32 * 2 + 1,
+        1,
+        UserData,
+        2,
+        3,
+      ),
+    ];
+    section.header.memory_entry.offset = offset;
+    section.header.uncached_data_size = name.len() as i32;
+    sections.push(section);
+  }
+
+  (
+    Toc::new(sections),
+    temp_file("sections.str", b"firstsecond"),
+  )
+}
+
+#[test]
+fn shared_bytes_and_sections() {
+  let bytes = SharedBytes::from(b"uncached".to_vec());
+  let cached = bytes.slice(2..8).unwrap();
+  assert_eq!(&cached[..], b"cached");
+  // relative to the narrowed view
+  assert_eq!(&cached.slice(1..3).unwrap()[..], b"ac");
+  assert!(cached.slice(4..7).is_none());
+
+  let (toc, path) = two_section_level();
+  let str = Str::read(&path).unwrap();
+  let mut sections = str.sections(&toc);
+  assert_eq!(sections.size_hint(), (2, Some(2)));
+
+  let (id, first) = sections.next().unwrap().unwrap();
+  assert_eq!(id, 0);
+  assert_eq!(&first.uncached[0].data[..], b"fi");
+  assert_eq!(&first.uncached[1].data[..], b"rst");
+  // components are views into one buffer
+  assert!(std::ptr::eq(
+    first.uncached[0].data.as_ptr().wrapping_add(2),
+    first.uncached[1].data.as_ptr()
+  ));
+  assert_eq!(sections.size_hint(), (1, Some(1)));
+
+  let (id, second) = sections.next().unwrap().unwrap();
+  assert_eq!((id, &second.uncached[1].data[..]), (1, &b"con"[..]));
+  assert!(sections.next().is_none());
+
+  drop(str);
+  std::fs::remove_file(path).unwrap();
+}
+
+// records every callback and stops once `stop_after` components were seen
+#[derive(Default)]
+struct RecordingProgress {
+  stop_after: Option<usize>,
+  calls: Mutex<Vec<String>>,
+}
+
+impl Progress for RecordingProgress {
+  fn section(&self, section_id: u32, name: &str, bytes: usize) -> ControlFlow<()> {
+    let mut calls = self.calls.lock().unwrap();
+    calls.push(format!("section {} {} {}", section_id, name, bytes));
+    ControlFlow::Continue(())
+  }
+
+  fn component(&self, _section_id: u32, path: &str, bytes: usize) -> ControlFlow<()> {
+    let mut calls = self.calls.lock().unwrap();
+    calls.push(format!("{} {}", path, bytes));
+    let components = calls
+      .iter()
+      .filter(|call| !call.starts_with("section"))
+      .count();
+    match self.stop_after {
+      Some(stop_after) if components >= stop_after => ControlFlow::Break(()),
+      _ => ControlFlow::Continue(()),
+    }
+  }
+}
+
+#[test]
+fn progress_and_cancellation() {
+  let (toc, path) = two_section_level();
+  let str = Str::read(&path).unwrap();
+
+  let progress = RecordingProgress::default();
+  let read = str.sections(&toc).with_progress(&progress).count();
+  assert_eq!(read, 2);
+  assert_eq!(
+    *progress.calls.lock().unwrap(),
+    [
+      "section 0 first 5",
+      "first/a.dat 2",
+      "first/b.dat 3",
+      "section 1 second 6",
+      "second/a.dat 2",
+      "second/b.dat 3",
+    ]
+  );
+
+  // stopping in the first section ends the iteration
+  let progress = RecordingProgress {
+    stop_after: Some(1),
+    ..Default::default()
+  };
+  let mut sections = str.sections(&toc).with_progress(&progress);
+  assert!(matches!(
+    sections.next(),
+    Some(Err(crate::Error::Cancelled))
+  ));
+  assert!(sections.next().is_none());
+  assert_eq!(progress.calls.lock().unwrap().len(), 2);
+
+  // stopping at a section skips its read, so the missing data doesn't matter
+  struct StopAtSection;
+  impl Progress for StopAtSection {
+    fn section(&self, _section_id: u32, _name: &str, _bytes: usize) -> ControlFlow<()> {
+      ControlFlow::Break(())
+    }
+  }
+  let empty = temp_file("empty.str", &[]);
+  let empty_str = Str::read(&empty).unwrap();
+  assert!(matches!(
+    empty_str
+      .sections(&toc)
+      .with_progress(&StopAtSection)
+      .next(),
+    Some(Err(crate::Error::Cancelled))
+  ));
+  drop(empty_str);
+  std::fs::remove_file(empty).unwrap();
+
+  drop(str);
+  std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn section_graph() {
+  let bounding = Bounding::new(Vector3::default(), Vector3::default());
+  let mut sections: Vec<Section> = ["a", "b", "c", "d"]
+    .into_iter()
+    .map(|name| empty_section(name, bounding))
+    .collect();
+  // a -> b twice, b -> c, c -> 9 and d -> 9 are broken
+  sections[0].header.link_table[..2].copy_from_slice(&[1, 1]);
+  sections[1].header.link_table[3] = 2;
+  sections[2].header.link_table[0] = 9;
+  sections[3].header.link_table[5] = 9;
+
+  assert_eq!(sections[1].header.links().collect::<Vec<_>>(), [(3, 2)]);
+
+  let graph = SectionGraph::new(&Toc::new(sections));
+  assert_eq!(graph.neighbors(0), [1]);
+  assert_eq!(graph.incoming(2), [1]);
+  assert_eq!(graph.orphans(), [3]);
+  assert_eq!(graph.connected_components(), [vec![0, 1, 2], vec![3]]);
+
+  let broken: Vec<_> = graph
+    .broken_links()
+    .iter()
+    .map(|link| (link.section_id, link.slot, link.target))
+    .collect();
+  assert_eq!(broken, [(2, 0, 9), (3, 5, 9)]);
+
+  let dot = graph.to_dot();
+  assert_eq!(dot.matches("missing9 [label").count(), 1);
+  assert!(dot.contains("s2 -> missing9"));
+  assert!(dot.contains("s3 -> missing9"));
+}
+
+#[test]
+fn glob_paths() {
+  assert!(utils::glob_match(
+    "textures/cars/**",
+    "Textures\\Cars\\red/body.dds"
+  ));
+  assert!(utils::glob_match(
+    "textures/**/body.dds",
+    "textures/body.dds"
+  ));
+  assert!(utils::glob_match(
+    "textures/*/body.?ds",
+    "textures/cars/body.dds"
+  ));
+  assert!(!utils::glob_match("textures/*", "textures/cars/body.dds"));
+  assert!(!utils::glob_match(
+    "textures/cars/**",
+    "textures/bikes/body.dds"
+  ));
+  assert!(utils::glob_match("**/*.dds", "a/b/c/wheel.dds"));
+  assert!(utils::glob_match("a/**/c/*.dds", "a/b/c/x/c/wheel.dds"));
+  assert!(!utils::glob_match("a/**/c/*.dds", "a/b/c/x/wheel.dds"));
+  assert!(!utils::glob_match("a/**/b", "a/xb"));
+  assert!(utils::glob_match("*x*y", "axxbxy"));
+
+  // would take exponential time with a recursive matcher
+  let path = format!("{}b", "a".repeat(64));
+  assert!(!utils::glob_match(&"*a".repeat(16), &path));
+  assert!(!utils::glob_match(&"**a".repeat(16), &path));
+  assert!(utils::glob_match(&format!("{}b", "**a".repeat(16)), &path));
+}
+
+#[test]
+fn find_by_path() {
+  let bounding = Bounding::new(Vector3::default(), Vector3::default());
+  let mut first = empty_section("first", bounding);
+  first.uncached_components = vec![component("Textures\\Sky.tex", 1, 0, Texture, 0, 0)];
+  first.cached_components = vec![component("textures/road.tex", 2, 1, Texture, 0, 0)];
+  let mut second = empty_section("second", bounding);
+  second.cached_components = vec![component("textures/sky.tex", 1, 0, Texture, 0, 0)];
+  let mut toc = Toc::new(vec![first, second]);
+
+  let ids = |toc: &Toc, path| -> Vec<(u32, i32)> {
+    toc
+      .find_by_path(path)
+      .into_iter()
+      .map(|(section_id, _, component)| (section_id, component.instance_id))
+      .collect()
+  };
+  assert_eq!(ids(&toc, "TEXTURES/sky.tex"), [(0, 1), (1, 1)]);
+  assert_eq!(ids(&toc, "textures\\road.tex"), [(0, 2)]);
+  assert_eq!(ids(&toc, "textures/grass.tex"), []);
+  assert!(toc.sections[0].component(2).is_none());
+
+  // a stale index leaves out what's no longer there instead of panicking
+  toc.sections.pop();
+  toc.sections[0].cached_components[0] = component("textures/grass.tex", 3, 1, Texture, 0, 0);
+  assert_eq!(ids(&toc, "textures/sky.tex"), [(0, 1)]);
+  assert_eq!(ids(&toc, "textures/road.tex"), []);
+}
+
+#[test]
+#[allow(deprecated)]
+fn decode_consecutive_zlib_chunks() {
+  let first = zlib(b"first chunk");
+  let second = zlib(b"second chunk");
+  let path = temp_file("consecutive_chunks.str", &[&first[..], &second].concat());
+  let mut str = Str::read(&path).unwrap();
+
+  let sizes = [first.len() as i32, second.len() as i32];
+  assert_eq!(
+    str.decode_zlib_data(11, &sizes[..1]).unwrap(),
+    b"first chunk"
+  );
+  assert_eq!(
+    str.decode_zlib_data(12, &sizes[1..]).unwrap(),
+    b"second chunk"
+  );
+  assert!(str.decode_zlib_data(0, &[-1]).is_err());
+
+  drop(str);
+  std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn read_mixed_compression_blocks() {
+  let uncached = b"uncached";
+  let cached = b"cached block, stored as a zlib chunk";
+
+  let compressed = zlib(cached);
+  let path = temp_file(
+    "mixed_compression.str",
+    &[&b"padding"[..], uncached, &compressed].concat(),
+  );
+
+  let section = Section {
+    header: SectionHeader {
+      name: [0; 260],
+      total_component_count: 0,
+      uncached_component_count: 0,
+      cached_component_count: 0,
+      shared_section_offset: -1,
+      uncached_page_offset: -1,
+      cached_page_offset: -1,
+      link_table: [-1; 8],
+      bounding: Bounding::new(Vector3::default(), Vector3::default()),
+      memory_entry: MemoryEntry { offset: 7, size: 0 },
+      uncached_data_size: uncached.len() as i32,
+      cached_data_size: cached.len() as i32,
+      zlib_header: Some(ZlibHeader {
+        cached_sizes: vec![compressed.len() as i32],
+        ..Default::default()
+      }),
+    },
+    uncached_components: Vec::new(),
+    cached_components: Vec::new(),
+  };
+
+  let str = Str::read(&path).unwrap();
+  let (uncached_data, cached_data) = str.read_section_blocks_at(&section).unwrap();
+  assert_eq!(&uncached_data[..], uncached);
+  assert_eq!(&cached_data[..], cached);
+
+  std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn decode_dxt_textures() {
+  // red/blue endpoints, the second pixel uses color1 and the rest color0
+  let block = [0x00, 0xf8, 0x1f, 0x00, 0b0100, 0, 0, 0];
+  let image = DXTFormat::Dxt1.decode_mip(&block, 4, 4, &[]).unwrap();
+  assert_eq!(image.pixel(0, 0), [255, 0, 0, 255]);
+  assert_eq!(image.pixel(1, 0), [0, 0, 255, 255]);
+  assert_eq!(image.pixel(3, 3), [255, 0, 0, 255]);
+
+  // half transparent light grey, stored premultiplied as a darker grey
+  let mut block = [0x88; 16];
+  block[8..12].copy_from_slice(&[0xef, 0x7b, 0xef, 0x7b]);
+  block[12..].fill(0);
+  let image = DXTFormat::Dxt2.decode_mip(&block, 4, 4, &[]).unwrap();
+  assert_eq!(image.pixel(2, 1), [231, 234, 231, 136]);
+
+  // interpolated alpha like Dxt5, so 16 bytes per block
+  assert_eq!(DXTFormat::Dxt4.mip_size(8, 8), 64);
+  assert_eq!(
+    DXTFormat::Dxt4Mm.mip_size(8, 8),
+    DXTFormat::Dxt5.mip_size(8, 8)
+  );
+  let mut block = [0u8; 16];
+  block[..2].copy_from_slice(&[0x88, 0x88]);
+  block[8..12].copy_from_slice(&[0xef, 0x7b, 0xef, 0x7b]);
+  let image = DXTFormat::Dxt4.decode_mip(&block, 4, 4, &[]).unwrap();
+  assert_eq!(image.pixel(2, 1), [231, 234, 231, 136]);
+  assert!(DXTFormat::Dxt4.decode_mip(&block[..8], 4, 4, &[]).is_err());
+
+  // swizzled 4x4, x bits take the even positions
+  let data: Vec<u8> = (0..16).collect();
+  let image = DXTFormat::Lum8.decode_mip(&data, 4, 4, &[]).unwrap();
+  assert_eq!(image.pixel(1, 0)[0], 1);
+  assert_eq!(image.pixel(0, 1)[0], 2);
+  assert_eq!(image.pixel(2, 0)[0], 4);
+  assert_eq!(image.pixel(3, 3)[0], 15);
+}
+
+#[test]
+fn decode_gct_textures() {
+  // one 8x8 tile, only the bottom right 4x4 block uses color1
+  let mut tile = [0u8; 32];
+  for block in tile.chunks_exact_mut(8) {
+    block[..4].copy_from_slice(&[0xf8, 0x00, 0x00, 0x1f]);
+  }
+  tile[24 + 4..].fill(0x55);
+  let image = GCTFormat::Cmpr
+    .decode_mip(&tile, 8, 8, &[], GXPaletteFormat::RGB565)
+    .unwrap();
+  assert_eq!(image.pixel(3, 3), [255, 0, 0, 255]);
+  assert_eq!(image.pixel(4, 4), [0, 0, 255, 255]);
+
+  // 8x4 tile, index 1 is a half transparent RGB5A3 entry
+  let palette = [0x80, 0x00, 0x4f, 0x00];
+  let mut tile = [0u8; 32];
+  tile[9] = 1;
+  let image = GCTFormat::Ci8
+    .decode_mip(&tile, 8, 4, &palette, GXPaletteFormat::RGB5A3)
+    .unwrap();
+  assert_eq!(image.pixel(0, 0), [0, 0, 0, 255]);
+  assert_eq!(image.pixel(1, 1), [255, 0, 0, 146]);
+}
+
+#[test]
+fn decode_dds_to_png() {
+  let header = DdsHeader {
+    size: 124,
+    flags: 0x100f,
+    height: 2,
+    width: 2,
+    pixel_format: DdsPixelFormat {
+      size: 32,
+      flags: DDPF_LUMINANCE,
+      rgb_bit_count: 8,
+      r_bit_mask: 0xff,
+      ..Default::default()
+    },
+    ..Default::default()
+  };
+  let mut file = std::io::Cursor::new(Vec::new());
+  header.write(&mut file).unwrap();
+  file.write_all(&[0, 64, 128, 255]).unwrap();
+
+  let texture = X360StaticTextureHeader {
+    dds_size: file.get_ref().len() as u32,
+    header_file: file.into_inner(),
+  };
+  let mips = texture.decode().unwrap();
+  assert_eq!(mips.len(), 1);
+  assert_eq!(mips[0].pixel(1, 1), [255, 255, 255, 255]);
+  assert_eq!(mips[0].pixel(1, 0), [64, 64, 64, 255]);
+
+  let mut png = Vec::new();
+  mips[0].write_png(&mut png).unwrap();
+  assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+  assert_eq!(&png[12..16], b"IHDR");
+  assert_eq!(&png[16..24], [0, 0, 0, 2, 0, 0, 0, 2]);
+  assert_eq!(&png[24..29], [8, 6, 0, 0, 0]);
+  assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+
+  // one IDAT chunk after the 25 byte IHDR, every row prefixed with filter type 0
+  let idat_size = u32::from_be_bytes(png[33..37].try_into().unwrap()) as usize;
+  assert_eq!(&png[37..41], b"IDAT");
+  let mut rows = Vec::new();
+  flate2::read::ZlibDecoder::new(&png[41..41 + idat_size])
+    .read_to_end(&mut rows)
+    .unwrap();
+  let pixels: Vec<u8> = mips[0]
+    .data
+    .chunks_exact(8)
+    .flat_map(|row| [&[0][..], row].concat())
+    .collect();
+  assert_eq!(rows, pixels);
+}
+
+#[test]
+fn write_dxt_dds() {
+  let data: Vec<u8> = (0..16).chain(0..4).collect();
+  let texture = DXTTextureHeader {
+    format: DXTFormat::Lum8,
+    palette_size: 0,
+    palette: Vec::new(),
+    mip_count: 2,
+    width: 4,
+    height: 4,
+  };
+
+  let mut file = std::io::Cursor::new(Vec::new());
+  texture.write_dds(&mut file, &data).unwrap();
+  file.set_position(0);
+
+  let header = DdsHeader::read(&mut file).unwrap();
+  assert_eq!(header.mip_count(), 2);
+  let position = file.position() as usize;
+  assert_eq!(
+    header.decode(&file.get_ref()[position..]).unwrap(),
+    texture.decode(&data).unwrap()
+  );
+}
+
+#[test]
+fn import_dxt_dds() {
+  let mut palette = vec![0; 8];
+  palette[4..].copy_from_slice(&[0x10, 0x20, 0x30, 0x80]);
+  let texture = DXTTextureHeader {
+    format: DXTFormat::Pal8Mm,
+    palette_size: 4,
+    palette,
+    mip_count: 3,
+    width: 4,
+    height: 2,
+  };
+  let data: Vec<u8> = [0, 1, 1, 0, 1, 0, 0, 1, 1, 0, 1].to_vec();
+
+  let mut file = std::io::Cursor::new(Vec::new());
+  texture.write_dds(&mut file, &data).unwrap();
+
+  file.set_position(0);
+  let (imported, imported_data) = DXTTextureHeader::from_dds(&mut file).unwrap();
+  assert_eq!(imported.format, DXTFormat::Pal8Mm);
+  assert_eq!(imported_data, data);
+  assert_eq!(
+    imported.decode(&imported_data).unwrap(),
+    texture.decode(&data).unwrap()
+  );
+
+  file.set_position(0);
+  let imported = DXTStaticTextureHeader::from_dds(&mut file).unwrap();
+  assert_eq!(imported.mips[0].header.width, 1);
+  assert_eq!(imported.decode().unwrap(), texture.decode(&data).unwrap());
+
+  let header = DdsHeader::new(3, 4, 1, DXTFormat::Dxt1.dds_pixel_format(), 16);
+  let mut file = std::io::Cursor::new(Vec::new());
+  header.write(&mut file).unwrap();
+  file.write_all(&[0; 16]).unwrap();
+  file.set_position(0);
+  assert!(matches!(
+    DXTTextureHeader::from_dds(&mut file),
+    Err(crate::Error::InvalidTextureDimensions { width: 3, .. })
+  ));
+}
+
+#[test]
+fn encode_textures() {
+  // 8x8 with a 565 exact color per quadrant and a transparent corner
+  let mut image = RgbaImage::new(8, 8);
+  for y in 0..8 {
+    for x in 0..8 {
+      let pixel = match (x < 4, y < 4) {
+        (true, true) => [255, 0, 0, 255],
+        (false, true) => [0, 255, 0, 255],
+        (true, false) => [0, 0, 255, 255],
+        (false, false) => [0, 0, 0, 0],
+      };
+      image.set_pixel(x, y, pixel);
+    }
+  }
+  let mips = image.mip_chain(MipFilter::Box);
+  assert_eq!(mips.len(), 4);
+  assert_eq!(mips[3].width, 1);
+  assert_eq!(mips[1].pixel(0, 0), [255, 0, 0, 255]);
+
+  for format in [DXTFormat::Dxt1Mm, DXTFormat::Dxt5Mm, DXTFormat::Pal8Mm] {
+    let (header, data) = DXTTextureHeader::encode(format, &mips, EncodeQuality::High).unwrap();
+    assert_eq!(data.len(), header.image_size().unwrap());
+    assert_eq!(header.decode(&data).unwrap()[0], image);
+  }
+
+  for format in [GCTFormat::Rgba8, GCTFormat::CmprMm, GCTFormat::Ci8Mm] {
+    let (header, data) = GCNTextureHeader::encode(
+      format,
+      &mips,
+      EncodeQuality::Normal,
+      GXPaletteFormat::RGB5A3,
+    )
+    .unwrap();
+    assert_eq!(
+      header.decode(&data, GXPaletteFormat::RGB5A3).unwrap()[0],
+      image
+    );
+
+    // extraction has no way to know a Ci8 palette's format
+    let decoded = TextureConvert::decode_rgba(&header, &data);
+    if header.format == GCTFormat::Ci8Mm {
+      assert!(matches!(
+        decoded,
+        Err(crate::Error::UnsupportedTextureFormat { .. })
+      ));
+
+      // and reports writing the native texture instead of a PNG
+      let texture = StreamingTexture {
+        model_info: ModelInfo::read_le(&mut Cursor::new(model_info(0, 0))).unwrap(),
+        version: 0,
+        header: header.clone(),
+      };
+      let resolved =
+        ResolvedComponent::<_, GCNStaticTextureHeader, GCGHeader>::StreamingTexture(&texture);
+      let options = ExtractOptions::new().texture_output(TextureOutput::Png);
+      let converted =
+        crate::extract::convert_component(&resolved, "ci8", "gct", &data, &options).unwrap();
+      assert_eq!(converted.files.len(), 1);
+      assert_eq!(converted.files[0].0, "ci8.gct");
+      assert_eq!(
+        converted.fallback.as_deref(),
+        Some("unsupported texture format Ci8Mm with an unknown palette format")
+      );
+    } else {
+      assert_eq!(decoded.unwrap()[0], image);
+    }
+  }
+
+  let blurred = image.downsample(MipFilter::Kaiser);
+  assert_eq!(blurred.width, 4);
+  assert_eq!(blurred.pixel(3, 3)[3], 0);
+}
+
+#[test]
+fn decode_x360_textures() {
+  let fetch = GpuTextureFetch::new()
+    .with_tiled(true)
+    .with_pitch(1)
+    .with_format(XenosFormat::K8888 as u8)
+    .with_width(31)
+    .with_height(31)
+    // X, Y, Z and W to red, green, blue and alpha
+    .with_swizzle(0x688);
+  assert_eq!(
+    fetch.to_dwords(),
+    [0x8040_0000, 6, 0x0003_e01f, 0xd10, 0, 0]
+  );
+  assert_eq!(GpuTextureFetch::from_dwords(fetch.to_dwords()), fetch);
+  // bits past a field's width are dropped instead of spilling into the next one
+  let spilled = fetch.with_format(0x7f);
+  assert_eq!((spilled.format(), spilled.endianness()), (0x3f, 0));
+  let texture = X360TextureHeader {
+    common: 3,
+    reference_count: 1,
+    fence: 0,
+    read_fence: 0,
+    identifier: 0,
+    base_flush: 0xffff0000,
+    mip_flush: 0xffff0000,
+    fetch,
+  };
+
+  let mut file = std::io::Cursor::new(Vec::new());
+  texture.write_be(&mut file).unwrap();
+  assert_eq!(file.get_ref().len(), 52);
+  file.set_position(0);
+  let read = X360TextureHeader::read_be(&mut file).unwrap();
+  assert_eq!(read.fetch, fetch);
+  assert_eq!((read.width(), read.height(), read.mip_count()), (32, 32, 1));
+  assert_eq!(texture.streaming_data_size(), Some(4096));
+  let unknown = X360TextureHeader {
+    fetch: fetch.with_format(0x3f),
+    ..read
+  };
+  assert_eq!(unknown.streaming_data_size(), None);
+  assert!(matches!(
+    unknown.image_size(),
+    Err(crate::Error::UnsupportedTextureFormat { .. })
+  ));
+
+  // every texel of a tiled 32x32 tile lands somewhere in the untiled image exactly once
+  let data: Vec<u8> = (0..1024u32).flat_map(|i| i.to_le_bytes()).collect();
+  let image = texture.decode(&data).unwrap().remove(0);
+  let mut texels: Vec<u32> = image.pixels().map(u32::from_le_bytes).collect();
+  assert_eq!(texels[0], 0);
+  texels.sort_unstable();
+  assert!(texels.into_iter().eq(0..1024));
+
+  assert!(matches!(
+    texture.decode(&data[..1000]),
+    Err(crate::Error::OutOfBounds { .. })
+  ));
+}
+
+// 360 DXT1 texture whose blocks hold their own index, stored with 16 bit swaps
+fn x360_dxt1(fetch: GpuTextureFetch, blocks: u64) -> (X360TextureHeader, Vec<u8>) {
+  let texture = X360TextureHeader {
+    common: 3,
+    reference_count: 1,
+    fence: 0,
+    read_fence: 0,
+    identifier: 0,
+    base_flush: 0xffff0000,
+    mip_flush: 0xffff0000,
+    fetch: fetch
+      .with_tiled(true)
+      .with_format(XenosFormat::Dxt1 as u8)
+      .with_endianness(1)
+      .with_base_address(0x100),
+  };
+  let mut data: Vec<u8> = (0..blocks).flat_map(|i| i.to_le_bytes()).collect();
+  data.chunks_exact_mut(2).for_each(|chunk| chunk.swap(0, 1));
+  (texture, data)
+}
+
+// the block indices of each level of the untiled DDS, in rows
+fn x360_dds_blocks(texture: &X360TextureHeader, data: &[u8]) -> Vec<Vec<Vec<u64>>> {
+  let mut dds = Cursor::new(Vec::new());
+  texture.write_dds(&mut dds, data).unwrap();
+  let dds = dds.into_inner();
+
+  let mips: Vec<_> = texture.mips().unwrap().collect();
+  let blocks: usize = mips
+    .iter()
+    .map(|mip| mip.width.div_ceil(4) * mip.height.div_ceil(4))
+    .sum();
+  let mut blocks = dds[dds.len() - blocks * 8..]
+    .chunks_exact(8)
+    .map(|block| u64::from_le_bytes(block.try_into().unwrap()));
+
+  mips
+    .iter()
+    .map(|mip| {
+      (0..mip.height.div_ceil(4))
+        .map(|_| blocks.by_ref().take(mip.width.div_ceil(4)).collect())
+        .collect()
+    })
+    .collect()
+}
+
+// The expected block orders follow xenia's TiledOffset2DOuter/Inner and GetPackedMipOffset.
+#[test]
+fn untile_x360_dxt1_mips() {
+  let fetch = GpuTextureFetch::new()
+    .with_pitch(2)
+    .with_width(63)
+    .with_height(63)
+    .with_max_mip_level(3)
+    .with_mip_address(0x102);
+  let (texture, data) = x360_dxt1(fetch, 4096);
+
+  let ranges: Vec<_> = texture.mips().unwrap().map(|mip| mip.range).collect();
+  assert_eq!(ranges, [0..8192, 8192..16384, 16384..24576, 24576..32768]);
+
+  let levels = x360_dds_blocks(&texture, &data);
+  // blocks are stored in 2x2 groups, interleaved across the 32x32 block tile
+  assert_eq!(
+    levels[0][..2],
+    [
+      [0, 1, 4, 5, 32, 33, 36, 37, 8, 9, 12, 13, 40, 41, 44, 45],
+      [2, 3, 6, 7, 34, 35, 38, 39, 10, 11, 14, 15, 42, 43, 46, 47],
+    ]
+  );
+  assert_eq!(
+    levels[0][15],
+    [722, 723, 726, 727, 754, 755, 758, 759, 730, 731, 734, 735, 762, 763, 766, 767]
+  );
+  assert_eq!(
+    levels[1][..2],
+    [
+      [1024, 1025, 1028, 1029, 1056, 1057, 1060, 1061],
+      [1026, 1027, 1030, 1031, 1058, 1059, 1062, 1063],
+    ]
+  );
+  assert_eq!(levels[2][2], [2112, 2113, 2116, 2117]);
+  assert_eq!(levels[3], [[3072, 3073], [3074, 3075]]);
+
+  // only the blocks a level covers are read, so the data has to reach the last level's last block
+  assert!(matches!(
+    texture.write_dds(&mut Cursor::new(Vec::new()), &data[..3075 * 8]),
+    Err(crate::Error::OutOfBounds { .. })
+  ));
+}
+
+#[test]
+fn untile_x360_packed_mips() {
+  // from 16x16 on the levels share one tile after the 32x32 level
+  let square = GpuTextureFetch::new()
+    .with_pitch(2)
+    .with_width(63)
+    .with_height(63)
+    .with_max_mip_level(6)
+    .with_mip_address(0x102)
+    .with_packed_mips(true);
+  let (texture, data) = x360_dxt1(square, 3072);
+
+  let ranges: Vec<_> = texture.mips().unwrap().map(|mip| mip.range).collect();
+  assert_eq!(
+    ranges,
+    [
+      0..8192,
+      8192..16384,
+      16384..24576,
+      16384..24576,
+      16384..24576,
+      16384..24576,
+      16384..24576
+    ]
+  );
+  let levels = x360_dds_blocks(&texture, &data);
+  assert_eq!(
+    levels[1][0],
+    [1024, 1025, 1028, 1029, 1056, 1057, 1060, 1061]
+  );
+  // 16x16 at texel (16, 0), 8x8 at (8, 0) and 4x4 at (4, 0), then 2x2 at (0, 8) and 1x1 at (0, 4)
+  assert_eq!(
+    levels[2],
+    [
+      [2080, 2081, 2084, 2085],
+      [2082, 2083, 2086, 2087],
+      [2144, 2145, 2148, 2149],
+      [2146, 2147, 2150, 2151],
+    ]
+  );
+  assert_eq!(levels[3], [[2052, 2053], [2054, 2055]]);
+  assert_eq!(levels[4], [[2049]]);
+  assert_eq!(levels[5], [[2112]]);
+  assert_eq!(levels[6], [[2050]]);
+
+  // wider than tall, the small levels are laid out the other way around and the tail starts a
+  // level earlier
+  let wide = GpuTextureFetch::new()
+    .with_pitch(4)
+    .with_width(127)
+    .with_height(31)
+    .with_max_mip_level(7)
+    .with_mip_address(0x102)
+    .with_packed_mips(true);
+  let (texture, data) = x360_dxt1(wide, 2048);
+
+  let ranges: Vec<_> = texture.mips().unwrap().map(|mip| mip.range).collect();
+  assert_eq!(ranges[..2], [0..8192, 8192..16384]);
+  assert!(ranges[2..].iter().all(|range| *range == ranges[1]));
+  let levels = x360_dds_blocks(&texture, &data);
+  // 64x16 at texel (0, 16), 32x8 at (0, 8) and 16x4 at (0, 4), then 8x2 at (32, 0), 4x1 at
+  // (16, 0), 2x1 at (8, 0) and 1x1 at (4, 0)
+  assert_eq!(
+    levels[1][0],
+    [
+      1152, 1153, 1156, 1157, 1184, 1185, 1188, 1189, 1160, 1161, 1164, 1165, 1192, 1193, 1196,
+      1197
+    ]
+  );
+  assert_eq!(
+    levels[2][0],
+    [1088, 1089, 1092, 1093, 1120, 1121, 1124, 1125]
+  );
+  assert_eq!(levels[3], [[1026, 1027, 1030, 1031]]);
+  assert_eq!(levels[4], [[1032, 1033]]);
+  assert_eq!(levels[5], [[1056]]);
+  assert_eq!(levels[6], [[1028]]);
+  assert_eq!(levels[7], [[1025]]);
+}
+
+#[test]
+fn decode_x360_block_formats() {
+  // a single linear 4x4 block at the start of a 4 KiB level
+  let decode = |format: XenosFormat, swizzle: u16, block: &[u8]| {
+    let texture = X360TextureHeader {
+      common: 3,
+      reference_count: 1,
+      fence: 0,
+      read_fence: 0,
+      identifier: 0,
+      base_flush: 0xffff0000,
+      mip_flush: 0xffff0000,
+      fetch: GpuTextureFetch::new()
+        .with_format(format as u8)
+        .with_width(3)
+        .with_height(3)
+        .with_swizzle(swizzle),
+    };
+    let mut data = vec![0; 4096];
+    data[..block.len()].copy_from_slice(block);
+    let image = texture.decode(&data).unwrap().remove(0);
+    (image.pixel(0, 0), image.pixel(1, 0))
+  };
+  // X, Y, Z and W to red, green, blue and alpha
+  let rgba = 0x688;
+
+  // red and blue end points, the first two texels pick each of them
+  let dxt1 = [0x00, 0xf8, 0x1f, 0x00, 0b0100, 0, 0, 0];
+  assert_eq!(
+    decode(XenosFormat::Dxt1, rgba, &dxt1),
+    ([255, 0, 0, 255], [0, 0, 255, 255])
+  );
+  // explicit 4 bit alpha of 0x3 and 0xf
+  let dxt3 = [&[0xf3, 0, 0, 0, 0, 0, 0, 0][..], &dxt1].concat();
+  assert_eq!(
+    decode(XenosFormat::Dxt2Or3, rgba, &dxt3),
+    ([255, 0, 0, 0x33], [0, 0, 255, 255])
+  );
+  // interpolated alpha end points of 200 and 100
+  let dxt5 = [&[200, 100, 0b001_000, 0, 0, 0, 0, 0][..], &dxt1].concat();
+  assert_eq!(
+    decode(XenosFormat::Dxt4Or5, rgba, &dxt5),
+    ([255, 0, 0, 200], [0, 0, 255, 100])
+  );
+  // DXN holds X and Y as two alpha blocks
+  let dxn = [
+    &[200, 100, 0b001_000, 0, 0, 0, 0, 0][..],
+    &[10, 90, 0b000_001, 0, 0, 0, 0, 0],
+  ]
+  .concat();
+  assert_eq!(
+    decode(XenosFormat::Dxn, rgba, &dxn),
+    ([200, 90, 0, 255], [100, 10, 0, 255])
+  );
+  // DXT5A only has X, which the swizzle copies to every channel
+  assert_eq!(
+    decode(XenosFormat::Dxt5A, 0, &dxn[..8]),
+    ([200; 4], [100; 4])
+  );
+}
+
+#[test]
+fn convert_static_textures() {
+  let mut image = RgbaImage::new(8, 4);
+  image.set_pixel(3, 1, [255, 0, 0, 255]);
+  let mips = image.mip_chain(MipFilter::Box);
+
+  let (texture, data) =
+    DXTTextureHeader::encode(DXTFormat::Dxt1Mm, &mips, Default::default()).unwrap();
+  let static_texture = texture.to_static(&data).unwrap();
+  assert_eq!(static_texture.mips[0].header.width, 1);
+  assert_eq!(
+    static_texture.decode().unwrap(),
+    texture.decode(&data).unwrap()
+  );
+  let (streaming, streaming_data) = static_texture.to_streaming().unwrap();
+  assert_eq!((streaming.mip_count, streaming_data), (4, data.clone()));
+  assert!(matches!(
+    texture.to_static(&data[1..]),
+    Err(crate::Error::SizeMismatch { .. })
+  ));
+
+  let (texture, data) = GCNTextureHeader::encode(
+    GCTFormat::Cmpr,
+    &mips[..1],
+    Default::default(),
+    GXPaletteFormat::RGB5A3,
+  )
+  .unwrap();
+  let mut static_texture = StaticTexture {
+    model_info: ModelInfo::read_le(&mut Cursor::new(model_info(0, 1))).unwrap(),
+    static_texture_header: texture.to_static(&data).unwrap(),
+  };
+  // the streamed version is passed through as is
+  let (streaming, streaming_data) = static_texture.to_streaming(0x8000_0002).unwrap();
+  assert_eq!(
+    (streaming.version, streaming.header.width),
+    (0x8000_0002, 8)
+  );
+  assert_eq!(streaming_data, data);
+  static_texture.static_texture_header.mips[0].header.width = 4;
+  assert!(matches!(
+    static_texture.to_streaming(0),
+    Err(crate::Error::InvalidTextureDimensions { width: 4, .. })
+  ));
+}
+
+#[test]
+fn mip_chain_sizes() {
+  let texture = DXTTextureHeader {
+    format: DXTFormat::Dxt1Mm,
+    palette_size: 0,
+    palette: Vec::new(),
+    mip_count: 4,
+    width: 8,
+    height: 2,
+  };
+  let mips: Vec<_> = texture.mips().unwrap().collect();
+  // narrow levels stay at least one pixel, and so one block, high
+  assert_eq!((mips[1].width, mips[1].height), (4, 1));
+  assert_eq!(mips[3].range, 32..40);
+  assert_eq!(texture.image_size().unwrap(), 40);
+  assert!(matches!(
+    texture.check_streaming_data(&[0; 32]),
+    Err(crate::Error::SizeMismatch { expected: 40, .. })
+  ));
+  // the old inherent helpers forward to the traits
+  #[allow(deprecated)]
+  {
+    assert_eq!(texture.calculate_image_size(), 40);
+    assert_eq!(texture.format.get_block_dim(), (4, 4));
+    assert_eq!(texture.format.get_bits_per_pixel(), 4);
+    assert_eq!(GCTFormat::Rgba8.calculate_mip_size(4, 4), 64);
+  }
+}
+
+#[test]
+fn sha256_digests() {
+  let digest = |parts: &[&[u8]]| {
+    let mut hasher = crate::sha256::Sha256::new();
+    for part in parts {
+      hasher.update(part);
+    }
+    hasher
+      .finalize()
+      .iter()
+      .map(|byte| format!("{:02x}", byte))
+      .collect::<String>()
+  };
+
+  assert_eq!(
+    digest(&[]),
+    "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+  );
+  assert_eq!(
+    digest(&[b"abc"]),
+    "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+  );
+  // 56 bytes, so the length spills into a second padding block
+  let message = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+  let expected = "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1";
+  assert_eq!(digest(&[message]), expected);
+  assert_eq!(
+    digest(&[&message[..3], &message[3..50], &message[50..]]),
+    expected
+  );
+}
+
+#[test]
+fn texture_report_across_levels() {
+  let bounding = Bounding::new(Vector3::default(), Vector3::default());
+  let wall = [0x00, 0xf8, 0x1f, 0x00, 0, 0, 0, 0];
+  let floor = [0xff; 8];
+
+  // the first level stores the wall and the floor, the second only the same wall
+  let mut first = empty_section("first", bounding);
+  first.uncached_components = vec![
+    component("textures/wall.tex", 0, 0, Texture, 0, 8),
+    component("textures/floor.tex", 1, 1, Texture, 8, 8),
+  ];
+  first.header.uncached_data_size = 16;
+  let first_soup = xbox_soup(
+    Toc::new(vec![first]),
+    (&[], &[]),
+    &[(0, 0, dxt1_header(4, 4)), (0, 1, dxt1_header(4, 4))],
+    &[],
+  );
+  let first_path = temp_file("first_level.str", &[wall, floor].concat());
+
+  let mut second = empty_section("second", bounding);
+  second.uncached_components = vec![component("walls/wall.tex", 0, 0, Texture, 0, 8)];
+  second.header.uncached_data_size = 8;
+  let second_soup = xbox_soup(
+    Toc::new(vec![second]),
+    (&[], &[]),
+    &[(0, 0, dxt1_header(4, 4))],
+    &[],
+  );
+  let second_path = temp_file("second_level.str", &wall);
+
+  let mut report = TextureReport::new(TextureHash::Native);
+  report
+    .add_level("first", &first_soup, &Str::read(&first_path).unwrap())
+    .unwrap();
+  let mut extracted = Vec::new();
+  report
+    .add_level_with_sink(
+      "second",
+      &second_soup,
+      &Str::read(&second_path).unwrap(),
+      &ExtractOptions::new(),
+      |file| {
+        extracted.push(file.path);
+        Ok(())
+      },
+    )
+    .unwrap();
+
+  // the wall was already seen in the first level
+  assert!(extracted.is_empty());
+  assert_eq!(report.groups().len(), 2);
+  let duplicates = report.duplicates();
+  assert_eq!(duplicates.len(), 1);
+  let usages: Vec<_> = duplicates[0]
+    .usages
+    .iter()
+    .map(|usage| (usage.level.as_str(), usage.path.as_str()))
+    .collect();
+  assert_eq!(
+    usages,
+    [("first", "textures/wall.tex"), ("second", "walls/wall.tex")]
+  );
+  assert_eq!(report.redundant_bytes(), duplicates[0].usages[1].size);
+  assert_ne!(report.groups()[0].hash, report.groups()[1].hash);
+
+  std::fs::remove_file(first_path).unwrap();
+  std::fs::remove_file(second_path).unwrap();
+
+  // the second section references the first one's wall, counted only when not skipping shared
+  let mut shared = empty_section("shared", bounding);
+  shared.uncached_components = vec![component("textures/wall.tex", 7, 0, Texture, 0, 8)];
+  shared.header.uncached_data_size = 8;
+  let mut level = empty_section("level", bounding);
+  level.uncached_components = vec![component("textures/wall.tex", 7, 0, Texture, 0, 8)];
+  level.header.uncached_data_size = 8;
+  level.header.memory_entry.offset = 8;
+  level.header.shared_section_offset = 0;
+  let soup = xbox_soup(
+    Toc::new(vec![shared, level]),
+    (&[], &[]),
+    &[(0, 0, dxt1_header(4, 4)), (1, 0, dxt1_header(4, 4))],
+    &[],
+  );
+  let path = temp_file("shared_level.str", &[wall, wall].concat());
+  let str = Str::read(&path).unwrap();
+  for (skip_shared, usages) in [(false, 2), (true, 1)] {
+    let mut report = TextureReport::new(TextureHash::Native).skip_shared(skip_shared);
+    report.add_level("level", &soup, &str).unwrap();
+    assert_eq!(report.groups().len(), 1);
+    assert_eq!(report.groups()[0].usages.len(), usages);
+  }
+  std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,534 @@
+// Block compression shared by the DXT formats and the GameCube's CMPR, which uses DXT1 blocks with
+// big endian colors.
+
+use crate::error::{Error, Result};
+use crate::{div_round_up, EncodeQuality, RgbaImage};
+
+pub(crate) type Block = [[u8; 4]; 16];
+
+pub(crate) fn rgb565(color: u16) -> [u8; 4] {
+  let r = (color >> 11) & 0x1f;
+  let g = (color >> 5) & 0x3f;
+  let b = color & 0x1f;
+
+  [
+    (r << 3 | r >> 2) as u8,
+    (g << 2 | g >> 4) as u8,
+    (b << 3 | b >> 2) as u8,
+    255,
+  ]
+}
+
+fn mix(a: [u8; 4], b: [u8; 4], weight_a: u32, weight_b: u32) -> [u8; 4] {
+  let channel =
+    |i: usize| ((a[i] as u32 * weight_a + b[i] as u32 * weight_b) / (weight_a + weight_b)) as u8;
+  [channel(0), channel(1), channel(2), 255]
+}
+
+/// The four colors a DXT1 block indexes. Blocks with `color0 <= color1` use the three color mode
+/// with a transparent fourth color, unless the block belongs to a DXT2-5 texture.
+pub(crate) fn dxt1_colors(color0: u16, color1: u16, three_color_mode: bool) -> [[u8; 4]; 4] {
+  let c0 = rgb565(color0);
+  let c1 = rgb565(color1);
+
+  if color0 > color1 || !three_color_mode {
+    [c0, c1, mix(c0, c1, 2, 1), mix(c0, c1, 1, 2)]
+  } else {
+    [c0, c1, mix(c0, c1, 1, 1), [0, 0, 0, 0]]
+  }
+}
+
+fn decode_color_block(block: &[u8], three_color_mode: bool) -> Block {
+  let colors = dxt1_colors(
+    u16::from_le_bytes([block[0], block[1]]),
+    u16::from_le_bytes([block[2], block[3]]),
+    three_color_mode,
+  );
+  let indices = u32::from_le_bytes(block[4..8].try_into().unwrap());
+
+  std::array::from_fn(|i| colors[(indices >> (i * 2)) as usize & 3])
+}
+
+pub(crate) fn decode_dxt1_block(block: &[u8]) -> Block {
+  decode_color_block(block, true)
+}
+
+/// DXT2/3, 4 bit explicit alpha followed by a DXT1 color block.
+pub(crate) fn decode_dxt3_block(block: &[u8]) -> Block {
+  let alpha = u64::from_le_bytes(block[..8].try_into().unwrap());
+  let mut pixels = decode_color_block(&block[8..], false);
+
+  for (i, pixel) in pixels.iter_mut().enumerate() {
+    pixel[3] = ((alpha >> (i * 4)) & 0xf) as u8 * 17;
+  }
+
+  pixels
+}
+
+// the 8 alpha values an interpolated alpha block indexes
+fn dxt5_alphas(alpha0: u8, alpha1: u8) -> [u8; 8] {
+  let (alpha0, alpha1) = (alpha0 as u32, alpha1 as u32);
+  let mut alphas = [0u8; 8];
+  alphas[0] = alpha0 as u8;
+  alphas[1] = alpha1 as u8;
+  if alpha0 > alpha1 {
+    for i in 1..7 {
+      alphas[i + 1] = ((alpha0 * (7 - i as u32) + alpha1 * i as u32) / 7) as u8;
+    }
+  } else {
+    for i in 1..5 {
+      alphas[i + 1] = ((alpha0 * (5 - i as u32) + alpha1 * i as u32) / 5) as u8;
+    }
+    alphas[7] = 255;
+  }
+
+  alphas
+}
+
+/// An interpolated alpha block as used by DXT4/5, also a single channel of DXN and DXT5A.
+pub(crate) fn decode_alpha_block(block: &[u8]) -> [u8; 16] {
+  let alphas = dxt5_alphas(block[0], block[1]);
+
+  let mut indices = [0; 8];
+  indices[..6].copy_from_slice(&block[2..8]);
+  let indices = u64::from_le_bytes(indices);
+
+  std::array::from_fn(|i| alphas[(indices >> (i * 3)) as usize & 7])
+}
+
+/// DXT4/5, interpolated alpha followed by a DXT1 color block.
+pub(crate) fn decode_dxt5_block(block: &[u8]) -> Block {
+  let alphas = decode_alpha_block(block);
+
+  let mut pixels = decode_color_block(&block[8..], false);
+  for (pixel, alpha) in pixels.iter_mut().zip(alphas) {
+    pixel[3] = alpha;
+  }
+
+  pixels
+}
+
+/// Decodes an image stored as rows of 4x4 blocks of `block_size` bytes each.
+pub(crate) fn decode_blocks(
+  data: &[u8],
+  width: usize,
+  height: usize,
+  block_size: usize,
+  decode_block: impl Fn(&[u8]) -> Block,
+) -> Result<RgbaImage> {
+  let blocks_wide = div_round_up(width, 4);
+  let blocks_high = div_round_up(height, 4);
+  check_size(data, blocks_wide * blocks_high * block_size)?;
+
+  let mut image = RgbaImage::new(width as u32, height as u32);
+  for (i, block) in data
+    .chunks_exact(block_size)
+    .take(blocks_wide * blocks_high)
+    .enumerate()
+  {
+    let (block_x, block_y) = ((i % blocks_wide) * 4, (i / blocks_wide) * 4);
+    for (j, pixel) in decode_block(block).into_iter().enumerate() {
+      let (x, y) = (block_x + j % 4, block_y + j / 4);
+      if x < width && y < height {
+        image.set_pixel(x as u32, y as u32, pixel);
+      }
+    }
+  }
+
+  Ok(image)
+}
+
+pub(crate) fn check_size(data: &[u8], size: usize) -> Result<()> {
+  if data.len() < size {
+    return Err(Error::SizeMismatch {
+      context: "texture mip data",
+      expected: size,
+      actual: data.len(),
+    });
+  }
+
+  Ok(())
+}
+
+/// Xbox swizzling interleaves the bits of the x and y coordinates, x first, until the smaller
+/// dimension runs out. Returns the masks to deposit each coordinate's bits with.
+fn swizzle_masks(width: usize, height: usize) -> (usize, usize) {
+  let (mut mask_x, mut mask_y) = (0, 0);
+  let mut bit = 1;
+  let mut mask_bit = 1;
+
+  while bit < width || bit < height {
+    if bit < width {
+      mask_x |= mask_bit;
+      mask_bit <<= 1;
+    }
+    if bit < height {
+      mask_y |= mask_bit;
+      mask_bit <<= 1;
+    }
+    bit <<= 1;
+  }
+
+  (mask_x, mask_y)
+}
+
+// spreads the low bits of `value` over the set bits of `mask`
+fn deposit_bits(mut value: usize, mask: usize) -> usize {
+  let mut result = 0;
+  let mut bit = 1;
+
+  while bit <= mask && value != 0 {
+    if mask & bit != 0 {
+      result |= (value & 1) * bit;
+      value >>= 1;
+    }
+    bit <<= 1;
+  }
+
+  result
+}
+
+/// Offsets of each pixel of a swizzled texture, in row order.
+fn swizzled_offsets(width: usize, height: usize) -> impl Iterator<Item = usize> {
+  let (mask_x, mask_y) = swizzle_masks(width, height);
+  (0..height)
+    .flat_map(move |y| (0..width).map(move |x| deposit_bits(x, mask_x) | deposit_bits(y, mask_y)))
+}
+
+/// Reorders a swizzled texture with one byte per pixel into rows.
+pub(crate) fn unswizzle(data: &[u8], width: usize, height: usize) -> Result<Vec<u8>> {
+  check_size(data, width * height)?;
+
+  swizzled_offsets(width, height)
+    .map(|offset| {
+      data.get(offset).copied().ok_or(Error::OutOfBounds {
+        context: "swizzled texture data",
+        offset,
+        size: 1,
+        available: data.len(),
+      })
+    })
+    .collect()
+}
+
+/// Reorders rows of one byte per pixel into the swizzled layout.
+pub(crate) fn swizzle(data: &[u8], width: usize, height: usize) -> Result<Vec<u8>> {
+  check_size(data, width * height)?;
+
+  let mut swizzled = vec![0; width * height];
+  for (i, offset) in swizzled_offsets(width, height).enumerate() {
+    let len = swizzled.len();
+    *swizzled.get_mut(offset).ok_or(Error::OutOfBounds {
+      context: "swizzled texture data",
+      offset,
+      size: 1,
+      available: len,
+    })? = data[i];
+  }
+
+  Ok(swizzled)
+}
+
+/// A DXT1 color block before packing, `indices` are in row order.
+pub(crate) struct Dxt1Block {
+  pub color0: u16,
+  pub color1: u16,
+  pub indices: [u8; 16],
+}
+
+fn to_rgb565(color: [f32; 3]) -> u16 {
+  let channel = |value: f32, max: f32| (value.clamp(0.0, 255.0) * max / 255.0).round() as u16;
+  channel(color[0], 31.0) << 11 | channel(color[1], 63.0) << 5 | channel(color[2], 31.0)
+}
+
+fn color_error(a: [u8; 4], b: [u8; 4]) -> u32 {
+  (0..3)
+    .map(|i| (a[i] as i32 - b[i] as i32).pow(2) as u32)
+    .sum()
+}
+
+// the line through the colors the block's endpoints are picked from
+fn endpoints(colors: &[[f32; 3]], quality: EncodeQuality) -> ([f32; 3], [f32; 3]) {
+  if quality == EncodeQuality::Fast {
+    let mut min = [255.0f32; 3];
+    let mut max = [0.0f32; 3];
+    for color in colors {
+      for i in 0..3 {
+        min[i] = min[i].min(color[i]);
+        max[i] = max[i].max(color[i]);
+      }
+    }
+    return (max, min);
+  }
+
+  let count = colors.len() as f32;
+  let mean: [f32; 3] =
+    std::array::from_fn(|i| colors.iter().map(|color| color[i]).sum::<f32>() / count);
+
+  let mut covariance = [[0.0f32; 3]; 3];
+  for color in colors {
+    for i in 0..3 {
+      for j in 0..3 {
+        covariance[i][j] += (color[i] - mean[i]) * (color[j] - mean[j]);
+      }
+    }
+  }
+
+  // power iteration for the principal axis
+  let mut axis = [1.0f32, 1.0, 1.0];
+  for _ in 0..8 {
+    let next: [f32; 3] = std::array::from_fn(|i| (0..3).map(|j| covariance[i][j] * axis[j]).sum());
+    let length = next.iter().map(|value| value * value).sum::<f32>().sqrt();
+    if length < f32::EPSILON {
+      break;
+    }
+    axis = next.map(|value| value / length);
+  }
+
+  let project = |color: &[f32; 3]| (0..3).map(|i| (color[i] - mean[i]) * axis[i]).sum::<f32>();
+  let (min, max) = colors
+    .iter()
+    .map(project)
+    .fold((f32::MAX, f32::MIN), |(min, max), t| {
+      (min.min(t), max.max(t))
+    });
+
+  let point = |t: f32| std::array::from_fn(|i| mean[i] + axis[i] * t);
+  (point(max), point(min))
+}
+
+// assigns each opaque pixel the closest of the first `color_count` colors, transparent ones 3
+fn fit_indices(
+  pixels: &Block,
+  transparent: &[bool; 16],
+  colors: &[[u8; 4]; 4],
+  color_count: usize,
+) -> ([u8; 16], u32) {
+  let mut error = 0;
+  let indices = std::array::from_fn(|i| {
+    if transparent[i] {
+      return 3;
+    }
+    let (index, pixel_error) = (0..color_count)
+      .map(|index| (index, color_error(pixels[i], colors[index])))
+      .min_by_key(|&(_, error)| error)
+      .unwrap();
+    error += pixel_error;
+    index as u8
+  });
+
+  (indices, error)
+}
+
+// least squares endpoints for the indices of a 4 color block
+fn refit_endpoints(colors: &[[f32; 3]], weights: &[f32]) -> Option<([f32; 3], [f32; 3])> {
+  let (mut aa, mut bb, mut ab) = (0.0f32, 0.0f32, 0.0f32);
+  let mut ax = [0.0f32; 3];
+  let mut bx = [0.0f32; 3];
+  for (color, &weight) in colors.iter().zip(weights) {
+    let (a, b) = (1.0 - weight, weight);
+    aa += a * a;
+    bb += b * b;
+    ab += a * b;
+    for i in 0..3 {
+      ax[i] += a * color[i];
+      bx[i] += b * color[i];
+    }
+  }
+
+  let determinant = aa * bb - ab * ab;
+  if determinant.abs() < f32::EPSILON {
+    return None;
+  }
+
+  Some((
+    std::array::from_fn(|i| (ax[i] * bb - bx[i] * ab) / determinant),
+    std::array::from_fn(|i| (bx[i] * aa - ax[i] * ab) / determinant),
+  ))
+}
+
+/// Compresses a DXT1 color block. With `three_color_mode` pixels with less than half alpha are
+/// made transparent, otherwise alpha is ignored as in the DXT2-5 color blocks.
+pub(crate) fn encode_dxt1_block(
+  pixels: &Block,
+  three_color_mode: bool,
+  quality: EncodeQuality,
+) -> Dxt1Block {
+  let transparent: [bool; 16] = std::array::from_fn(|i| three_color_mode && pixels[i][3] < 128);
+  let colors: Vec<[f32; 3]> = pixels
+    .iter()
+    .zip(transparent)
+    .filter(|(_, transparent)| !transparent)
+    .map(|(pixel, _)| [pixel[0] as f32, pixel[1] as f32, pixel[2] as f32])
+    .collect();
+
+  if colors.is_empty() {
+    // equal endpoints select the three color mode, index 3 is transparent
+    return Dxt1Block {
+      color0: 0,
+      color1: 0,
+      indices: [3; 16],
+    };
+  }
+
+  let has_transparency = transparent.contains(&true);
+  let (start, end) = endpoints(&colors, quality);
+  let mut endpoints = (to_rgb565(start), to_rgb565(end));
+
+  let encode = |(color0, color1): (u16, u16), three_colors: bool| {
+    let (color0, color1) = if three_colors {
+      (color0.min(color1), color0.max(color1))
+    } else {
+      (color0.max(color1), color0.min(color1))
+    };
+    let colors = dxt1_colors(color0, color1, true);
+    // equal endpoints always decode in the three color mode
+    let color_count = if three_colors || color0 == color1 {
+      3
+    } else {
+      4
+    };
+    let (indices, error) = fit_indices(pixels, &transparent, &colors, color_count);
+    (
+      Dxt1Block {
+        color0,
+        color1,
+        indices,
+      },
+      error,
+    )
+  };
+
+  let (mut block, mut error) = encode(endpoints, has_transparency);
+
+  if quality == EncodeQuality::High && !has_transparency {
+    for _ in 0..2 {
+      let weights: Vec<f32> = block
+        .indices
+        .iter()
+        .map(|&index| [0.0, 1.0, 1.0 / 3.0, 2.0 / 3.0][index as usize])
+        .collect();
+      let Some((start, end)) = refit_endpoints(&colors, &weights) else {
+        break;
+      };
+      endpoints = (to_rgb565(start), to_rgb565(end));
+      let (refit, refit_error) = encode(endpoints, false);
+      if refit_error >= error {
+        break;
+      }
+      (block, error) = (refit, refit_error);
+    }
+
+    // the three color mode's midpoint can be closer for blocks along a short line
+    if three_color_mode {
+      let (three_color, three_color_error) = encode(endpoints, true);
+      if three_color_error < error {
+        block = three_color;
+      }
+    }
+  }
+
+  block
+}
+
+pub(crate) fn pack_dxt1_block(block: &Dxt1Block) -> [u8; 8] {
+  let indices = block
+    .indices
+    .iter()
+    .enumerate()
+    .fold(0u32, |bits, (i, &index)| bits | (index as u32) << (i * 2));
+
+  let mut packed = [0; 8];
+  packed[..2].copy_from_slice(&block.color0.to_le_bytes());
+  packed[2..4].copy_from_slice(&block.color1.to_le_bytes());
+  packed[4..].copy_from_slice(&indices.to_le_bytes());
+  packed
+}
+
+/// DXT2/3, 4 bit explicit alpha followed by a DXT1 color block.
+pub(crate) fn encode_dxt3_block(pixels: &Block, quality: EncodeQuality) -> [u8; 16] {
+  let alpha = pixels.iter().enumerate().fold(0u64, |bits, (i, pixel)| {
+    bits | ((pixel[3] as u64 * 15 + 127) / 255) << (i * 4)
+  });
+
+  let mut packed = [0; 16];
+  packed[..8].copy_from_slice(&alpha.to_le_bytes());
+  packed[8..].copy_from_slice(&pack_dxt1_block(&encode_dxt1_block(pixels, false, quality)));
+  packed
+}
+
+// (alpha0, alpha1, indices, error) of an interpolated alpha block
+fn fit_alpha(pixels: &Block, alpha0: u8, alpha1: u8) -> (u8, u8, u64, u32) {
+  let alphas = dxt5_alphas(alpha0, alpha1);
+
+  let mut error = 0;
+  let indices = pixels.iter().enumerate().fold(0u64, |bits, (i, pixel)| {
+    let (index, pixel_error) = alphas
+      .iter()
+      .enumerate()
+      .map(|(index, &alpha)| (index, (alpha as i32 - pixel[3] as i32).unsigned_abs()))
+      .min_by_key(|&(_, error)| error)
+      .unwrap();
+    error += pixel_error * pixel_error;
+    bits | (index as u64) << (i * 3)
+  });
+
+  (alpha0, alpha1, indices, error)
+}
+
+/// DXT4/5, interpolated alpha followed by a DXT1 color block.
+pub(crate) fn encode_dxt5_block(pixels: &Block, quality: EncodeQuality) -> [u8; 16] {
+  let alphas = pixels.map(|pixel| pixel[3]);
+  let (min, max) = (*alphas.iter().min().unwrap(), *alphas.iter().max().unwrap());
+  let mut best = fit_alpha(pixels, max, min);
+
+  // the 6 value mode has exact 0 and 255, which helps blocks mixing them with other values
+  if quality != EncodeQuality::Fast {
+    let inner = alphas.iter().filter(|&&alpha| alpha != 0 && alpha != 255);
+    let inner_min = inner.clone().min().copied().unwrap_or(0);
+    let inner_max = inner.max().copied().unwrap_or(255);
+    let six_value = fit_alpha(pixels, inner_min, inner_max);
+    if six_value.3 < best.3 {
+      best = six_value;
+    }
+  }
+
+  let (alpha0, alpha1, indices, _) = best;
+  let mut packed = [0; 16];
+  packed[0] = alpha0;
+  packed[1] = alpha1;
+  packed[2..8].copy_from_slice(&indices.to_le_bytes()[..6]);
+  packed[8..].copy_from_slice(&pack_dxt1_block(&encode_dxt1_block(pixels, false, quality)));
+  packed
+}
+
+/// Gathers the 4x4 blocks of an image in row order, repeating the edge pixels of partial blocks.
+pub(crate) fn image_blocks(image: &RgbaImage) -> impl Iterator<Item = Block> + '_ {
+  tiles(image, 4, 4).map(|pixels| pixels.try_into().unwrap())
+}
+
+/// Gathers tiles of an image in row order, each in row order, repeating the edge pixels of partial
+/// tiles.
+pub(crate) fn tiles(
+  image: &RgbaImage,
+  tile_width: usize,
+  tile_height: usize,
+) -> impl Iterator<Item = Vec<[u8; 4]>> + '_ {
+  let (width, height) = (image.width as usize, image.height as usize);
+  let tiles_wide = div_round_up(width, tile_width);
+  let tiles_high = div_round_up(height, tile_height);
+
+  (0..tiles_wide * tiles_high).map(move |i| {
+    let (tile_x, tile_y) = (
+      (i % tiles_wide) * tile_width,
+      (i / tiles_wide) * tile_height,
+    );
+    (0..tile_width * tile_height)
+      .map(|j| {
+        let x = (tile_x + j % tile_width).min(width.max(1) - 1);
+        let y = (tile_y + j / tile_width).min(height.max(1) - 1);
+        image.pixel(x as u32, y as u32)
+      })
+      .collect()
+  })
+}
@@ -1,4 +1,13 @@
-use binrw::BinRead;
+use std::io::{Cursor, Read, Seek, Write};
+
+use binrw::{BinRead, BinWrite};
+
+use crate::error::{Error, Result};
+use crate::textures::block::*;
+use crate::{
+  div_round_up, DXTFormat, DXTStaticTextureHeader, DXTSurface, DXTSurfaceHeader, DXTTextureHeader,
+  MipChain, RgbaImage, StreamingTexture, Texture, TextureFormat,
+};
 
 #[derive(BinRead, Debug)]
 pub struct X360StaticTextureHeader {
@@ -6,3 +15,488 @@ pub struct X360StaticTextureHeader {
   #[br(count = dds_size)]
   pub header_file: Vec<u8>,
 }
+
+impl X360StaticTextureHeader {
+  /// Decodes every mip level of the stored DDS file, biggest first.
+  pub fn decode(&self) -> Result<Vec<RgbaImage>> {
+    let mut reader = Cursor::new(&self.header_file);
+    let header = DdsHeader::read(&mut reader)?;
+    header.decode(&self.header_file[reader.position() as usize..])
+  }
+}
+
+pub const DDSD_CAPS: u32 = 0x1;
+pub const DDSD_HEIGHT: u32 = 0x2;
+pub const DDSD_WIDTH: u32 = 0x4;
+pub const DDSD_PITCH: u32 = 0x8;
+pub const DDSD_PIXELFORMAT: u32 = 0x1000;
+pub const DDSD_MIPMAPCOUNT: u32 = 0x20000;
+pub const DDSD_LINEARSIZE: u32 = 0x80000;
+pub const DDPF_ALPHAPIXELS: u32 = 0x1;
+pub const DDPF_ALPHA: u32 = 0x2;
+pub const DDPF_FOURCC: u32 = 0x4;
+pub const DDPF_PALETTEINDEXED8: u32 = 0x20;
+pub const DDPF_RGB: u32 = 0x40;
+pub const DDPF_LUMINANCE: u32 = 0x20000;
+pub const DDSCAPS_COMPLEX: u32 = 0x8;
+pub const DDSCAPS_TEXTURE: u32 = 0x1000;
+pub const DDSCAPS_MIPMAP: u32 = 0x400000;
+
+#[derive(BinRead, BinWrite, Debug, Clone, Default)]
+pub struct DdsPixelFormat {
+  pub size: u32,
+  pub flags: u32,
+  pub four_cc: [u8; 4],
+  pub rgb_bit_count: u32,
+  pub r_bit_mask: u32,
+  pub g_bit_mask: u32,
+  pub b_bit_mask: u32,
+  pub a_bit_mask: u32,
+}
+
+/// The header of a DirectDraw Surface file, including the magic.
+#[derive(BinRead, BinWrite, Debug, Clone, Default)]
+#[brw(little, magic = b"DDS ")]
+pub struct DdsHeader {
+  pub size: u32,
+  pub flags: u32,
+  pub height: u32,
+  pub width: u32,
+  pub pitch_or_linear_size: u32,
+  pub depth: u32,
+  pub mip_map_count: u32,
+  pub reserved1: [u32; 11],
+  pub pixel_format: DdsPixelFormat,
+  pub caps: u32,
+  pub caps2: u32,
+  pub caps3: u32,
+  pub caps4: u32,
+  pub reserved2: u32,
+}
+
+impl DdsHeader {
+  /// Header of a 2D texture. `top_level_size` is the size of the biggest mip, written as the linear
+  /// size of compressed formats or the row pitch of uncompressed ones.
+  pub fn new(
+    width: u32,
+    height: u32,
+    mip_count: u32,
+    pixel_format: DdsPixelFormat,
+    top_level_size: u32,
+  ) -> Self {
+    let mut flags = DDSD_CAPS | DDSD_HEIGHT | DDSD_WIDTH | DDSD_PIXELFORMAT;
+    let mut caps = DDSCAPS_TEXTURE;
+    let pitch_or_linear_size = if pixel_format.flags & DDPF_FOURCC != 0 {
+      flags |= DDSD_LINEARSIZE;
+      top_level_size
+    } else {
+      flags |= DDSD_PITCH;
+      top_level_size / height.max(1)
+    };
+    if mip_count > 1 {
+      flags |= DDSD_MIPMAPCOUNT;
+      caps |= DDSCAPS_COMPLEX | DDSCAPS_MIPMAP;
+    }
+
+    Self {
+      size: 124,
+      flags,
+      height,
+      width,
+      pitch_or_linear_size,
+      mip_map_count: mip_count,
+      pixel_format,
+      caps,
+      ..Default::default()
+    }
+  }
+
+  pub fn mip_count(&self) -> u32 {
+    if self.flags & DDSD_MIPMAPCOUNT != 0 {
+      self.mip_map_count.max(1)
+    } else {
+      1
+    }
+  }
+
+  /// Decodes the mip levels in `data`, the file contents following the header. Only the block
+  /// compressed FourCCs and uncompressed formats described by bit masks are supported.
+  pub fn decode(&self, data: &[u8]) -> Result<Vec<RgbaImage>> {
+    let pixel_format = &self.pixel_format;
+    let mut offset = 0;
+
+    (0..self.mip_count())
+      .map(|level| {
+        let width = 1.max(self.width as usize >> level);
+        let height = 1.max(self.height as usize >> level);
+        let data = data.get(offset..).unwrap_or_default();
+
+        let (image, size) = if pixel_format.flags & DDPF_FOURCC != 0 {
+          let four_cc = &pixel_format.four_cc;
+          let (block_size, decode_block): (usize, fn(&[u8]) -> Block) = match four_cc {
+            b"DXT1" => (8, decode_dxt1_block),
+            b"DXT2" | b"DXT3" => (16, decode_dxt3_block),
+            b"DXT4" | b"DXT5" => (16, decode_dxt5_block),
+            _ => {
+              return Err(Error::UnsupportedTextureFormat {
+                format: String::from_utf8_lossy(four_cc).into_owned(),
+              })
+            }
+          };
+
+          let mut image = decode_blocks(data, width, height, block_size, decode_block)?;
+          if matches!(four_cc, b"DXT2" | b"DXT4") {
+            image.unpremultiply();
+          }
+          let size = div_round_up(width, 4) * div_round_up(height, 4) * block_size;
+          (image, size)
+        } else {
+          let image = self.decode_masked(data, width, height)?;
+          (
+            image,
+            width * height * pixel_format.rgb_bit_count as usize / 8,
+          )
+        };
+
+        offset += size;
+        Ok(image)
+      })
+      .collect()
+  }
+
+  fn decode_masked(&self, data: &[u8], width: usize, height: usize) -> Result<RgbaImage> {
+    let pixel_format = &self.pixel_format;
+    let bytes_per_pixel = pixel_format.rgb_bit_count as usize / 8;
+    if !matches!(bytes_per_pixel, 1..=4)
+      || pixel_format.flags & (DDPF_RGB | DDPF_LUMINANCE | DDPF_ALPHA) == 0
+    {
+      return Err(Error::UnsupportedTextureFormat {
+        format: format!(
+          "flags {:#x} with {} bits per pixel",
+          pixel_format.flags, pixel_format.rgb_bit_count
+        ),
+      });
+    }
+    check_size(data, width * height * bytes_per_pixel)?;
+
+    // scales the masked bits of a pixel to 8 bits
+    let channel = |pixel: u32, mask: u32| {
+      if mask == 0 {
+        return None;
+      }
+      let max = (1u64 << mask.count_ones()) - 1;
+      let value = (pixel & mask) >> mask.trailing_zeros();
+      Some((value as u64 * 255 / max) as u8)
+    };
+    let has_alpha = pixel_format.flags & (DDPF_ALPHAPIXELS | DDPF_ALPHA) != 0;
+
+    let mut image = RgbaImage::new(width as u32, height as u32);
+    for (i, bytes) in data
+      .chunks_exact(bytes_per_pixel)
+      .take(width * height)
+      .enumerate()
+    {
+      let mut pixel = [0; 4];
+      pixel[..bytes_per_pixel].copy_from_slice(bytes);
+      let pixel = u32::from_le_bytes(pixel);
+
+      let r = channel(pixel, pixel_format.r_bit_mask).unwrap_or(0);
+      let (g, b) = if pixel_format.flags & DDPF_LUMINANCE != 0 {
+        (r, r)
+      } else {
+        (
+          channel(pixel, pixel_format.g_bit_mask).unwrap_or(0),
+          channel(pixel, pixel_format.b_bit_mask).unwrap_or(0),
+        )
+      };
+      let a = channel(pixel, pixel_format.a_bit_mask)
+        .filter(|_| has_alpha)
+        .unwrap_or(255);
+
+      image.set_pixel((i % width) as u32, (i / width) as u32, [r, g, b, a]);
+    }
+
+    Ok(image)
+  }
+}
+
+impl DXTFormat {
+  /// The matching DDS pixel format. Pal8 maps to a palettized format, DXT2 and DXT4 keep their
+  /// premultiplied FourCCs.
+  pub fn dds_pixel_format(&self) -> DdsPixelFormat {
+    let four_cc = |four_cc: &[u8; 4]| DdsPixelFormat {
+      size: 32,
+      flags: DDPF_FOURCC,
+      four_cc: *four_cc,
+      ..Default::default()
+    };
+
+    match self {
+      DXTFormat::Dxt1 | DXTFormat::Dxt1Mm => four_cc(b"DXT1"),
+      DXTFormat::Dxt2 | DXTFormat::Dxt2Mm => four_cc(b"DXT2"),
+      DXTFormat::Dxt3 | DXTFormat::Dxt3Mm => four_cc(b"DXT3"),
+      DXTFormat::Dxt4 | DXTFormat::Dxt4Mm => four_cc(b"DXT4"),
+      DXTFormat::Dxt5 | DXTFormat::Dxt5Mm => four_cc(b"DXT5"),
+      DXTFormat::Pal8 | DXTFormat::Pal8Mm => DdsPixelFormat {
+        size: 32,
+        flags: DDPF_PALETTEINDEXED8,
+        rgb_bit_count: 8,
+        ..Default::default()
+      },
+      DXTFormat::Lum8 => DdsPixelFormat {
+        size: 32,
+        flags: DDPF_LUMINANCE,
+        rgb_bit_count: 8,
+        r_bit_mask: 0xff,
+        ..Default::default()
+      },
+    }
+  }
+}
+
+// `mips` are (width, height, data), biggest first
+fn write_dxt_dds<W: Write + Seek>(
+  writer: &mut W,
+  format: &DXTFormat,
+  palette: &[u8],
+  mips: &[(usize, usize, &[u8])],
+) -> Result<()> {
+  let Some(&(width, height, _)) = mips.first() else {
+    return Err(Error::MissingField { field: "mips" });
+  };
+
+  let header = DdsHeader::new(
+    width as u32,
+    height as u32,
+    mips.len() as u32,
+    format.dds_pixel_format(),
+    format.mip_size(width, height) as u32,
+  );
+  header.write(writer)?;
+
+  if matches!(format, DXTFormat::Pal8 | DXTFormat::Pal8Mm) {
+    // 256 PALETTEENTRYs in RGBA order, the D3DCOLORs are stored as BGRA
+    let mut entries = [0u8; 256 * 4];
+    for (entry, color) in entries.chunks_exact_mut(4).zip(palette.chunks_exact(4)) {
+      entry.copy_from_slice(&[color[2], color[1], color[0], color[3]]);
+    }
+    writer.write_all(&entries)?;
+  }
+
+  for &(width, height, data) in mips {
+    let size = format.mip_size(width, height);
+    check_size(data, size)?;
+
+    // DDS stores rows, the 8 bit Xbox formats are swizzled
+    if format.is_swizzled() {
+      writer.write_all(&unswizzle(data, width, height)?)?;
+    } else {
+      writer.write_all(&data[..size])?;
+    }
+  }
+
+  Ok(())
+}
+
+impl DXTTextureHeader {
+  /// Writes the streamed texture as a DDS file with its full mip chain.
+  pub fn write_dds<W: Write + Seek>(&self, writer: &mut W, streaming_data: &[u8]) -> Result<()> {
+    let mips: Vec<_> = self
+      .mip_data(streaming_data)?
+      .into_iter()
+      .map(|(mip, data)| (mip.width, mip.height, data))
+      .collect();
+
+    write_dxt_dds(writer, &self.format, &self.palette, &mips)
+  }
+}
+
+impl StreamingTexture<DXTTextureHeader> {
+  pub fn write_dds<W: Write + Seek>(&self, writer: &mut W, streaming_data: &[u8]) -> Result<()> {
+    self.header.write_dds(writer, streaming_data)
+  }
+}
+
+impl DXTStaticTextureHeader {
+  /// Writes the texture as a DDS file with its full mip chain.
+  pub fn write_dds<W: Write + Seek>(&self, writer: &mut W) -> Result<()> {
+    let mut mips: Vec<_> = self
+      .mips
+      .iter()
+      .map(|mip| {
+        (
+          mip.header.width as usize,
+          mip.header.height as usize,
+          &mip.data[..],
+        )
+      })
+      .collect();
+    // standalone files store the smallest mip first
+    mips.sort_by_key(|&(width, height, _)| std::cmp::Reverse(width * height));
+
+    write_dxt_dds(writer, &self.format, &self.palette, &mips)
+  }
+}
+
+// the biggest texture the Xbox GPU samples
+const MAX_XBOX_TEXTURE_SIZE: u32 = 4096;
+
+struct DxtImport {
+  format: DXTFormat,
+  palette: Vec<u8>,
+  width: u32,
+  height: u32,
+  // (width, height, data) biggest first, already swizzled where the format needs it
+  mips: Vec<(u32, u32, Vec<u8>)>,
+}
+
+impl DdsPixelFormat {
+  /// The Xbox format storing this pixel format, the `Mm` variants are used for textures with mips.
+  pub fn dxt_format(&self, has_mips: bool) -> Result<DXTFormat> {
+    let format = if self.flags & DDPF_FOURCC != 0 {
+      match (&self.four_cc, has_mips) {
+        (b"DXT1", false) => DXTFormat::Dxt1,
+        (b"DXT1", true) => DXTFormat::Dxt1Mm,
+        (b"DXT2", false) => DXTFormat::Dxt2,
+        (b"DXT2", true) => DXTFormat::Dxt2Mm,
+        (b"DXT3", false) => DXTFormat::Dxt3,
+        (b"DXT3", true) => DXTFormat::Dxt3Mm,
+        (b"DXT4", false) => DXTFormat::Dxt4,
+        (b"DXT4", true) => DXTFormat::Dxt4Mm,
+        (b"DXT5", false) => DXTFormat::Dxt5,
+        (b"DXT5", true) => DXTFormat::Dxt5Mm,
+        (four_cc, _) => {
+          return Err(Error::UnsupportedTextureFormat {
+            format: String::from_utf8_lossy(four_cc).into_owned(),
+          })
+        }
+      }
+    } else if self.flags & DDPF_PALETTEINDEXED8 != 0 && self.rgb_bit_count == 8 {
+      if has_mips {
+        DXTFormat::Pal8Mm
+      } else {
+        DXTFormat::Pal8
+      }
+    } else if self.flags & DDPF_LUMINANCE != 0 && self.rgb_bit_count == 8 && self.r_bit_mask == 0xff
+    {
+      // there's no Lum8 variant for textures with mips
+      DXTFormat::Lum8
+    } else {
+      return Err(Error::UnsupportedTextureFormat {
+        format: format!(
+          "flags {:#x} with {} bits per pixel",
+          self.flags, self.rgb_bit_count
+        ),
+      });
+    };
+
+    Ok(format)
+  }
+}
+
+fn read_dxt_dds<R: Read + Seek>(reader: &mut R) -> Result<DxtImport> {
+  let header = DdsHeader::read(reader)?;
+  let (width, height, mip_count) = (header.width, header.height, header.mip_count());
+
+  // swizzling needs powers of two, and so do the mip chains the game computes
+  let max_mip_count = 32 - width.max(height).leading_zeros();
+  if !width.is_power_of_two()
+    || !height.is_power_of_two()
+    || width.max(height) > MAX_XBOX_TEXTURE_SIZE
+    || mip_count > max_mip_count
+  {
+    return Err(Error::InvalidTextureDimensions {
+      width,
+      height,
+      mip_count,
+    });
+  }
+
+  let format = header.pixel_format.dxt_format(mip_count > 1)?;
+
+  let mut palette = Vec::new();
+  if matches!(format, DXTFormat::Pal8 | DXTFormat::Pal8Mm) {
+    // PALETTEENTRYs are RGBA, the Xbox stores D3DCOLORs as BGRA
+    let mut entries = [0u8; 256 * 4];
+    reader.read_exact(&mut entries)?;
+    palette = entries
+      .chunks_exact(4)
+      .flat_map(|entry| [entry[2], entry[1], entry[0], entry[3]])
+      .collect();
+  }
+
+  let mips = MipChain::new(&format, width, height, mip_count)
+    .map(|mip| {
+      let mut data = vec![0; mip.range.len()];
+      reader.read_exact(&mut data)?;
+      if format.is_swizzled() {
+        data = swizzle(&data, mip.width, mip.height)?;
+      }
+      Ok((mip.width as u32, mip.height as u32, data))
+    })
+    .collect::<Result<Vec<_>>>()?;
+
+  Ok(DxtImport {
+    format,
+    palette,
+    width,
+    height,
+    mips,
+  })
+}
+
+impl DXTTextureHeader {
+  /// Reads a DXT1-5, L8 or palettized DDS file into a streaming texture header and its streaming
+  /// data. Textures the game can't use are rejected.
+  pub fn from_dds<R: Read + Seek>(reader: &mut R) -> Result<(Self, Vec<u8>)> {
+    let import = read_dxt_dds(reader)?;
+    let header = Self {
+      format: import.format,
+      palette_size: import.palette.len() as u32 / 2,
+      palette: import.palette,
+      mip_count: import.mips.len() as u32,
+      width: import.width,
+      height: import.height,
+    };
+
+    // mips are streamed biggest first
+    let streaming_data = import
+      .mips
+      .into_iter()
+      .flat_map(|(_, _, data)| data)
+      .collect();
+    Ok((header, streaming_data))
+  }
+}
+
+impl DXTStaticTextureHeader {
+  /// Reads a DXT1-5, L8 or palettized DDS file into a standalone texture. Textures the game can't
+  /// use are rejected.
+  pub fn from_dds<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+    let import = read_dxt_dds(reader)?;
+
+    Ok(Self {
+      version: 2,
+      format: import.format,
+      palette_size: import.palette.len() as u32 / 2,
+      palette: import.palette,
+      mip_count: import.mips.len() as u32,
+      width: import.width,
+      height: import.height,
+      // standalone files store the smallest mip first
+      mips: import
+        .mips
+        .into_iter()
+        .rev()
+        .map(|(width, height, data)| DXTSurface {
+          header: DXTSurfaceHeader {
+            width,
+            height,
+            size: data.len() as u32,
+          },
+          data,
+        })
+        .collect(),
+    })
+  }
+}
@@ -1,6 +1,12 @@
 use binrw::*;
 
-use crate::div_round_up;
+use crate::convert::join_mips;
+use crate::error::{Error, Result};
+use crate::textures::block::*;
+use crate::textures::encode::*;
+use crate::{
+  EncodeQuality, MipChain, RgbaImage, StaticTexture, StreamingTexture, Texture, TextureFormat,
+};
 
 #[derive(BinRead, BinWrite, PartialEq, Debug, Clone)]
 #[brw(repr = u32)]
@@ -20,8 +26,8 @@ pub enum DXTFormat {
   Lum8 = 57,
 }
 
-impl DXTFormat {
-  pub fn get_block_dim(&self) -> (usize, usize) {
+impl TextureFormat for DXTFormat {
+  fn block_dim(&self) -> (usize, usize) {
     match self {
       DXTFormat::Pal8 => (1, 1),
       DXTFormat::Pal8Mm => (1, 1),
@@ -29,7 +35,7 @@ impl DXTFormat {
       _ => (4, 4),
     }
   }
-  pub fn get_bits_per_pixel(&self) -> usize {
+  fn bits_per_pixel(&self) -> usize {
     match self {
       DXTFormat::Dxt1 => 4,
       DXTFormat::Dxt1Mm => 4,
@@ -37,8 +43,9 @@ impl DXTFormat {
       DXTFormat::Dxt2Mm => 8,
       DXTFormat::Dxt3 => 8,
       DXTFormat::Dxt3Mm => 8,
-      DXTFormat::Dxt4 => 4,
-      DXTFormat::Dxt4Mm => 4,
+      // a 64 bit alpha block before the color block, 16 bytes per 4x4 like Dxt5
+      DXTFormat::Dxt4 => 8,
+      DXTFormat::Dxt4Mm => 8,
       DXTFormat::Dxt5 => 8,
       DXTFormat::Dxt5Mm => 8,
       DXTFormat::Pal8 => 8,
@@ -46,18 +53,142 @@ impl DXTFormat {
       DXTFormat::Lum8 => 8,
     }
   }
+}
+
+impl DXTFormat {
+  #[deprecated(note = "use `TextureFormat::block_dim`")]
+  pub fn get_block_dim(&self) -> (usize, usize) {
+    self.block_dim()
+  }
+
+  #[deprecated(note = "use `TextureFormat::bits_per_pixel`")]
+  pub fn get_bits_per_pixel(&self) -> usize {
+    self.bits_per_pixel()
+  }
+
+  #[deprecated(note = "use `TextureFormat::mip_size`")]
   pub fn calculate_mip_size(&self, width: usize, height: usize) -> usize {
-    let (blk_width_pixels, blk_height_pixels) = self.get_block_dim();
-    let bits_per_pixel = self.get_bits_per_pixel();
-    let blk_size_bytes = ((blk_width_pixels * blk_height_pixels) * bits_per_pixel) / 8;
-    let size_bytes = div_round_up(width, blk_width_pixels)
-      * div_round_up(height, blk_height_pixels)
-      * blk_size_bytes;
+    self.mip_size(width, height)
+  }
+
+  /// Decodes one mip level. `palette` holds the D3DCOLOR entries of the Pal8 formats and is unused
+  /// by the others.
+  pub fn decode_mip(
+    &self,
+    data: &[u8],
+    width: usize,
+    height: usize,
+    palette: &[u8],
+  ) -> Result<RgbaImage> {
+    match self {
+      // the block compressed formats are stored linearly
+      DXTFormat::Dxt1 | DXTFormat::Dxt1Mm => {
+        decode_blocks(data, width, height, 8, decode_dxt1_block)
+      }
+      DXTFormat::Dxt3 | DXTFormat::Dxt3Mm => {
+        decode_blocks(data, width, height, 16, decode_dxt3_block)
+      }
+      DXTFormat::Dxt5 | DXTFormat::Dxt5Mm => {
+        decode_blocks(data, width, height, 16, decode_dxt5_block)
+      }
+      DXTFormat::Dxt2 | DXTFormat::Dxt2Mm => {
+        let mut image = decode_blocks(data, width, height, 16, decode_dxt3_block)?;
+        image.unpremultiply();
+        Ok(image)
+      }
+      DXTFormat::Dxt4 | DXTFormat::Dxt4Mm => {
+        let mut image = decode_blocks(data, width, height, 16, decode_dxt5_block)?;
+        image.unpremultiply();
+        Ok(image)
+      }
+      DXTFormat::Pal8 | DXTFormat::Pal8Mm => decode_swizzled(data, width, height, |index| {
+        let offset = index as usize * 4;
+        // D3DCOLOR is stored as BGRA
+        match palette.get(offset..offset + 4) {
+          Some(&[b, g, r, a]) => Ok([r, g, b, a]),
+          _ => Err(Error::OutOfBounds {
+            context: "texture palette",
+            offset,
+            size: 4,
+            available: palette.len(),
+          }),
+        }
+      }),
+      DXTFormat::Lum8 => decode_swizzled(data, width, height, |l| Ok([l, l, l, 255])),
+    }
+  }
+
+  /// The 8 bit formats are swizzled, which needs power of two dimensions.
+  pub fn is_swizzled(&self) -> bool {
+    matches!(self, DXTFormat::Pal8 | DXTFormat::Pal8Mm | DXTFormat::Lum8)
+  }
 
-    size_bytes
+  /// Encodes one mip level. `palette` holds the D3DCOLOR entries the Pal8 formats index and is
+  /// unused by the others.
+  pub fn encode_mip(
+    &self,
+    image: &RgbaImage,
+    palette: &[u8],
+    quality: EncodeQuality,
+  ) -> Result<Vec<u8>> {
+    let (width, height) = (image.width as usize, image.height as usize);
+    let pixels = image.pixels();
+
+    let data = match self {
+      DXTFormat::Dxt1 | DXTFormat::Dxt1Mm => image_blocks(image)
+        .flat_map(|block| pack_dxt1_block(&encode_dxt1_block(&block, true, quality)))
+        .collect(),
+      DXTFormat::Dxt3 | DXTFormat::Dxt3Mm => image_blocks(image)
+        .flat_map(|block| encode_dxt3_block(&block, quality))
+        .collect(),
+      DXTFormat::Dxt5 | DXTFormat::Dxt5Mm => image_blocks(image)
+        .flat_map(|block| encode_dxt5_block(&block, quality))
+        .collect(),
+      DXTFormat::Dxt2 | DXTFormat::Dxt2Mm => {
+        let mut image = image.clone();
+        image.premultiply();
+        image_blocks(&image)
+          .flat_map(|block| encode_dxt3_block(&block, quality))
+          .collect()
+      }
+      DXTFormat::Dxt4 | DXTFormat::Dxt4Mm => {
+        let mut image = image.clone();
+        image.premultiply();
+        image_blocks(&image)
+          .flat_map(|block| encode_dxt5_block(&block, quality))
+          .collect()
+      }
+      DXTFormat::Pal8 | DXTFormat::Pal8Mm => {
+        // D3DCOLOR is stored as BGRA
+        let palette: Vec<[u8; 4]> = palette
+          .chunks_exact(4)
+          .map(|color| [color[2], color[1], color[0], color[3]])
+          .collect();
+        let indices: Vec<u8> = pixels.map(|pixel| nearest(&palette, pixel) as u8).collect();
+        swizzle(&indices, width, height)?
+      }
+      DXTFormat::Lum8 => swizzle(&pixels.map(luminance).collect::<Vec<_>>(), width, height)?,
+    };
+
+    Ok(data)
   }
 }
 
+// the 8 bit formats are swizzled, one byte per pixel
+fn decode_swizzled(
+  data: &[u8],
+  width: usize,
+  height: usize,
+  pixel: impl Fn(u8) -> Result<[u8; 4]>,
+) -> Result<RgbaImage> {
+  let mut image = RgbaImage::new(width as u32, height as u32);
+  for (i, value) in unswizzle(data, width, height)?.into_iter().enumerate() {
+    image.set_pixel((i % width) as u32, (i / width) as u32, pixel(value)?);
+  }
+
+  Ok(image)
+}
+
 impl std::fmt::Display for DXTFormat {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     match self {
@@ -93,24 +224,124 @@ pub struct DXTSurfaceHeader {
 }
 
 impl DXTTextureHeader {
+  /// Levels are at least 1x1, the smallest ones used to be counted as empty.
+  #[deprecated(note = "use `Texture::image_size`")]
   pub fn calculate_image_size(&self) -> usize {
-    let (blk_width_pixels, blk_height_pixels) = self.format.get_block_dim();
-    let bits_per_pixel = self.format.get_bits_per_pixel();
-    let blk_size_bytes = ((blk_width_pixels * blk_height_pixels) * bits_per_pixel) / 8;
-    let mut size_bytes = div_round_up(self.width as usize, blk_width_pixels)
-      * div_round_up(self.height as usize, blk_height_pixels)
-      * blk_size_bytes;
-
-    for i in 1..self.mip_count {
-      let mip_width = self.width as usize / (2 as usize).pow(i);
-      let mip_height = self.height as usize / (2 as usize).pow(i);
-
-      size_bytes += div_round_up(mip_width as usize, blk_width_pixels)
-        * div_round_up(mip_height as usize, blk_height_pixels)
-        * blk_size_bytes;
+    // the mip chain of these formats is always known
+    self.image_size().unwrap_or_default()
+  }
+
+  /// Decodes every mip level of the streamed texture, biggest first.
+  pub fn decode(&self, streaming_data: &[u8]) -> Result<Vec<RgbaImage>> {
+    self
+      .mip_data(streaming_data)?
+      .into_iter()
+      .map(|(mip, data)| {
+        self
+          .format
+          .decode_mip(data, mip.width, mip.height, &self.palette)
+      })
+      .collect()
+  }
+
+  /// Encodes a mip chain, biggest first, into a streamed texture and its streaming data. The Pal8
+  /// formats get a palette quantized from all levels.
+  pub fn encode(
+    format: DXTFormat,
+    mips: &[RgbaImage],
+    quality: EncodeQuality,
+  ) -> Result<(Self, Vec<u8>)> {
+    check_mip_chain(mips)?;
+    let (width, height) = (mips[0].width, mips[0].height);
+    if format.is_swizzled() && !(width.is_power_of_two() && height.is_power_of_two()) {
+      return Err(Error::InvalidTextureDimensions {
+        width,
+        height,
+        mip_count: mips.len() as u32,
+      });
+    }
+
+    let mut palette = Vec::new();
+    if matches!(format, DXTFormat::Pal8 | DXTFormat::Pal8Mm) {
+      let pixels = mips.iter().flat_map(RgbaImage::pixels);
+      palette = vec![0; 256 * 4];
+      for (entry, color) in palette
+        .chunks_exact_mut(4)
+        .zip(quantize(pixels, 256, quality))
+      {
+        entry.copy_from_slice(&[color[2], color[1], color[0], color[3]]);
+      }
+    }
+
+    let mut streaming_data = Vec::new();
+    for mip in mips {
+      streaming_data.extend(format.encode_mip(mip, &palette, quality)?);
     }
 
-    size_bytes
+    let header = Self {
+      format,
+      palette_size: palette.len() as u32 / 2,
+      palette,
+      mip_count: mips.len() as u32,
+      width,
+      height,
+    };
+    Ok((header, streaming_data))
+  }
+
+  /// Builds the standalone, version 2 texture holding the streaming data's mips.
+  pub fn to_static(&self, streaming_data: &[u8]) -> Result<DXTStaticTextureHeader> {
+    self.check_streaming_data(streaming_data)?;
+
+    let mut mips: Vec<DXTSurface> = self
+      .mips()?
+      .map(|mip| DXTSurface {
+        header: DXTSurfaceHeader {
+          width: mip.width as u32,
+          height: mip.height as u32,
+          size: mip.range.len() as u32,
+        },
+        data: streaming_data[mip.range].to_vec(),
+      })
+      .collect();
+    // mips are streamed biggest first, but standalone files store them smallest first
+    mips.reverse();
+
+    Ok(DXTStaticTextureHeader {
+      version: 2,
+      format: self.format.clone(),
+      palette_size: self.palette_size,
+      palette: self.palette.clone(),
+      mip_count: self.mip_count,
+      width: self.width,
+      height: self.height,
+      mips,
+    })
+  }
+}
+
+impl Texture for DXTTextureHeader {
+  type Mips<'a> = MipChain<'a, DXTFormat>;
+
+  fn width(&self) -> u32 {
+    self.width
+  }
+
+  fn height(&self) -> u32 {
+    self.height
+  }
+
+  fn mip_count(&self) -> u32 {
+    self.mip_count
+  }
+
+  fn mips(&self) -> Result<MipChain<'_, DXTFormat>> {
+    Ok(MipChain::new(
+      &self.format,
+      self.width,
+      self.height,
+      self.mip_count,
+    ))
   }
 }
 
@@ -138,3 +369,75 @@ pub struct DXTStaticTextureHeader {
   #[br(count = mip_count)]
   pub mips: Vec<DXTSurface>,
 }
+
+impl DXTStaticTextureHeader {
+  /// Decodes every mip level, biggest first.
+  pub fn decode(&self) -> Result<Vec<RgbaImage>> {
+    let mut images = self
+      .mips
+      .iter()
+      .map(|mip| {
+        self.format.decode_mip(
+          &mip.data,
+          mip.header.width as usize,
+          mip.header.height as usize,
+          &self.palette,
+        )
+      })
+      .collect::<Result<Vec<_>>>()?;
+
+    // standalone files store the smallest mip first
+    images.sort_by_key(|image| std::cmp::Reverse(image.width as u64 * image.height as u64));
+    Ok(images)
+  }
+
+  /// Splits the texture into a streamed texture header and its streaming data.
+  pub fn to_streaming(&self) -> Result<(DXTTextureHeader, Vec<u8>)> {
+    let mips = self
+      .mips
+      .iter()
+      .map(|mip| {
+        (
+          mip.header.width as usize,
+          mip.header.height as usize,
+          &mip.data[..],
+        )
+      })
+      .collect();
+    let streaming_data = join_mips(mips, &self.format, (self.width, self.height))?;
+
+    let header = DXTTextureHeader {
+      format: self.format.clone(),
+      palette_size: self.palette_size,
+      palette: self.palette.clone(),
+      mip_count: self.mips.len() as u32,
+      width: self.width,
+      height: self.height,
+    };
+    Ok((header, streaming_data))
+  }
+}
+
+impl StreamingTexture<DXTTextureHeader> {
+  /// Turns the streamed texture into an always resident one.
+  pub fn to_static(&self, streaming_data: &[u8]) -> Result<StaticTexture<DXTStaticTextureHeader>> {
+    Ok(StaticTexture {
+      model_info: self.model_info.clone(),
+      static_texture_header: self.header.to_static(streaming_data)?,
+    })
+  }
+}
+
+impl StaticTexture<DXTStaticTextureHeader> {
+  /// Turns the texture into a streamed one and its streaming data.
+  pub fn to_streaming(&self) -> Result<(StreamingTexture<DXTTextureHeader>, Vec<u8>)> {
+    let (header, streaming_data) = self.static_texture_header.to_streaming()?;
+    let texture = StreamingTexture {
+      model_info: self.model_info.clone(),
+      // the field is padding on xbox, nothing reads it
+      version: 0,
+      header,
+    };
+    Ok((texture, streaming_data))
+  }
+}
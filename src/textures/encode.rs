@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+
+use crate::error::{Error, Result};
+use crate::RgbaImage;
+
+/// Trade-off between encoding speed and quality of the lossy formats.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum EncodeQuality {
+  /// Block endpoints from the colors' bounding box.
+  Fast,
+  /// Block endpoints along the colors' principal axis.
+  #[default]
+  Normal,
+  /// Like `Normal` with refined endpoints, and refined palettes.
+  High,
+}
+
+fn distance(a: [u8; 4], b: [u8; 4]) -> u32 {
+  (0..4)
+    .map(|i| (a[i] as i32 - b[i] as i32).pow(2) as u32)
+    .sum()
+}
+
+/// Index of the palette color closest to `color`.
+pub(crate) fn nearest(palette: &[[u8; 4]], color: [u8; 4]) -> usize {
+  palette
+    .iter()
+    .enumerate()
+    .min_by_key(|&(_, &entry)| distance(entry, color))
+    .map(|(index, _)| index)
+    .unwrap_or(0)
+}
+
+fn average(colors: &[([u8; 4], u32)]) -> [u8; 4] {
+  let count: u64 = colors.iter().map(|&(_, count)| count as u64).sum();
+  std::array::from_fn(|i| {
+    let sum: u64 = colors
+      .iter()
+      .map(|&(color, count)| color[i] as u64 * count as u64)
+      .sum();
+    ((sum + count / 2) / count.max(1)) as u8
+  })
+}
+
+/// Picks up to `max_colors` colors representing `pixels` by median cut.
+pub(crate) fn quantize(
+  pixels: impl Iterator<Item = [u8; 4]>,
+  max_colors: usize,
+  quality: EncodeQuality,
+) -> Vec<[u8; 4]> {
+  let mut counts = HashMap::new();
+  for pixel in pixels {
+    *counts.entry(pixel).or_insert(0u32) += 1;
+  }
+  let mut colors: Vec<([u8; 4], u32)> = counts.into_iter().collect();
+  colors.sort_unstable();
+
+  if colors.len() <= max_colors {
+    return colors.into_iter().map(|(color, _)| color).collect();
+  }
+
+  // repeatedly split the box with the widest channel at its median
+  let mut boxes = vec![colors];
+  while boxes.len() < max_colors {
+    let widest = boxes
+      .iter()
+      .enumerate()
+      .filter(|(_, colors)| colors.len() > 1)
+      .map(|(index, colors)| {
+        let (channel, range) = (0..4)
+          .map(|i| {
+            let min = colors.iter().map(|(color, _)| color[i]).min().unwrap();
+            let max = colors.iter().map(|(color, _)| color[i]).max().unwrap();
+            (i, max - min)
+          })
+          .max_by_key(|&(_, range)| range)
+          .unwrap();
+        (index, channel, range)
+      })
+      .max_by_key(|&(_, _, range)| range);
+    let Some((index, channel, _)) = widest else {
+      break;
+    };
+
+    let mut colors = boxes.swap_remove(index);
+    colors.sort_unstable_by_key(|(color, _)| color[channel]);
+    let total: u64 = colors.iter().map(|&(_, count)| count as u64).sum();
+    let mut seen = 0;
+    let median = colors
+      .iter()
+      .position(|&(_, count)| {
+        seen += count as u64;
+        seen * 2 >= total
+      })
+      .unwrap_or(0)
+      .clamp(0, colors.len() - 2);
+
+    let upper = colors.split_off(median + 1);
+    boxes.push(colors);
+    boxes.push(upper);
+  }
+
+  let mut palette: Vec<[u8; 4]> = boxes.iter().map(|colors| average(colors)).collect();
+
+  if quality == EncodeQuality::High {
+    // a few k-means rounds move the entries to the center of the colors they end up representing
+    let colors: Vec<([u8; 4], u32)> = boxes.into_iter().flatten().collect();
+    for _ in 0..4 {
+      let mut clusters = vec![Vec::new(); palette.len()];
+      for &(color, count) in &colors {
+        clusters[nearest(&palette, color)].push((color, count));
+      }
+      for (entry, cluster) in palette.iter_mut().zip(&clusters) {
+        if !cluster.is_empty() {
+          *entry = average(cluster);
+        }
+      }
+    }
+  }
+
+  palette
+}
+
+pub(crate) fn luminance(pixel: [u8; 4]) -> u8 {
+  ((pixel[0] as u32 * 299 + pixel[1] as u32 * 587 + pixel[2] as u32 * 114 + 500) / 1000) as u8
+}
+
+/// Checks that `mips` is a mip chain, each level half the size of the previous one down to 1.
+pub(crate) fn check_mip_chain(mips: &[RgbaImage]) -> Result<()> {
+  let Some(top) = mips.first() else {
+    return Err(Error::MissingField { field: "mips" });
+  };
+
+  let is_chain = mips.iter().enumerate().all(|(level, mip)| {
+    mip.width == 1.max(top.width >> level) && mip.height == 1.max(top.height >> level)
+  });
+  if top.width == 0 || top.height == 0 || !is_chain {
+    return Err(Error::InvalidTextureDimensions {
+      width: top.width,
+      height: top.height,
+      mip_count: mips.len() as u32,
+    });
+  }
+
+  Ok(())
+}
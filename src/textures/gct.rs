@@ -1,6 +1,13 @@
 use binrw::*;
 
+use crate::convert::join_mips;
 use crate::div_round_up;
+use crate::error::{Error, Result};
+use crate::textures::block::*;
+use crate::textures::encode::*;
+use crate::{
+  EncodeQuality, MipChain, RgbaImage, StaticTexture, StreamingTexture, Texture, TextureFormat,
+};
 
 #[derive(BinRead, BinWrite, PartialEq, Debug, Clone)]
 #[brw(repr = u32)]
@@ -13,8 +20,8 @@ pub enum GCTFormat {
   I8 = 0x3C,
 }
 
-impl GCTFormat {
-  pub fn get_block_dim(&self) -> (usize, usize) {
+impl TextureFormat for GCTFormat {
+  fn block_dim(&self) -> (usize, usize) {
     match self {
       GCTFormat::Rgba8 => (4, 4),
       GCTFormat::Cmpr => (8, 8),
@@ -24,7 +31,7 @@ impl GCTFormat {
       GCTFormat::I8 => (8, 4),
     }
   }
-  pub fn get_bits_per_pixel(&self) -> usize {
+  fn bits_per_pixel(&self) -> usize {
     match self {
       GCTFormat::Rgba8 => 32,
       GCTFormat::Cmpr => 4,
@@ -34,15 +41,230 @@ impl GCTFormat {
       GCTFormat::I8 => 8,
     }
   }
+}
+
+impl GCTFormat {
+  #[deprecated(note = "use `TextureFormat::block_dim`")]
+  pub fn get_block_dim(&self) -> (usize, usize) {
+    self.block_dim()
+  }
+
+  #[deprecated(note = "use `TextureFormat::bits_per_pixel`")]
+  pub fn get_bits_per_pixel(&self) -> usize {
+    self.bits_per_pixel()
+  }
+
+  #[deprecated(note = "use `TextureFormat::mip_size`")]
   pub fn calculate_mip_size(&self, width: usize, height: usize) -> usize {
-    let (blk_width_pixels, blk_height_pixels) = self.get_block_dim();
-    let bits_per_pixel = self.get_bits_per_pixel();
-    let blk_size_bytes = ((blk_width_pixels * blk_height_pixels) * bits_per_pixel) / 8;
-    let size_bytes = div_round_up(width, blk_width_pixels)
-      * div_round_up(height, blk_height_pixels)
-      * blk_size_bytes;
+    self.mip_size(width, height)
+  }
+
+  /// Decodes one mip level. `palette` holds the Ci8 formats' 16 bit entries in `palette_format`
+  /// and is unused by the others.
+  pub fn decode_mip(
+    &self,
+    data: &[u8],
+    width: usize,
+    height: usize,
+    palette: &[u8],
+    palette_format: GXPaletteFormat,
+  ) -> Result<RgbaImage> {
+    match self {
+      GCTFormat::Rgba8 => decode_tiles(data, width, height, (4, 4), 64, |tile| {
+        // the first half of a tile holds the AR pairs, the second the GB pairs
+        (0..16)
+          .map(|i| {
+            let (ar, gb) = (&tile[i * 2..], &tile[32 + i * 2..]);
+            Ok([ar[1], gb[0], gb[1], ar[0]])
+          })
+          .collect()
+      }),
+      GCTFormat::Cmpr | GCTFormat::CmprMm => {
+        decode_tiles(data, width, height, (8, 8), 32, |tile| {
+          // 2x2 DXT1 blocks with big endian colors and the indices' first pixel in the high bits
+          let blocks: Vec<_> = tile.chunks_exact(8).map(decode_cmpr_block).collect();
+          Ok(
+            (0..64)
+              .map(|i| {
+                let (x, y) = (i % 8, i / 8);
+                blocks[y / 4 * 2 + x / 4][y % 4 * 4 + x % 4]
+              })
+              .collect(),
+          )
+        })
+      }
+      GCTFormat::Ci8 | GCTFormat::Ci8Mm => decode_tiles(data, width, height, (8, 4), 32, |tile| {
+        tile
+          .iter()
+          .map(|&index| {
+            let offset = index as usize * 2;
+            match palette.get(offset..offset + 2) {
+              Some(&[hi, lo]) => Ok(palette_format.decode(u16::from_be_bytes([hi, lo]))),
+              _ => Err(Error::OutOfBounds {
+                context: "texture palette",
+                offset,
+                size: 2,
+                available: palette.len(),
+              }),
+            }
+          })
+          .collect()
+      }),
+      GCTFormat::I8 => decode_tiles(data, width, height, (8, 4), 32, |tile| {
+        Ok(tile.iter().map(|&i| [i, i, i, i]).collect())
+      }),
+    }
+  }
+
+  /// Encodes one mip level. `palette` holds the 16 bit entries in `palette_format` the Ci8 formats
+  /// index and is unused by the others.
+  pub fn encode_mip(
+    &self,
+    image: &RgbaImage,
+    palette: &[u8],
+    palette_format: GXPaletteFormat,
+    quality: EncodeQuality,
+  ) -> Vec<u8> {
+    match self {
+      GCTFormat::Rgba8 => tiles(image, 4, 4)
+        .flat_map(|tile| {
+          // the AR pairs of the whole tile, then the GB pairs
+          let ar = tile.iter().flat_map(|pixel| [pixel[3], pixel[0]]);
+          let gb = tile.iter().flat_map(|pixel| [pixel[1], pixel[2]]);
+          ar.chain(gb).collect::<Vec<_>>()
+        })
+        .collect(),
+      GCTFormat::Cmpr | GCTFormat::CmprMm => tiles(image, 8, 8)
+        .flat_map(|tile| {
+          (0..4)
+            .flat_map(|block| {
+              let (block_x, block_y) = (block % 2 * 4, block / 2 * 4);
+              let pixels = std::array::from_fn(|i| tile[(block_y + i / 4) * 8 + block_x + i % 4]);
+              pack_cmpr_block(&encode_dxt1_block(&pixels, true, quality))
+            })
+            .collect::<Vec<_>>()
+        })
+        .collect(),
+      GCTFormat::Ci8 | GCTFormat::Ci8Mm => {
+        let palette: Vec<[u8; 4]> = palette
+          .chunks_exact(2)
+          .map(|entry| palette_format.decode(u16::from_be_bytes([entry[0], entry[1]])))
+          .collect();
+        tiles(image, 8, 4)
+          .flatten()
+          .map(|pixel| nearest(&palette, pixel) as u8)
+          .collect()
+      }
+      GCTFormat::I8 => tiles(image, 8, 4).flatten().map(luminance).collect(),
+    }
+  }
+}
 
-    size_bytes
+fn pack_cmpr_block(block: &Dxt1Block) -> [u8; 8] {
+  let mut packed = [0; 8];
+  packed[..2].copy_from_slice(&block.color0.to_be_bytes());
+  packed[2..4].copy_from_slice(&block.color1.to_be_bytes());
+  for (row, indices) in packed[4..].iter_mut().zip(block.indices.chunks_exact(4)) {
+    *row = indices.iter().fold(0, |bits, &index| bits << 2 | index);
+  }
+  packed
+}
+
+fn decode_cmpr_block(block: &[u8]) -> Block {
+  let colors = dxt1_colors(
+    u16::from_be_bytes([block[0], block[1]]),
+    u16::from_be_bytes([block[2], block[3]]),
+    true,
+  );
+
+  std::array::from_fn(|i| {
+    let row = block[4 + i / 4];
+    colors[(row >> (6 - (i % 4) * 2)) as usize & 3]
+  })
+}
+
+// GX textures are stored as rows of tiles, `decode_tile` returns a tile's pixels in row order
+fn decode_tiles(
+  data: &[u8],
+  width: usize,
+  height: usize,
+  (tile_width, tile_height): (usize, usize),
+  tile_size: usize,
+  decode_tile: impl Fn(&[u8]) -> Result<Vec<[u8; 4]>>,
+) -> Result<RgbaImage> {
+  let tiles_wide = div_round_up(width, tile_width);
+  let tiles_high = div_round_up(height, tile_height);
+  check_size(data, tiles_wide * tiles_high * tile_size)?;
+
+  let mut image = RgbaImage::new(width as u32, height as u32);
+  for (i, tile) in data
+    .chunks_exact(tile_size)
+    .take(tiles_wide * tiles_high)
+    .enumerate()
+  {
+    let (tile_x, tile_y) = (
+      (i % tiles_wide) * tile_width,
+      (i / tiles_wide) * tile_height,
+    );
+    for (j, pixel) in decode_tile(tile)?.into_iter().enumerate() {
+      let (x, y) = (tile_x + j % tile_width, tile_y + j / tile_width);
+      if x < width && y < height {
+        image.set_pixel(x as u32, y as u32, pixel);
+      }
+    }
+  }
+
+  Ok(image)
+}
+
+/// GX formats of Ci8 palette entries. The texture headers don't record it, so callers have to know
+/// which one a texture uses.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum GXPaletteFormat {
+  /// 8 bit alpha followed by 8 bit intensity.
+  IA8,
+  RGB565,
+  /// RGB555 when the top bit is set, otherwise 3 bit alpha and RGB444.
+  RGB5A3,
+}
+
+impl GXPaletteFormat {
+  pub fn decode(&self, entry: u16) -> [u8; 4] {
+    match self {
+      GXPaletteFormat::IA8 => {
+        let (a, i) = ((entry >> 8) as u8, entry as u8);
+        [i, i, i, a]
+      }
+      GXPaletteFormat::RGB565 => rgb565(entry),
+      GXPaletteFormat::RGB5A3 if entry & 0x8000 != 0 => {
+        let channel = |shift: u16| {
+          let value = (entry >> shift) & 0x1f;
+          (value << 3 | value >> 2) as u8
+        };
+        [channel(10), channel(5), channel(0), 255]
+      }
+      GXPaletteFormat::RGB5A3 => {
+        let alpha = (entry >> 12) & 0x7;
+        let channel = |shift: u16| ((entry >> shift) & 0xf) as u8 * 17;
+        [
+          channel(8),
+          channel(4),
+          channel(0),
+          (alpha << 5 | alpha << 2 | alpha >> 1) as u8,
+        ]
+      }
+    }
+  }
+
+  pub fn encode(&self, pixel: [u8; 4]) -> u16 {
+    let [r, g, b, a] = pixel.map(|channel| channel as u16);
+    match self {
+      GXPaletteFormat::IA8 => (a << 8) | luminance(pixel) as u16,
+      GXPaletteFormat::RGB565 => (r >> 3) << 11 | (g >> 2) << 5 | b >> 3,
+      // alpha that would round to 7 is stored opaque with the extra color bit
+      GXPaletteFormat::RGB5A3 if a >= 0xf0 => 0x8000 | (r >> 3) << 10 | (g >> 3) << 5 | b >> 3,
+      GXPaletteFormat::RGB5A3 => (a >> 5) << 12 | (r >> 4) << 8 | (g >> 4) << 4 | b >> 4,
+    }
   }
 }
 
@@ -78,24 +300,121 @@ pub struct GCTSurfaceHeader {
 }
 
 impl GCNTextureHeader {
+  /// Levels are at least 1x1, the smallest ones used to be counted as empty.
+  #[deprecated(note = "use `Texture::image_size`")]
   pub fn calculate_image_size(&self) -> usize {
-    let (blk_width_pixels, blk_height_pixels) = self.format.get_block_dim();
-    let bits_per_pixel = self.format.get_bits_per_pixel();
-    let blk_size_bytes = ((blk_width_pixels * blk_height_pixels) * bits_per_pixel) / 8;
-    let mut size_bytes = div_round_up(self.width as usize, blk_width_pixels)
-      * div_round_up(self.height as usize, blk_height_pixels)
-      * blk_size_bytes;
-
-    for i in 1..self.mip_count {
-      let mip_width = self.width as usize / (2 as usize).pow(i);
-      let mip_height = self.height as usize / (2 as usize).pow(i);
-
-      size_bytes += div_round_up(mip_width as usize, blk_width_pixels)
-        * div_round_up(mip_height as usize, blk_height_pixels)
-        * blk_size_bytes;
+    // the mip chain of these formats is always known
+    self.image_size().unwrap_or_default()
+  }
+
+  /// Decodes every mip level of the streamed texture, biggest first.
+  pub fn decode(
+    &self,
+    streaming_data: &[u8],
+    palette_format: GXPaletteFormat,
+  ) -> Result<Vec<RgbaImage>> {
+    self
+      .mip_data(streaming_data)?
+      .into_iter()
+      .map(|(mip, data)| {
+        self
+          .format
+          .decode_mip(data, mip.width, mip.height, &self.palette, palette_format)
+      })
+      .collect()
+  }
+
+  /// Encodes a mip chain, biggest first, into a streamed texture and its streaming data. The Ci8
+  /// formats get a palette quantized from all levels, stored in `palette_format`.
+  pub fn encode(
+    format: GCTFormat,
+    mips: &[RgbaImage],
+    quality: EncodeQuality,
+    palette_format: GXPaletteFormat,
+  ) -> Result<(Self, Vec<u8>)> {
+    check_mip_chain(mips)?;
+
+    let mut palette = Vec::new();
+    if matches!(format, GCTFormat::Ci8 | GCTFormat::Ci8Mm) {
+      let pixels = mips.iter().flat_map(RgbaImage::pixels);
+      palette = vec![0; 256 * 2];
+      for (entry, color) in palette
+        .chunks_exact_mut(2)
+        .zip(quantize(pixels, 256, quality))
+      {
+        entry.copy_from_slice(&palette_format.encode(color).to_be_bytes());
+      }
     }
 
-    size_bytes
+    let streaming_data = mips
+      .iter()
+      .flat_map(|mip| format.encode_mip(mip, &palette, palette_format, quality))
+      .collect();
+
+    let header = Self {
+      format,
+      palette_size: palette.len() as u32 / 2,
+      palette,
+      mip_count: mips.len() as u32,
+      width: mips[0].width,
+      height: mips[0].height,
+    };
+    Ok((header, streaming_data))
+  }
+
+  /// Builds the standalone, version 2 texture holding the streaming data's mips.
+  pub fn to_static(&self, streaming_data: &[u8]) -> Result<GCNStaticTextureHeader> {
+    self.check_streaming_data(streaming_data)?;
+
+    let mut mips: Vec<GCTSurface> = self
+      .mips()?
+      .map(|mip| GCTSurface {
+        header: GCTSurfaceHeader {
+          width: mip.width as u32,
+          height: mip.height as u32,
+          size: mip.range.len() as u32,
+        },
+        data: streaming_data[mip.range].to_vec(),
+      })
+      .collect();
+    // mips are streamed biggest first, but standalone files store them smallest first
+    mips.reverse();
+
+    Ok(GCNStaticTextureHeader {
+      version: 2,
+      format: self.format.clone(),
+      palette_size: self.palette_size,
+      palette: self.palette.clone(),
+      mip_count: self.mip_count,
+      width: self.width,
+      height: self.height,
+      mips,
+    })
+  }
+}
+
+impl Texture for GCNTextureHeader {
+  type Mips<'a> = MipChain<'a, GCTFormat>;
+
+  fn width(&self) -> u32 {
+    self.width
+  }
+
+  fn height(&self) -> u32 {
+    self.height
+  }
+
+  fn mip_count(&self) -> u32 {
+    self.mip_count
+  }
+
+  fn mips(&self) -> Result<MipChain<'_, GCTFormat>> {
+    Ok(MipChain::new(
+      &self.format,
+      self.width,
+      self.height,
+      self.mip_count,
+    ))
   }
 }
 
@@ -123,3 +442,79 @@ pub struct GCNStaticTextureHeader {
   #[br(count = mip_count)]
   pub mips: Vec<GCTSurface>,
 }
+
+impl GCNStaticTextureHeader {
+  /// Decodes every mip level, biggest first.
+  pub fn decode(&self, palette_format: GXPaletteFormat) -> Result<Vec<RgbaImage>> {
+    let mut images = self
+      .mips
+      .iter()
+      .map(|mip| {
+        self.format.decode_mip(
+          &mip.data,
+          mip.header.width as usize,
+          mip.header.height as usize,
+          &self.palette,
+          palette_format,
+        )
+      })
+      .collect::<Result<Vec<_>>>()?;
+
+    // standalone files store the smallest mip first
+    images.sort_by_key(|image| std::cmp::Reverse(image.width as u64 * image.height as u64));
+    Ok(images)
+  }
+
+  /// Splits the texture into a streamed texture header and its streaming data.
+  pub fn to_streaming(&self) -> Result<(GCNTextureHeader, Vec<u8>)> {
+    let mips = self
+      .mips
+      .iter()
+      .map(|mip| {
+        (
+          mip.header.width as usize,
+          mip.header.height as usize,
+          &mip.data[..],
+        )
+      })
+      .collect();
+    let streaming_data = join_mips(mips, &self.format, (self.width, self.height))?;
+
+    let header = GCNTextureHeader {
+      format: self.format.clone(),
+      palette_size: self.palette_size,
+      palette: self.palette.clone(),
+      mip_count: self.mips.len() as u32,
+      width: self.width,
+      height: self.height,
+    };
+    Ok((header, streaming_data))
+  }
+}
+
+impl StreamingTexture<GCNTextureHeader> {
+  /// Turns the streamed texture into an always resident one.
+  pub fn to_static(&self, streaming_data: &[u8]) -> Result<StaticTexture<GCNStaticTextureHeader>> {
+    Ok(StaticTexture {
+      model_info: self.model_info.clone(),
+      static_texture_header: self.header.to_static(streaming_data)?,
+    })
+  }
+}
+
+impl StaticTexture<GCNStaticTextureHeader> {
+  /// Turns the texture into a streamed one and its streaming data. Streamed textures have a flag
+  /// OR'd on the static texture's version that isn't documented, so `version` is stored as is.
+  pub fn to_streaming(
+    &self,
+    version: u32,
+  ) -> Result<(StreamingTexture<GCNTextureHeader>, Vec<u8>)> {
+    let (header, streaming_data) = self.static_texture_header.to_streaming()?;
+    let texture = StreamingTexture {
+      model_info: self.model_info.clone(),
+      version,
+      header,
+    };
+    Ok((texture, streaming_data))
+  }
+}
@@ -0,0 +1,125 @@
+use std::ops::Range;
+
+use crate::div_round_up;
+use crate::error::{slice_checked, Error, Result};
+
+/// Pixel formats stored in blocks of a fixed size.
+pub trait TextureFormat {
+  /// Width and height of a block in pixels.
+  fn block_dim(&self) -> (usize, usize);
+  fn bits_per_pixel(&self) -> usize;
+
+  /// Size in bytes of a `width` by `height` mip level.
+  fn mip_size(&self, width: usize, height: usize) -> usize {
+    let (block_width, block_height) = self.block_dim();
+    let block_size = block_width * block_height * self.bits_per_pixel() / 8;
+    div_round_up(width, block_width) * div_round_up(height, block_height) * block_size
+  }
+}
+
+/// One level of a texture's mip chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MipLevel {
+  pub level: u32,
+  pub width: usize,
+  pub height: usize,
+  /// Where the level is stored in the streaming data.
+  pub range: Range<usize>,
+}
+
+/// The levels of a mip chain stored back to back, biggest first like they're streamed. Each level
+/// is half the size of the previous one, down to 1.
+#[derive(Debug, Clone)]
+pub struct MipChain<'a, F: TextureFormat> {
+  format: &'a F,
+  width: u32,
+  height: u32,
+  levels: Range<u32>,
+  offset: usize,
+}
+
+impl<'a, F: TextureFormat> MipChain<'a, F> {
+  pub fn new(format: &'a F, width: u32, height: u32, mip_count: u32) -> Self {
+    Self {
+      format,
+      width,
+      height,
+      levels: 0..mip_count,
+      offset: 0,
+    }
+  }
+}
+
+impl<F: TextureFormat> Iterator for MipChain<'_, F> {
+  type Item = MipLevel;
+
+  fn next(&mut self) -> Option<MipLevel> {
+    let level = self.levels.next()?;
+    let width = 1.max(self.width.checked_shr(level).unwrap_or(0) as usize);
+    let height = 1.max(self.height.checked_shr(level).unwrap_or(0) as usize);
+
+    let start = self.offset;
+    self.offset += self.format.mip_size(width, height);
+    Some(MipLevel {
+      level,
+      width,
+      height,
+      range: start..self.offset,
+    })
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    self.levels.size_hint()
+  }
+}
+
+impl<F: TextureFormat> ExactSizeIterator for MipChain<'_, F> {}
+
+/// Streamed textures, whatever the platform. Their streaming data holds a chain of mip levels.
+pub trait Texture {
+  type Mips<'a>: Iterator<Item = MipLevel>
+  where
+    Self: 'a;
+
+  fn width(&self) -> u32;
+  fn height(&self) -> u32;
+  fn mip_count(&self) -> u32;
+
+  /// Every mip level, biggest first. Fails for formats whose layout isn't known.
+  fn mips(&self) -> Result<Self::Mips<'_>>;
+
+  /// Size in bytes of the streaming data holding every level.
+  fn image_size(&self) -> Result<usize> {
+    Ok(self.mips()?.map(|mip| mip.range.end).max().unwrap_or(0))
+  }
+
+  /// Checks that the streaming data holds exactly the texture's mip levels.
+  fn check_streaming_data(&self, streaming_data: &[u8]) -> Result<()> {
+    let size = self.image_size()?;
+    if size != streaming_data.len() {
+      return Err(Error::SizeMismatch {
+        context: "texture streaming data",
+        expected: size,
+        actual: streaming_data.len(),
+      });
+    }
+
+    Ok(())
+  }
+
+  /// Every mip level, biggest first, along with its data.
+  fn mip_data<'a>(&self, streaming_data: &'a [u8]) -> Result<Vec<(MipLevel, &'a [u8])>> {
+    self
+      .mips()?
+      .map(|mip| {
+        let data = slice_checked(
+          streaming_data,
+          mip.range.start,
+          mip.range.len(),
+          "texture streaming data",
+        )?;
+        Ok((mip, data))
+      })
+      .collect()
+  }
+}
@@ -1,6 +1,16 @@
+mod block;
 mod dds;
 mod dxt;
+mod encode;
 mod gct;
+mod mips;
+mod png;
+mod rgba;
+mod x360;
 pub use self::dds::*;
 pub use self::dxt::*;
+pub use self::encode::*;
 pub use self::gct::*;
+pub use self::mips::*;
+pub use self::rgba::*;
+pub use self::x360::*;
@@ -0,0 +1,43 @@
+use std::io::{self, Write};
+
+use flate2::write::ZlibEncoder;
+use flate2::{Compression, Crc};
+
+use crate::RgbaImage;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+fn write_chunk<W: Write>(writer: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+  let mut crc = Crc::new();
+  crc.update(kind);
+  crc.update(data);
+
+  writer.write_all(&(data.len() as u32).to_be_bytes())?;
+  writer.write_all(kind)?;
+  writer.write_all(data)?;
+  writer.write_all(&crc.sum().to_be_bytes())
+}
+
+impl RgbaImage {
+  /// Writes the image as an 8 bit RGBA PNG.
+  pub fn write_png<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+    writer.write_all(&PNG_SIGNATURE)?;
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&self.width.to_be_bytes());
+    header.extend_from_slice(&self.height.to_be_bytes());
+    // bit depth 8, color type RGBA, default compression, filtering and no interlacing
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+    write_chunk(writer, b"IHDR", &header)?;
+
+    // every row starts with its filter type, always none
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    for row in self.data.chunks_exact(self.width.max(1) as usize * 4) {
+      encoder.write_all(&[0])?;
+      encoder.write_all(row)?;
+    }
+    write_chunk(writer, b"IDAT", &encoder.finish()?)?;
+
+    write_chunk(writer, b"IEND", &[])
+  }
+}
@@ -0,0 +1,234 @@
+/// Filter used to compute smaller mip levels.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MipFilter {
+  /// Averages each 2x2 square.
+  #[default]
+  Box,
+  /// Kaiser windowed sinc, keeps smaller levels sharper than the box filter.
+  Kaiser,
+}
+
+impl MipFilter {
+  // weight of a source pixel `t` destination half-pixels from the center
+  fn weight(&self, t: f32) -> f32 {
+    match self {
+      MipFilter::Box => (t.abs() <= 1.0) as u8 as f32,
+      MipFilter::Kaiser => {
+        const RADIUS: f32 = 3.0;
+        const ALPHA: f32 = 4.0;
+        if t.abs() >= RADIUS {
+          return 0.0;
+        }
+        let window = bessel_i0(ALPHA * (1.0 - (t / RADIUS).powi(2)).sqrt()) / bessel_i0(ALPHA);
+        sinc(t / 2.0) * window
+      }
+    }
+  }
+
+  fn radius(&self) -> f32 {
+    match self {
+      MipFilter::Box => 1.0,
+      MipFilter::Kaiser => 3.0,
+    }
+  }
+}
+
+fn sinc(x: f32) -> f32 {
+  if x.abs() < f32::EPSILON {
+    1.0
+  } else {
+    let x = x * std::f32::consts::PI;
+    x.sin() / x
+  }
+}
+
+fn bessel_i0(x: f32) -> f32 {
+  let mut sum = 1.0;
+  let mut term = 1.0;
+  for k in 1..16 {
+    term *= (x / (2.0 * k as f32)).powi(2);
+    sum += term;
+  }
+  sum
+}
+
+// resamples `count` lines of `src_len` values spaced `stride` apart, each value 4 channels
+fn resample(
+  data: &[f32],
+  (src_len, dst_len): (usize, usize),
+  (count, line_stride, stride): (usize, usize, usize),
+  filter: MipFilter,
+) -> Vec<f32> {
+  let scale = src_len as f32 / dst_len as f32;
+  // (first source pixel, weights) of each destination pixel
+  let kernels: Vec<(isize, Vec<f32>)> = (0..dst_len)
+    .map(|i| {
+      let center = (i as f32 + 0.5) * scale;
+      let support = filter.radius() * scale / 2.0;
+      let first = (center - support).floor() as isize;
+      let last = (center + support).ceil() as isize;
+      let weights: Vec<f32> = (first..=last)
+        .map(|j| filter.weight((j as f32 + 0.5 - center) / scale * 2.0))
+        .collect();
+      let total: f32 = weights.iter().sum();
+      (first, weights.iter().map(|weight| weight / total).collect())
+    })
+    .collect();
+
+  let mut output = vec![0.0; count * dst_len * 4];
+  for line in 0..count {
+    for (i, (first, weights)) in kernels.iter().enumerate() {
+      let out = (line * dst_len + i) * 4;
+      for (k, weight) in weights.iter().enumerate() {
+        let j = (first + k as isize).clamp(0, src_len as isize - 1) as usize;
+        let src = line * line_stride + j * stride;
+        for channel in 0..4 {
+          output[out + channel] += data[src + channel] * weight;
+        }
+      }
+    }
+  }
+
+  output
+}
+
+/// A decoded texture level, 8 bits per channel RGBA with rows stored top to bottom.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RgbaImage {
+  pub width: u32,
+  pub height: u32,
+  pub data: Vec<u8>,
+}
+
+impl RgbaImage {
+  /// A fully transparent black image.
+  pub fn new(width: u32, height: u32) -> Self {
+    Self {
+      width,
+      height,
+      data: vec![0; width as usize * height as usize * 4],
+    }
+  }
+
+  pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+    let offset = self.offset(x, y);
+    self.data[offset..offset + 4].try_into().unwrap()
+  }
+
+  /// Every pixel in row order.
+  pub fn pixels(&self) -> impl Iterator<Item = [u8; 4]> + '_ {
+    self
+      .data
+      .chunks_exact(4)
+      .map(|pixel| [pixel[0], pixel[1], pixel[2], pixel[3]])
+  }
+
+  pub fn set_pixel(&mut self, x: u32, y: u32, pixel: [u8; 4]) {
+    let offset = self.offset(x, y);
+    self.data[offset..offset + 4].copy_from_slice(&pixel);
+  }
+
+  fn offset(&self, x: u32, y: u32) -> usize {
+    (y as usize * self.width as usize + x as usize) * 4
+  }
+
+  // DXT2 and DXT4 store colors multiplied by alpha
+  pub(crate) fn unpremultiply(&mut self) {
+    for pixel in self.data.chunks_exact_mut(4) {
+      let alpha = pixel[3] as u32;
+      if alpha == 0 || alpha == 255 {
+        continue;
+      }
+
+      for channel in &mut pixel[..3] {
+        *channel = ((*channel as u32 * 255 + alpha / 2) / alpha).min(255) as u8;
+      }
+    }
+  }
+
+  pub(crate) fn premultiply(&mut self) {
+    for pixel in self.data.chunks_exact_mut(4) {
+      let alpha = pixel[3] as u32;
+      for channel in &mut pixel[..3] {
+        *channel = ((*channel as u32 * alpha + 127) / 255) as u8;
+      }
+    }
+  }
+
+  /// The next smaller mip level, half the size in each dimension down to 1.
+  pub fn downsample(&self, filter: MipFilter) -> Self {
+    let (width, height) = (self.width as usize, self.height as usize);
+    let (mip_width, mip_height) = (1.max(width / 2), 1.max(height / 2));
+
+    // filtering premultiplied colors keeps transparent pixels from bleeding into the others
+    let mut data: Vec<f32> = self
+      .data
+      .chunks_exact(4)
+      .flat_map(|pixel| {
+        let alpha = pixel[3] as f32 / 255.0;
+        [
+          pixel[0] as f32 * alpha,
+          pixel[1] as f32 * alpha,
+          pixel[2] as f32 * alpha,
+          pixel[3] as f32,
+        ]
+      })
+      .collect();
+
+    if mip_width != width {
+      // rows stay rows, the output is mip_width values per row
+      data = resample(&data, (width, mip_width), (height, width * 4, 4), filter);
+    }
+    if mip_height != height {
+      let columns = resample(
+        &data,
+        (height, mip_height),
+        (mip_width, 4, mip_width * 4),
+        filter,
+      );
+      // the columns come out one after the other, transpose them back into rows
+      data = vec![0.0; mip_width * mip_height * 4];
+      for x in 0..mip_width {
+        for y in 0..mip_height {
+          let (src, dst) = ((x * mip_height + y) * 4, (y * mip_width + x) * 4);
+          data[dst..dst + 4].copy_from_slice(&columns[src..src + 4]);
+        }
+      }
+    }
+
+    let data = data
+      .chunks_exact(4)
+      .flat_map(|pixel| {
+        let alpha = pixel[3].clamp(0.0, 255.0);
+        let color = |value: f32| {
+          if alpha > 0.0 {
+            (value * 255.0 / alpha).round().clamp(0.0, 255.0) as u8
+          } else {
+            0
+          }
+        };
+        [
+          color(pixel[0]),
+          color(pixel[1]),
+          color(pixel[2]),
+          alpha.round() as u8,
+        ]
+      })
+      .collect();
+
+    Self {
+      width: mip_width as u32,
+      height: mip_height as u32,
+      data,
+    }
+  }
+
+  /// This image followed by every smaller mip level down to 1x1.
+  pub fn mip_chain(&self, filter: MipFilter) -> Vec<Self> {
+    let mut mips = vec![self.clone()];
+    while let Some(mip) = mips.last().filter(|mip| mip.width > 1 || mip.height > 1) {
+      mips.push(mip.downsample(filter));
+    }
+    mips
+  }
+}
@@ -0,0 +1,658 @@
+// Untiling follows xenia's texture handling, see licenses/xenia.txt.
+
+use std::io::{Seek, Write};
+
+use binrw::{BinRead, BinWrite};
+
+use crate::error::{slice_checked, Error, Result};
+use crate::textures::block::*;
+use crate::{
+  div_round_up, round_up, DdsHeader, DdsPixelFormat, MipLevel, RgbaImage, Texture, TextureFormat,
+  DDPF_ALPHAPIXELS, DDPF_FOURCC, DDPF_RGB,
+};
+
+/// The GPU texture fetch constant describing a texture's format and memory layout. It's stored as
+/// six dwords, each field starting at the least significant bit of its dword.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct GpuTextureFetch {
+  dwords: [u32; 6],
+}
+
+impl GpuTextureFetch {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn from_dwords(dwords: [u32; 6]) -> Self {
+    Self { dwords }
+  }
+
+  pub fn to_dwords(self) -> [u32; 6] {
+    self.dwords
+  }
+
+  fn field(&self, dword: usize, shift: u32, bits: u32) -> u32 {
+    (self.dwords[dword] >> shift) & (u32::MAX >> (32 - bits))
+  }
+
+  fn set_field(&mut self, dword: usize, shift: u32, bits: u32, value: u32) {
+    let mask = (u32::MAX >> (32 - bits)) << shift;
+    self.dwords[dword] = (self.dwords[dword] & !mask) | ((value << shift) & mask);
+  }
+}
+
+trait FetchField {
+  fn from_bits(bits: u32) -> Self;
+  fn into_bits(self) -> u32;
+}
+
+impl FetchField for bool {
+  fn from_bits(bits: u32) -> Self {
+    bits != 0
+  }
+  fn into_bits(self) -> u32 {
+    self as u32
+  }
+}
+
+impl FetchField for u8 {
+  fn from_bits(bits: u32) -> Self {
+    bits as u8
+  }
+  fn into_bits(self) -> u32 {
+    self as u32
+  }
+}
+
+impl FetchField for u16 {
+  fn from_bits(bits: u32) -> Self {
+    bits as u16
+  }
+  fn into_bits(self) -> u32 {
+    self as u32
+  }
+}
+
+impl FetchField for u32 {
+  fn from_bits(bits: u32) -> Self {
+    bits
+  }
+  fn into_bits(self) -> u32 {
+    self
+  }
+}
+
+// A getter and a `with_` builder per field, given as dword, first bit and width. The builders drop
+// the bits that don't fit.
+macro_rules! fetch_fields {
+  ($($(#[$attr:meta])* $name:ident, $with:ident: $ty:ty = $dword:literal[$shift:literal; $bits:literal];)*) => {
+    impl GpuTextureFetch {
+      $(
+        $(#[$attr])*
+        pub fn $name(&self) -> $ty {
+          <$ty>::from_bits(self.field($dword, $shift, $bits))
+        }
+
+        pub fn $with(mut self, value: $ty) -> Self {
+          self.set_field($dword, $shift, $bits, value.into_bits());
+          self
+        }
+      )*
+    }
+  };
+}
+
+fetch_fields! {
+  fetch_type, with_fetch_type: u8 = 0[0; 2];
+  sign_x, with_sign_x: u8 = 0[2; 2];
+  sign_y, with_sign_y: u8 = 0[4; 2];
+  sign_z, with_sign_z: u8 = 0[6; 2];
+  sign_w, with_sign_w: u8 = 0[8; 2];
+  clamp_x, with_clamp_x: u8 = 0[10; 3];
+  clamp_y, with_clamp_y: u8 = 0[13; 3];
+  clamp_z, with_clamp_z: u8 = 0[16; 3];
+  signed_rf_mode_all, with_signed_rf_mode_all: bool = 0[19; 1];
+  dim_tbd, with_dim_tbd: u8 = 0[20; 2];
+  /// In units of 32 texels.
+  pitch, with_pitch: u16 = 0[22; 9];
+  tiled, with_tiled: bool = 0[31; 1];
+
+  format, with_format: u8 = 1[0; 6];
+  endianness, with_endianness: u8 = 1[6; 2];
+  request_size, with_request_size: u8 = 1[8; 2];
+  stacked, with_stacked: bool = 1[10; 1];
+  nearest_clamp_policy, with_nearest_clamp_policy: bool = 1[11; 1];
+  /// In 4 KiB pages.
+  base_address, with_base_address: u32 = 1[12; 20];
+
+  /// The width of 2D textures minus one.
+  width, with_width: u16 = 2[0; 13];
+  /// The height of 2D textures minus one.
+  height, with_height: u16 = 2[13; 13];
+  stack_depth, with_stack_depth: u8 = 2[26; 6];
+
+  num_format, with_num_format: bool = 3[0; 1];
+  swizzle, with_swizzle: u16 = 3[1; 12];
+  exp_adjust, with_exp_adjust: u8 = 3[13; 6];
+  mag_filter, with_mag_filter: u8 = 3[19; 2];
+  min_filter, with_min_filter: u8 = 3[21; 2];
+  mip_filter, with_mip_filter: u8 = 3[23; 2];
+  aniso_filter, with_aniso_filter: u8 = 3[25; 3];
+  arbitrary_filter, with_arbitrary_filter: u8 = 3[28; 3];
+  border_size, with_border_size: bool = 3[31; 1];
+
+  vol_mag_filter, with_vol_mag_filter: bool = 4[0; 1];
+  vol_min_filter, with_vol_min_filter: bool = 4[1; 1];
+  min_mip_level, with_min_mip_level: u8 = 4[2; 4];
+  max_mip_level, with_max_mip_level: u8 = 4[6; 4];
+  mag_aniso_walk, with_mag_aniso_walk: bool = 4[10; 1];
+  min_aniso_walk, with_min_aniso_walk: bool = 4[11; 1];
+  lod_bias, with_lod_bias: u16 = 4[12; 10];
+  grad_exp_adjust_h, with_grad_exp_adjust_h: u8 = 4[22; 5];
+  grad_exp_adjust_v, with_grad_exp_adjust_v: u8 = 4[27; 5];
+
+  border_color, with_border_color: u8 = 5[0; 2];
+  force_bc_w_to_max, with_force_bc_w_to_max: bool = 5[2; 1];
+  tri_clamp, with_tri_clamp: u8 = 5[3; 2];
+  aniso_bias, with_aniso_bias: u8 = 5[5; 4];
+  dimension, with_dimension: u8 = 5[9; 2];
+  packed_mips, with_packed_mips: bool = 5[11; 1];
+  /// In 4 KiB pages, 0 for textures without mips.
+  mip_address, with_mip_address: u32 = 5[12; 20];
+}
+
+/// The D3D texture record of a 360 streaming texture.
+#[derive(BinRead, BinWrite, Debug, Clone)]
+pub struct X360TextureHeader {
+  pub common: u32,
+  pub reference_count: u32,
+  pub fence: u32,
+  pub read_fence: u32,
+  pub identifier: u32,
+  pub base_flush: u32,
+  pub mip_flush: u32,
+  #[br(map = GpuTextureFetch::from_dwords)]
+  #[bw(map = |fetch| fetch.to_dwords())]
+  pub fetch: GpuTextureFetch,
+}
+
+/// The Xenos texture formats the games use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XenosFormat {
+  K8 = 2,
+  K1555 = 3,
+  K565 = 4,
+  K8888 = 6,
+  K88 = 10,
+  K4444 = 15,
+  Dxt1 = 18,
+  Dxt2Or3 = 19,
+  Dxt4Or5 = 20,
+  Dxn = 49,
+  Dxt5A = 59,
+}
+
+impl XenosFormat {
+  pub fn from_raw(format: u8) -> Option<Self> {
+    let format = match format {
+      2 => XenosFormat::K8,
+      3 => XenosFormat::K1555,
+      4 => XenosFormat::K565,
+      6 => XenosFormat::K8888,
+      10 => XenosFormat::K88,
+      15 => XenosFormat::K4444,
+      18 => XenosFormat::Dxt1,
+      19 => XenosFormat::Dxt2Or3,
+      20 => XenosFormat::Dxt4Or5,
+      49 => XenosFormat::Dxn,
+      59 => XenosFormat::Dxt5A,
+      _ => return None,
+    };
+    Some(format)
+  }
+
+  pub fn bytes_per_block(&self) -> usize {
+    match self {
+      XenosFormat::K8 => 1,
+      XenosFormat::K1555 | XenosFormat::K565 | XenosFormat::K88 | XenosFormat::K4444 => 2,
+      XenosFormat::K8888 => 4,
+      XenosFormat::Dxt1 | XenosFormat::Dxt5A => 8,
+      XenosFormat::Dxt2Or3 | XenosFormat::Dxt4Or5 | XenosFormat::Dxn => 16,
+    }
+  }
+
+  // block compressed formats DDS can store as is
+  fn four_cc(&self) -> Option<&'static [u8; 4]> {
+    match self {
+      XenosFormat::Dxt1 => Some(b"DXT1"),
+      XenosFormat::Dxt2Or3 => Some(b"DXT3"),
+      XenosFormat::Dxt4Or5 => Some(b"DXT5"),
+      XenosFormat::Dxn => Some(b"ATI2"),
+      XenosFormat::Dxt5A => Some(b"ATI1"),
+      _ => None,
+    }
+  }
+
+  // the X, Y, Z and W components of a level's untiled data, missing ones are 0 and W is 1
+  fn decode_components(&self, data: &[u8], width: usize, height: usize) -> Result<RgbaImage> {
+    let (block_width, block_height) = self.block_dim();
+    let block_size = self.bytes_per_block();
+
+    if block_width == 4 {
+      return match self {
+        XenosFormat::Dxt1 => decode_blocks(data, width, height, 8, decode_dxt1_block),
+        XenosFormat::Dxt2Or3 => decode_blocks(data, width, height, 16, decode_dxt3_block),
+        XenosFormat::Dxt4Or5 => decode_blocks(data, width, height, 16, decode_dxt5_block),
+        XenosFormat::Dxn => decode_blocks(data, width, height, 16, |block| {
+          let (x, y) = (
+            decode_alpha_block(&block[..8]),
+            decode_alpha_block(&block[8..]),
+          );
+          std::array::from_fn(|i| [x[i], y[i], 0, 255])
+        }),
+        _ => decode_blocks(data, width, height, 8, |block| {
+          decode_alpha_block(block).map(|x| [x, 0, 0, 255])
+        }),
+      };
+    }
+
+    check_size(
+      data,
+      width * height * block_size * block_width * block_height,
+    )?;
+    let scale = |value: u32, bits: u32| (value * 255 / ((1 << bits) - 1)) as u8;
+
+    let mut image = RgbaImage::new(width as u32, height as u32);
+    for (i, bytes) in data
+      .chunks_exact(block_size)
+      .take(width * height)
+      .enumerate()
+    {
+      let mut value = [0; 4];
+      value[..block_size].copy_from_slice(bytes);
+      let value = u32::from_le_bytes(value);
+
+      let components = match self {
+        XenosFormat::K8 => [value as u8, 0, 0, 255],
+        XenosFormat::K1555 => [
+          scale(value & 0x1f, 5),
+          scale(value >> 5 & 0x1f, 5),
+          scale(value >> 10 & 0x1f, 5),
+          scale(value >> 15 & 1, 1),
+        ],
+        XenosFormat::K565 => [
+          scale(value & 0x1f, 5),
+          scale(value >> 5 & 0x3f, 6),
+          scale(value >> 11 & 0x1f, 5),
+          255,
+        ],
+        XenosFormat::K4444 => [
+          scale(value & 0xf, 4),
+          scale(value >> 4 & 0xf, 4),
+          scale(value >> 8 & 0xf, 4),
+          scale(value >> 12 & 0xf, 4),
+        ],
+        XenosFormat::K88 => [value as u8, (value >> 8) as u8, 0, 255],
+        _ => value.to_le_bytes(),
+      };
+      image.set_pixel((i % width) as u32, (i / width) as u32, components);
+    }
+
+    Ok(image)
+  }
+}
+
+// Sizes of the untiled level, without the padding tiled and packed levels are stored with. The mip
+// ranges of `X360TextureHeader` account for it.
+impl TextureFormat for XenosFormat {
+  fn block_dim(&self) -> (usize, usize) {
+    match self {
+      XenosFormat::Dxt1
+      | XenosFormat::Dxt2Or3
+      | XenosFormat::Dxt4Or5
+      | XenosFormat::Dxn
+      | XenosFormat::Dxt5A => (4, 4),
+      _ => (1, 1),
+    }
+  }
+
+  fn bits_per_pixel(&self) -> usize {
+    let (block_width, block_height) = self.block_dim();
+    self.bytes_per_block() * 8 / (block_width * block_height)
+  }
+}
+
+// where one mip level is stored, in blocks
+struct Level {
+  width: usize,
+  height: usize,
+  offset: usize,
+  // of the level's allocation, which packed levels share
+  size: usize,
+  pitch_blocks: usize,
+  x_blocks: usize,
+  y_blocks: usize,
+}
+
+fn log2_ceil(value: usize) -> usize {
+  value.next_power_of_two().trailing_zeros() as usize
+}
+
+// Once the smaller dimension is 16 texels or less the remaining levels share one 32x32 block tile.
+// Returns the level's texel offset within that tile.
+fn packed_mip_offset(width: usize, height: usize, level: usize) -> Option<(usize, usize)> {
+  let (log2_width, log2_height) = (log2_ceil(width), log2_ceil(height));
+  let log2_size = log2_width.min(log2_height);
+  if log2_size > 4 + level {
+    return None;
+  }
+
+  let packed_base = log2_size.saturating_sub(4);
+  let packed_level = level - packed_base;
+  let wide = log2_width > log2_height;
+
+  if packed_level < 3 {
+    // 16x16, 8x8 and 4x4 next to each other along the smaller dimension
+    return Some(if wide {
+      (0, 16 >> packed_level)
+    } else {
+      (16 >> packed_level, 0)
+    });
+  }
+
+  let log2_long = if wide { log2_width } else { log2_height };
+  let offset = (1 << (log2_long - packed_base)) >> (packed_level - 2);
+  let (mut x, mut y) = if wide { (offset, 0) } else { (0, offset) };
+  // 1x1 sits next to 2x2
+  if offset < 4 {
+    if wide {
+      y += 4;
+    } else {
+      x += 4;
+    }
+  }
+
+  Some((x, y))
+}
+
+fn tiled_offset_outer(y: usize, pitch_blocks: usize, log2_bpb: usize) -> usize {
+  let macro_offset = ((y >> 5) * (pitch_blocks >> 5)) << (log2_bpb + 7);
+  let micro_offset = ((y & 6) << 2) << log2_bpb;
+  macro_offset
+    + ((micro_offset & !15) << 1)
+    + (micro_offset & 15)
+    + ((y & 8) << (3 + log2_bpb))
+    + ((y & 1) << 4)
+}
+
+fn tiled_offset_inner(x: usize, y: usize, log2_bpb: usize, outer: usize) -> usize {
+  let macro_offset = (x >> 5) << (log2_bpb + 7);
+  let micro_offset = (x & 7) << log2_bpb;
+  let offset = outer + macro_offset + ((micro_offset & !15) << 1) + (micro_offset & 15);
+  ((offset & !511) << 3)
+    + ((offset & 448) << 2)
+    + (offset & 63)
+    + ((y & 16) << 7)
+    + (((((y & 8) >> 2) + (x >> 3)) & 3) << 6)
+}
+
+impl Texture for X360TextureHeader {
+  type Mips<'a> = std::vec::IntoIter<MipLevel>;
+
+  fn width(&self) -> u32 {
+    self.fetch.width() as u32 + 1
+  }
+
+  fn height(&self) -> u32 {
+    self.fetch.height() as u32 + 1
+  }
+
+  fn mip_count(&self) -> u32 {
+    if self.fetch.mip_address() == 0 {
+      return 1;
+    }
+    // the fetch constant's max level can reach past the 1x1 level
+    let full_chain = 32 - self.width().max(self.height()).leading_zeros();
+    let levels = (self.fetch.max_mip_level() as u32 + 1).min(full_chain);
+    levels
+      .saturating_sub(self.fetch.min_mip_level() as u32)
+      .max(1)
+  }
+
+  /// Packed levels share the range of their tile.
+  fn mips(&self) -> Result<Self::Mips<'_>> {
+    let levels = self.levels(self.format()?);
+    let mips: Vec<MipLevel> = levels
+      .into_iter()
+      .enumerate()
+      .map(|(index, level)| MipLevel {
+        level: index as u32,
+        width: level.width,
+        height: level.height,
+        range: level.offset..level.offset + level.size,
+      })
+      .collect();
+    Ok(mips.into_iter())
+  }
+}
+
+impl X360TextureHeader {
+  pub fn format(&self) -> Result<XenosFormat> {
+    XenosFormat::from_raw(self.fetch.format()).ok_or_else(|| Error::UnsupportedTextureFormat {
+      format: format!("xenos format {}", self.fetch.format()),
+    })
+  }
+
+  fn levels(&self, format: XenosFormat) -> Vec<Level> {
+    let (block_width, block_height) = format.block_dim();
+    let block_size = format.bytes_per_block();
+    let (width, height) = (self.width() as usize, self.height() as usize);
+    let tiled = self.fetch.tiled();
+
+    let mut levels = Vec::new();
+    let mut offset = 0;
+    let mut packed_tile = None;
+
+    for level in 0..self.mip_count() as usize {
+      // the base level uses the fetch constant's pitch, mips are stored padded to powers of two
+      let (stored_width, stored_height) = if level == 0 {
+        let pitch = (self.fetch.pitch() as usize) << 5;
+        (if pitch == 0 { width } else { pitch }, height)
+      } else {
+        (
+          1.max(width.next_power_of_two() >> level),
+          1.max(height.next_power_of_two() >> level),
+        )
+      };
+
+      let mut pitch_blocks = div_round_up(stored_width, block_width);
+      let mut height_blocks = div_round_up(stored_height, block_height);
+      if tiled {
+        pitch_blocks = round_up(pitch_blocks, 32);
+        height_blocks = round_up(height_blocks, 32);
+      } else {
+        pitch_blocks = round_up(pitch_blocks * block_size, 256) / block_size;
+      }
+      let size = round_up(pitch_blocks * height_blocks * block_size, 4096);
+
+      if level == 1 {
+        // mips live in their own allocation, which gets its own packed tile
+        let (base_address, mip_address) = (self.fetch.base_address(), self.fetch.mip_address());
+        if mip_address >= base_address {
+          offset = ((mip_address - base_address) as usize) << 12;
+        }
+        packed_tile = None;
+      }
+
+      let packed = if self.fetch.packed_mips() {
+        packed_mip_offset(width, height, level)
+      } else {
+        None
+      };
+      let (x, y) = packed.unwrap_or_default();
+      let level_offset = match packed {
+        Some(_) => *packed_tile.get_or_insert(offset),
+        None => offset,
+      };
+      if level_offset == offset {
+        offset += size;
+      }
+
+      levels.push(Level {
+        width: 1.max(width >> level),
+        height: 1.max(height >> level),
+        offset: level_offset,
+        size,
+        pitch_blocks,
+        x_blocks: x / block_width,
+        y_blocks: y / block_height,
+      });
+    }
+
+    levels
+  }
+
+  // (width, height, blocks in rows) of each level, biggest first
+  fn untile(&self, streaming_data: &[u8]) -> Result<Vec<(usize, usize, Vec<u8>)>> {
+    let format = self.format()?;
+    let (block_width, block_height) = format.block_dim();
+    let block_size = format.bytes_per_block();
+    let log2_block_size = block_size.trailing_zeros() as usize;
+
+    let mut data = streaming_data.to_vec();
+    match self.fetch.endianness() {
+      1 => data.chunks_exact_mut(2).for_each(|chunk| chunk.swap(0, 1)),
+      2 => data.chunks_exact_mut(4).for_each(|chunk| chunk.reverse()),
+      3 => data
+        .chunks_exact_mut(4)
+        .for_each(|chunk| chunk.rotate_left(2)),
+      _ => {}
+    }
+
+    self
+      .levels(format)
+      .into_iter()
+      .map(|level| {
+        let blocks_wide = div_round_up(level.width, block_width);
+        let blocks_high = div_round_up(level.height, block_height);
+
+        let mut blocks = Vec::with_capacity(blocks_wide * blocks_high * block_size);
+        for y in 0..blocks_high {
+          let y = y + level.y_blocks;
+          let outer = tiled_offset_outer(y, level.pitch_blocks, log2_block_size);
+
+          for x in 0..blocks_wide {
+            let x = x + level.x_blocks;
+            let offset = if self.fetch.tiled() {
+              tiled_offset_inner(x, y, log2_block_size, outer) >> log2_block_size << log2_block_size
+            } else {
+              (y * level.pitch_blocks + x) * block_size
+            };
+            blocks.extend_from_slice(slice_checked(
+              &data,
+              level.offset + offset,
+              block_size,
+              "360 texture data",
+            )?);
+          }
+        }
+
+        Ok((level.width, level.height, blocks))
+      })
+      .collect()
+  }
+
+  /// Untiles and decodes every mip level, biggest first. The fetch constant's swizzle picks the
+  /// channels.
+  pub fn decode(&self, streaming_data: &[u8]) -> Result<Vec<RgbaImage>> {
+    let format = self.format()?;
+    let swizzle = self.fetch.swizzle();
+
+    self
+      .untile(streaming_data)?
+      .into_iter()
+      .map(|(width, height, blocks)| {
+        let mut image = format.decode_components(&blocks, width, height)?;
+        for pixel in image.data.chunks_exact_mut(4) {
+          let components = [pixel[0], pixel[1], pixel[2], pixel[3]];
+          for (channel, value) in pixel.iter_mut().enumerate() {
+            *value = match (swizzle >> (channel * 3)) & 7 {
+              select @ 0..=3 => components[select as usize],
+              5 => 255,
+              _ => 0,
+            };
+          }
+        }
+        Ok(image)
+      })
+      .collect()
+  }
+
+  /// Writes the texture as a DDS file with its full mip chain. Block compressed formats are written
+  /// as is, the others as A8R8G8B8.
+  pub fn write_dds<W: Write + Seek>(&self, writer: &mut W, streaming_data: &[u8]) -> Result<()> {
+    let format = self.format()?;
+
+    let (pixel_format, levels) = match format.four_cc() {
+      Some(four_cc) => {
+        let pixel_format = DdsPixelFormat {
+          size: 32,
+          flags: DDPF_FOURCC,
+          four_cc: *four_cc,
+          ..Default::default()
+        };
+        let levels = self.untile(streaming_data)?;
+        (
+          pixel_format,
+          levels.into_iter().map(|(_, _, data)| data).collect(),
+        )
+      }
+      None => {
+        let pixel_format = DdsPixelFormat {
+          size: 32,
+          flags: DDPF_RGB | DDPF_ALPHAPIXELS,
+          rgb_bit_count: 32,
+          r_bit_mask: 0xff0000,
+          g_bit_mask: 0xff00,
+          b_bit_mask: 0xff,
+          a_bit_mask: 0xff000000,
+          ..Default::default()
+        };
+        // RGBA to BGRA
+        let levels = self
+          .decode(streaming_data)?
+          .into_iter()
+          .map(|image| {
+            image
+              .pixels()
+              .flat_map(|[r, g, b, a]| [b, g, r, a])
+              .collect()
+          })
+          .collect();
+        (pixel_format, levels)
+      }
+    };
+
+    let levels: Vec<Vec<u8>> = levels;
+    let top_level_size = levels.first().map(Vec::len).unwrap_or(0);
+    let header = DdsHeader::new(
+      self.width(),
+      self.height(),
+      levels.len() as u32,
+      pixel_format,
+      top_level_size as u32,
+    );
+    header.write(writer)?;
+    for level in levels {
+      writer.write_all(&level)?;
+    }
+
+    Ok(())
+  }
+}
+
+impl crate::StreamingTexture<X360TextureHeader> {
+  pub fn write_dds<W: Write + Seek>(&self, writer: &mut W, streaming_data: &[u8]) -> Result<()> {
+    self.header.write_dds(writer, streaming_data)
+  }
+}
@@ -1,21 +1,23 @@
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::Seek;
 use std::path::Path;
 
-use binrw::{BinRead, BinResult, Endian};
+use binrw::{binread, BinRead, BinResult, Endian};
 
-use crate::utils::clean_path;
+use crate::utils::{clean_path, Vector3};
+use crate::SectionGraph;
 
-#[derive(BinRead, Debug)]
-pub(crate) struct Bounding {
-  pub(crate) min_x: f32,
-  pub(crate) max_x: f32,
+#[derive(BinRead, Debug, Clone, Copy, PartialEq)]
+pub struct Bounding {
+  pub min_x: f32,
+  pub max_x: f32,
 
-  pub(crate) min_y: f32,
-  pub(crate) max_y: f32,
+  pub min_y: f32,
+  pub max_y: f32,
 
-  pub(crate) min_z: f32,
-  pub(crate) max_z: f32,
+  pub min_z: f32,
+  pub max_z: f32,
 }
 
 #[derive(BinRead, Debug)]
@@ -35,13 +37,19 @@ pub enum ComponentKind {
   CollisionGrid,
 }
 
-#[derive(Default, BinRead, Debug)]
+#[binread]
+#[derive(Default, Debug)]
 pub(crate) struct ZlibHeader {
-  pub(crate) uncached_total_size: i32,
-  pub(crate) cached_total_size: i32,
+  // the blocks' total sizes, unused since every chunk size is read
+  #[br(temp)]
+  uncached_total_size: i32,
+  #[br(temp)]
+  cached_total_size: i32,
 
-  pub(crate) uncached_amount: i32,
-  pub(crate) cached_amount: i32,
+  #[br(temp)]
+  uncached_amount: i32,
+  #[br(temp)]
+  cached_amount: i32,
 
   #[br(count = uncached_amount)]
   pub(crate) uncached_sizes: Vec<i32>,
@@ -59,13 +67,16 @@ pub struct SectionHeader {
   pub uncached_component_count: i32,
   pub cached_component_count: i32,
 
+  // Undocumented. Like `link_table` they hold -1 when unused, so they're read as the index of a
+  // shared section and indices into the SOI's page size lists. That reading isn't verified against
+  // game files, only against the hand built sections in the tests.
   pub(crate) shared_section_offset: i32,
   pub(crate) uncached_page_offset: i32,
   pub(crate) cached_page_offset: i32,
 
   pub(crate) link_table: [i32; 8],
 
-  pub(crate) bounding: Bounding,
+  pub bounding: Bounding,
 
   pub(crate) memory_entry: MemoryEntry,
   pub(crate) uncached_data_size: i32,
@@ -77,7 +88,7 @@ pub struct SectionHeader {
 
 #[derive(BinRead, Debug)]
 pub struct ComponentHeader {
-  raw_path: [u8; 260],
+  pub(crate) raw_path: [u8; 260],
 
   pub instance_id: i32,
   pub id: i32,
@@ -85,6 +96,15 @@ pub struct ComponentHeader {
   pub kind: ComponentKind,
 }
 
+/// Where a component's data is physically stored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ComponentSource {
+  /// The component lives in its own section's data.
+  Owned,
+  /// The component is stored once in a shared section and only referenced by this one.
+  Shared { section_id: u32, component_id: u32 },
+}
+
 #[derive(BinRead, Debug)]
 #[br(import{read_zlib_header: bool})]
 pub struct Section {
@@ -101,6 +121,10 @@ pub struct Section {
 #[derive(Debug)]
 pub struct Toc {
   pub sections: Vec<Section>,
+  // instance id -> (section id, component id) of every occurrence, in section order
+  instances: HashMap<i32, Vec<(u32, u32)>>,
+  // lowercase path -> (section id, position in `Section::components`) of every occurrence
+  paths: HashMap<String, Vec<(u32, usize)>>,
 }
 
 impl Toc {
@@ -110,39 +134,359 @@ impl Toc {
   }
 
   pub fn read_file(file: &mut File, endian: Endian, is_new: bool) -> BinResult<Self> {
+    debug_span!("toc", is_new);
     let mut sections = Vec::new();
     let file_size = file.metadata()?.len();
 
+    if is_new {
+      debug!("newer SOI package, sections have no zlib header");
+    }
+
     // read sections until the end of the file is reached
     while file.stream_position()? < file_size {
       // hack that allows newer SOI packages to load
       let read_zlib_header = !is_new;
       let section = Section::read_options(file, endian, binrw::args! {read_zlib_header})?;
+      trace!(
+        id = sections.len(),
+        name = %section.header.name(),
+        uncached = section.uncached_components.len(),
+        cached = section.cached_components.len(),
+        "read section"
+      );
       sections.push(section);
     }
 
-    Ok(Self { sections })
+    debug!(sections = sections.len(), "read toc");
+    Ok(Self::new(sections))
   }
-  
+
+  pub fn new(sections: Vec<Section>) -> Self {
+    let mut instances: HashMap<i32, Vec<(u32, u32)>> = HashMap::new();
+    let mut paths: HashMap<String, Vec<(u32, usize)>> = HashMap::new();
+
+    for (index, section) in sections.iter().enumerate() {
+      for (position, component) in section.components().enumerate() {
+        instances
+          .entry(component.instance_id)
+          .or_default()
+          .push((index as u32, component.id as u32));
+        paths
+          .entry(component.path().to_ascii_lowercase())
+          .or_default()
+          .push((index as u32, position));
+      }
+    }
+
+    Self {
+      sections,
+      instances,
+      paths,
+    }
+  }
+
+  pub fn link_graph(&self) -> SectionGraph {
+    SectionGraph::new(self)
+  }
+
   pub fn find_section(&self, id: u32) -> Option<&Section> {
     self.sections.get(id as usize)
   }
 
+  /// Sections that other sections pull shared components from.
+  pub fn shared_sections(&self) -> Vec<u32> {
+    let mut shared: Vec<u32> = self
+      .sections
+      .iter()
+      .filter_map(|section| section.header.shared_section())
+      .filter(|&id| (id as usize) < self.sections.len())
+      .collect();
+
+    shared.sort_unstable();
+    shared.dedup();
+    shared
+  }
+
+  /// A component is shared when its section points at a shared section that carries the same instance.
+  pub fn component_source(&self, section_id: u32, component: &ComponentHeader) -> ComponentSource {
+    let shared = self
+      .find_section(section_id)
+      .and_then(|section| section.header.shared_section())
+      .filter(|&shared_id| shared_id != section_id)
+      .and_then(|shared_id| {
+        let shared_section = self.find_section(shared_id)?;
+        let (header, _) = shared_section.find_instance(component.instance_id)?;
+        Some(ComponentSource::Shared {
+          section_id: shared_id,
+          component_id: header.id as u32,
+        })
+      });
+
+    shared.unwrap_or(ComponentSource::Owned)
+  }
+
   pub fn find_ids(&self, instance_id: u32) -> Option<(u32, u32)> {
-    for (index, section) in self.sections.iter().enumerate() {
-      for component in &section.uncached_components {
-        if component.instance_id == instance_id as i32 {
-          return Some((index as u32, component.id as u32));
+    self.find_all_ids(instance_id).first().copied()
+  }
+
+  /// Every (section id, component id) pair an instance appears under.
+  pub fn find_all_ids(&self, instance_id: u32) -> &[(u32, u32)] {
+    self
+      .instances
+      .get(&(instance_id as i32))
+      .map(|ids| ids.as_slice())
+      .unwrap_or(&[])
+  }
+
+  /// Every component stored under a path, compared case-insensitively. Backslashes are treated as
+  /// slashes. The path index is built by `new`, so components added to `sections` afterwards aren't
+  /// found, and ones that were moved or removed are left out.
+  pub fn find_by_path(&self, path: &str) -> Vec<(u32, &Section, &ComponentHeader)> {
+    let key = path.replace('\\', "/").to_ascii_lowercase();
+
+    self
+      .paths
+      .get(&key)
+      .into_iter()
+      .flatten()
+      .filter_map(|&(section_id, position)| {
+        let section = self.find_section(section_id)?;
+        let component = section
+          .component(position)
+          .filter(|component| component.path().to_ascii_lowercase() == key)?;
+        Some((section_id, section, component))
+      })
+      .collect()
+  }
+
+  pub fn sections_containing(&self, point: &Vector3) -> Vec<u32> {
+    self
+      .sections
+      .iter()
+      .enumerate()
+      .filter(|(_, section)| section.header.bounding.contains(point))
+      .map(|(id, _)| id as u32)
+      .collect()
+  }
+
+  pub fn sections_intersecting(&self, bounds: &Bounding) -> Vec<u32> {
+    self
+      .sections
+      .iter()
+      .enumerate()
+      .filter(|(_, section)| section.header.bounding.intersects(bounds))
+      .map(|(id, _)| id as u32)
+      .collect()
+  }
+
+  /// Returns every section hit by the ray along with its entry distance, closest first.
+  pub fn sections_intersecting_ray(
+    &self,
+    origin: &Vector3,
+    direction: &Vector3,
+  ) -> Vec<(u32, f32)> {
+    let mut hits: Vec<(u32, f32)> = self
+      .sections
+      .iter()
+      .enumerate()
+      .filter_map(|(id, section)| {
+        let distance = section.header.bounding.intersect_ray(origin, direction)?;
+        Some((id as u32, distance))
+      })
+      .collect();
+
+    hits.sort_by(|a, b| a.1.total_cmp(&b.1));
+    hits
+  }
+
+  /// Returns the section whose bounding box is closest to `point`. Ties (e.g. overlapping sections
+  /// that both contain the point) are broken by the distance to the box center.
+  pub fn nearest_section(&self, point: &Vector3) -> Option<u32> {
+    let center_distance = |bounding: &Bounding| {
+      let center = bounding.center();
+      let (dx, dy, dz) = (center.x - point.x, center.y - point.y, center.z - point.z);
+      dx * dx + dy * dy + dz * dz
+    };
+
+    self
+      .sections
+      .iter()
+      .enumerate()
+      .min_by(|(_, a), (_, b)| {
+        let (a, b) = (&a.header.bounding, &b.header.bounding);
+        a.distance_squared(point)
+          .total_cmp(&b.distance_squared(point))
+          .then_with(|| center_distance(a).total_cmp(&center_distance(b)))
+      })
+      .map(|(id, _)| id as u32)
+  }
+}
+
+impl Bounding {
+  pub fn new(min: Vector3, max: Vector3) -> Self {
+    Self {
+      min_x: min.x,
+      max_x: max.x,
+      min_y: min.y,
+      max_y: max.y,
+      min_z: min.z,
+      max_z: max.z,
+    }
+  }
+
+  pub fn min(&self) -> Vector3 {
+    Vector3 {
+      x: self.min_x,
+      y: self.min_y,
+      z: self.min_z,
+    }
+  }
+
+  pub fn max(&self) -> Vector3 {
+    Vector3 {
+      x: self.max_x,
+      y: self.max_y,
+      z: self.max_z,
+    }
+  }
+
+  pub fn center(&self) -> Vector3 {
+    Vector3 {
+      x: (self.min_x + self.max_x) * 0.5,
+      y: (self.min_y + self.max_y) * 0.5,
+      z: (self.min_z + self.max_z) * 0.5,
+    }
+  }
+
+  pub fn contains(&self, point: &Vector3) -> bool {
+    point.x >= self.min_x
+      && point.x <= self.max_x
+      && point.y >= self.min_y
+      && point.y <= self.max_y
+      && point.z >= self.min_z
+      && point.z <= self.max_z
+  }
+
+  pub fn intersects(&self, other: &Bounding) -> bool {
+    self.min_x <= other.max_x
+      && self.max_x >= other.min_x
+      && self.min_y <= other.max_y
+      && self.max_y >= other.min_y
+      && self.min_z <= other.max_z
+      && self.max_z >= other.min_z
+  }
+
+  /// Returns the distance along `direction` at which the ray enters the box (0 if `origin` is
+  /// inside), using the slab method.
+  pub fn intersect_ray(&self, origin: &Vector3, direction: &Vector3) -> Option<f32> {
+    let mut t_min = 0.0_f32;
+    let mut t_max = f32::INFINITY;
+
+    let slabs = [
+      (origin.x, direction.x, self.min_x, self.max_x),
+      (origin.y, direction.y, self.min_y, self.max_y),
+      (origin.z, direction.z, self.min_z, self.max_z),
+    ];
+
+    for (origin, direction, min, max) in slabs {
+      if direction == 0.0 {
+        // parallel to this slab, so the origin has to already be between its planes
+        if origin < min || origin > max {
+          return None;
         }
+        continue;
       }
-      for component in &section.cached_components {
-        if component.instance_id == instance_id as i32 {
-          return Some((index as u32, component.id as u32));
-        }
+
+      let inverse = 1.0 / direction;
+      let mut t0 = (min - origin) * inverse;
+      let mut t1 = (max - origin) * inverse;
+      if t0 > t1 {
+        std::mem::swap(&mut t0, &mut t1);
+      }
+
+      t_min = t_min.max(t0);
+      t_max = t_max.min(t1);
+      if t_min > t_max {
+        return None;
       }
     }
 
-    None
+    Some(t_min)
+  }
+
+  /// Squared distance from `point` to the closest point of the box (0 if inside).
+  pub fn distance_squared(&self, point: &Vector3) -> f32 {
+    let dx = (self.min_x - point.x).max(0.0).max(point.x - self.max_x);
+    let dy = (self.min_y - point.y).max(0.0).max(point.y - self.max_y);
+    let dz = (self.min_z - point.z).max(0.0).max(point.z - self.max_z);
+
+    dx * dx + dy * dy + dz * dz
+  }
+}
+
+impl SectionHeader {
+  pub fn name(&self) -> String {
+    clean_path(&self.name)
+  }
+
+  /// Index of the section holding components shared with this one, if any (stored as -1 otherwise).
+  pub fn shared_section(&self) -> Option<u32> {
+    (self.shared_section_offset >= 0).then_some(self.shared_section_offset as u32)
+  }
+
+  /// Index into the SOI's uncached page size list.
+  pub fn uncached_page(&self) -> Option<u32> {
+    (self.uncached_page_offset >= 0).then_some(self.uncached_page_offset as u32)
+  }
+
+  /// Index into the SOI's cached page size list.
+  pub fn cached_page(&self) -> Option<u32> {
+    (self.cached_page_offset >= 0).then_some(self.cached_page_offset as u32)
+  }
+
+  /// Link table slots in use along with the section id they hold. Unused slots (-1) are skipped,
+  /// but out of range ids are not.
+  pub fn links(&self) -> impl Iterator<Item = (usize, i32)> + '_ {
+    self
+      .link_table
+      .iter()
+      .copied()
+      .enumerate()
+      .filter(|&(_, link)| link >= 0)
+  }
+}
+
+impl Section {
+  pub fn components(&self) -> impl Iterator<Item = &ComponentHeader> {
+    self
+      .uncached_components
+      .iter()
+      .chain(self.cached_components.iter())
+  }
+
+  /// The component at `position` in `components`.
+  pub fn component(&self, position: usize) -> Option<&ComponentHeader> {
+    match position.checked_sub(self.uncached_components.len()) {
+      Some(cached) => self.cached_components.get(cached),
+      None => self.uncached_components.get(position),
+    }
+  }
+
+  /// Finds a component by instance id, also returning whether it is stored in the cached block.
+  pub fn find_instance(&self, instance_id: i32) -> Option<(&ComponentHeader, bool)> {
+    let uncached = self
+      .uncached_components
+      .iter()
+      .find(|component| component.instance_id == instance_id)
+      .map(|component| (component, false));
+
+    uncached.or_else(|| {
+      self
+        .cached_components
+        .iter()
+        .find(|component| component.instance_id == instance_id)
+        .map(|component| (component, true))
+    })
   }
 }
 
@@ -0,0 +1,74 @@
+// Wrappers around the `tracing` macros. Without the `tracing` feature the arguments are moved into
+// closures that are never called, so they still count as used but aren't evaluated.
+
+macro_rules! trace {
+  ($($arg:tt)*) => {{
+    #[cfg(feature = "tracing")]
+    tracing::trace!($($arg)*);
+    #[cfg(not(feature = "tracing"))]
+    unused_fields!($($arg)*);
+  }};
+}
+
+macro_rules! debug {
+  ($($arg:tt)*) => {{
+    #[cfg(feature = "tracing")]
+    tracing::debug!($($arg)*);
+    #[cfg(not(feature = "tracing"))]
+    unused_fields!($($arg)*);
+  }};
+}
+
+macro_rules! warn {
+  ($($arg:tt)*) => {{
+    #[cfg(feature = "tracing")]
+    tracing::warn!($($arg)*);
+    #[cfg(not(feature = "tracing"))]
+    unused_fields!($($arg)*);
+  }};
+}
+
+// Enters a debug span until the end of the enclosing block.
+macro_rules! debug_span {
+  ($name:literal $(, $($field:tt)*)?) => {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::debug_span!($name $(, $($field)*)?).entered();
+    #[cfg(not(feature = "tracing"))]
+    unused_fields!($($($field)*)?);
+  };
+}
+
+// Borrows every field value and message argument inside a closure that is never called.
+#[cfg(not(feature = "tracing"))]
+macro_rules! unused_fields {
+  () => {};
+  ($name:ident = %$value:expr $(, $($rest:tt)*)?) => {
+    let _ = || {
+      let _ = &$value;
+    };
+    unused_fields!($($($rest)*)?);
+  };
+  ($name:ident = ?$value:expr $(, $($rest:tt)*)?) => {
+    let _ = || {
+      let _ = &$value;
+    };
+    unused_fields!($($($rest)*)?);
+  };
+  ($name:ident = $value:expr $(, $($rest:tt)*)?) => {
+    let _ = || {
+      let _ = &$value;
+    };
+    unused_fields!($($($rest)*)?);
+  };
+  ($name:ident $(, $($rest:tt)*)?) => {
+    let _ = || {
+      let _ = &$name;
+    };
+    unused_fields!($($($rest)*)?);
+  };
+  ($message:literal $(, $arg:expr)* $(,)?) => {
+    let _ = || {
+      let _ = format!($message $(, $arg)*);
+    };
+  };
+}
@@ -1,6 +1,8 @@
 use binrw::{BinRead, BinWrite};
 
-#[derive(Default, BinRead, BinWrite, Debug)]
+use crate::error::Result;
+
+#[derive(Default, BinRead, BinWrite, Debug, Clone)]
 pub struct Vector4 {
   pub x: f32,
   pub y: f32,
@@ -25,6 +27,16 @@ pub struct Vector3 {
   pub z: f32,
 }
 
+impl From<&Vector4> for Vector3 {
+  fn from(vector: &Vector4) -> Self {
+    Self {
+      x: vector.x,
+      y: vector.y,
+      z: vector.z,
+    }
+  }
+}
+
 impl std::fmt::Display for Vector3 {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     write!(f, "{},{},{}", self.x, self.y, self.z)
@@ -62,11 +74,11 @@ pub struct Vector4i16 {
 }
 
 pub fn round_up(num_to_round: usize, round_to: usize) -> usize {
-  return ((num_to_round + round_to - 1) / round_to) * round_to;
+  num_to_round.div_ceil(round_to) * round_to
 }
 
 pub fn div_round_up(num_to_round: usize, round_to: usize) -> usize {
-  return (num_to_round + round_to - 1) / round_to;
+  num_to_round.div_ceil(round_to)
 }
 
 pub fn clean_path(input: &[u8]) -> String {
@@ -87,15 +99,108 @@ pub fn clean_path(input: &[u8]) -> String {
   output
 }
 
-pub fn clean_string(input: &[u8]) -> String {
-  let mut output = Vec::new();
+pub fn clean_string(input: &[u8]) -> Result<String> {
+  let end = input
+    .iter()
+    .position(|c| c == &NULL_BYTE)
+    .unwrap_or(input.len());
 
-  for c in input {
-    if c == &NULL_BYTE {
-      return std::str::from_utf8(&output).unwrap().to_owned();
+  Ok(std::str::from_utf8(&input[..end])?.to_owned())
+}
+
+/// Like `clean_string`, but replaces invalid UTF-8 instead of failing. Used for display purposes.
+pub fn clean_string_lossy(input: &[u8]) -> String {
+  let end = input
+    .iter()
+    .position(|c| c == &NULL_BYTE)
+    .unwrap_or(input.len());
+
+  String::from_utf8_lossy(&input[..end]).into_owned()
+}
+
+/// Case-insensitive glob match of a component path. `*` and `?` stay within one directory, `**`
+/// spans any number of them.
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+  let normalize = |input: &str| -> Vec<char> {
+    input
+      .chars()
+      .map(|c| {
+        if c == '\\' {
+          SLASH
+        } else {
+          c.to_ascii_lowercase()
+        }
+      })
+      .collect()
+  };
+
+  glob_match_chars(&normalize(pattern), &normalize(path))
+}
+
+// Iterative wildcard matching, only the latest `*` and `**` are ever retried so it never goes
+// exponential. Extending an earlier `*` of the same directory can't help once the latest one fails.
+fn glob_match_chars(pattern: &[char], path: &[char]) -> bool {
+  let (mut p, mut s) = (0, 0);
+  // (pattern index after the star, path index the rest last resumed at)
+  let mut star: Option<(usize, usize)> = None;
+  // same for `**`, also whether the rest may only resume at the start of a directory
+  let mut globstar: Option<(usize, usize, bool)> = None;
+
+  while p < pattern.len() || s < path.len() {
+    match pattern.get(p) {
+      Some('*') if pattern.get(p + 1) == Some(&'*') => {
+        // `a/**/b` also matches `a/b`, so the slash is part of the `**`
+        let segments = pattern.get(p + 2) == Some(&SLASH);
+        p += if segments { 3 } else { 2 };
+        globstar = Some((p, s, segments));
+        star = None;
+        continue;
+      }
+      Some('*') => {
+        p += 1;
+        star = Some((p, s));
+        continue;
+      }
+      Some('?') if s < path.len() && path[s] != SLASH => {
+        p += 1;
+        s += 1;
+        continue;
+      }
+      Some(&c) if c != '?' && path.get(s) == Some(&c) => {
+        p += 1;
+        s += 1;
+        continue;
+      }
+      _ => {}
     }
-    output.push(*c)
+
+    // `*` takes one more character, as long as it stays in its directory
+    if let Some((rest, next)) = star {
+      if next < path.len() && path[next] != SLASH {
+        star = Some((rest, next + 1));
+        (p, s) = (rest, next + 1);
+        continue;
+      }
+    }
+
+    // otherwise `**` takes one more character, or everything up to the next directory
+    let Some((rest, next, segments)) = globstar else {
+      return false;
+    };
+    let next = if segments {
+      match path[next..].iter().position(|&c| c == SLASH) {
+        Some(slash) => next + slash + 1,
+        None => return false,
+      }
+    } else if next < path.len() {
+      next + 1
+    } else {
+      return false;
+    };
+    globstar = Some((rest, next, segments));
+    star = None;
+    (p, s) = (rest, next);
   }
 
-  std::str::from_utf8(&output).unwrap().to_owned()
+  true
 }
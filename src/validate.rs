@@ -0,0 +1,481 @@
+use binrw::BinRead;
+
+use crate::{
+  ComponentHeader, ComponentKind, ComponentSource, DXGHeader, DXTStaticTextureHeader,
+  DXTTextureHeader, GCGHeader, GCNStaticTextureHeader, GCNTextureHeader, ResolvedComponent,
+  Section, SoiSoup, Str, Texture, TextureFormat, X360StaticTextureHeader, X360TextureHeader,
+  XNGHeader,
+};
+
+/// Headers that know how long the streaming data stored alongside them in the STR should be.
+pub trait StreamingDataSize {
+  fn streaming_data_size(&self) -> Option<usize>;
+}
+
+impl StreamingDataSize for DXTTextureHeader {
+  fn streaming_data_size(&self) -> Option<usize> {
+    self.image_size().ok()
+  }
+}
+
+impl StreamingDataSize for GCNTextureHeader {
+  fn streaming_data_size(&self) -> Option<usize> {
+    self.image_size().ok()
+  }
+}
+
+impl StreamingDataSize for X360TextureHeader {
+  // the mip chain of formats the untiler doesn't know is unknown
+  fn streaming_data_size(&self) -> Option<usize> {
+    self.image_size().ok()
+  }
+}
+
+impl StreamingDataSize for DXGHeader {
+  fn streaming_data_size(&self) -> Option<usize> {
+    Some(DXGHeader::streaming_data_size(self))
+  }
+}
+
+impl StreamingDataSize for XNGHeader {
+  fn streaming_data_size(&self) -> Option<usize> {
+    Some(XNGHeader::streaming_data_size(self))
+  }
+}
+
+impl StreamingDataSize for GCGHeader {
+  fn streaming_data_size(&self) -> Option<usize> {
+    Some(GCGHeader::streaming_data_size(self))
+  }
+}
+
+/// Static texture headers, which carry their mip levels in the SOI instead of the STR.
+pub trait StaticDataSize {
+  /// The size the header's dimensions call for and the size of the mip data it holds, if known.
+  fn static_data_size(&self) -> Option<(usize, usize)>;
+}
+
+impl StaticDataSize for DXTStaticTextureHeader {
+  fn static_data_size(&self) -> Option<(usize, usize)> {
+    let mips = self.mips.iter().map(|mip| {
+      let (width, height) = (mip.header.width as usize, mip.header.height as usize);
+      (width, height, mip.data.len())
+    });
+    Some(static_mip_sizes(&self.format, mips))
+  }
+}
+
+impl StaticDataSize for GCNStaticTextureHeader {
+  fn static_data_size(&self) -> Option<(usize, usize)> {
+    let mips = self.mips.iter().map(|mip| {
+      let (width, height) = (mip.header.width as usize, mip.header.height as usize);
+      (width, height, mip.data.len())
+    });
+    Some(static_mip_sizes(&self.format, mips))
+  }
+}
+
+// (expected, actual) summed over every (width, height, data length) level
+fn static_mip_sizes(
+  format: &impl TextureFormat,
+  mips: impl Iterator<Item = (usize, usize, usize)>,
+) -> (usize, usize) {
+  mips.fold((0, 0), |(expected, actual), (width, height, size)| {
+    (expected + format.mip_size(width, height), actual + size)
+  })
+}
+
+impl StaticDataSize for X360StaticTextureHeader {
+  // a whole DDS file, nothing else records its size
+  fn static_data_size(&self) -> Option<(usize, usize)> {
+    None
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Block {
+  Uncached,
+  Cached,
+}
+
+impl std::fmt::Display for Block {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Block::Uncached => write!(f, "uncached"),
+      Block::Cached => write!(f, "cached"),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationIssue {
+  /// The STR data of a section could not be read at all.
+  SectionUnreadable { section_id: u32, error: String },
+  /// The decompressed (or read) block length differs from the size in the TOC.
+  BlockSizeMismatch {
+    section_id: u32,
+    block: Block,
+    expected: usize,
+    actual: usize,
+  },
+  /// A section refers to a page that doesn't exist in the SOI page size list. Only reported by
+  /// `validate_pages`.
+  PageOutOfRange {
+    section_id: u32,
+    block: Block,
+    page: u32,
+    page_count: usize,
+  },
+  /// The SOI page a section is streamed into is smaller than the section's block. Only reported
+  /// by `validate_pages`.
+  PageTooSmall {
+    section_id: u32,
+    block: Block,
+    page: u32,
+    page_size: usize,
+    block_size: usize,
+  },
+  /// A component's memory entry reaches past the end of its block.
+  ComponentOutOfBounds {
+    section_id: u32,
+    path: String,
+    offset: i32,
+    size: i32,
+    block_size: usize,
+  },
+  /// There's no SOI record describing a component.
+  MissingSoiRecord {
+    section_id: u32,
+    path: String,
+    kind: ComponentKind,
+  },
+  /// The streaming data length differs from what the SOI header describes.
+  StreamingDataSizeMismatch {
+    section_id: u32,
+    path: String,
+    kind: ComponentKind,
+    expected: usize,
+    actual: usize,
+  },
+  /// The mip data in a static texture's SOI header differs from what its dimensions call for.
+  StaticDataSizeMismatch {
+    section_id: u32,
+    path: String,
+    expected: usize,
+    actual: usize,
+  },
+}
+
+impl std::fmt::Display for ValidationIssue {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      ValidationIssue::SectionUnreadable { section_id, error } => {
+        write!(f, "section {}: failed to read data: {}", section_id, error)
+      }
+      ValidationIssue::BlockSizeMismatch {
+        section_id,
+        block,
+        expected,
+        actual,
+      } => write!(
+        f,
+        "section {}: {} block is {} bytes, expected {}",
+        section_id, block, actual, expected
+      ),
+      ValidationIssue::PageOutOfRange {
+        section_id,
+        block,
+        page,
+        page_count,
+      } => write!(
+        f,
+        "section {}: {} page {} is out of range ({} pages)",
+        section_id, block, page, page_count
+      ),
+      ValidationIssue::PageTooSmall {
+        section_id,
+        block,
+        page,
+        page_size,
+        block_size,
+      } => write!(
+        f,
+        "section {}: {} page {} holds {} bytes but the block is {} bytes",
+        section_id, block, page, page_size, block_size
+      ),
+      ValidationIssue::ComponentOutOfBounds {
+        section_id,
+        path,
+        offset,
+        size,
+        block_size,
+      } => write!(
+        f,
+        "section {}: {} ({} bytes at {}) doesn't fit in its {} byte block",
+        section_id, path, size, offset, block_size
+      ),
+      ValidationIssue::MissingSoiRecord {
+        section_id,
+        path,
+        kind,
+      } => write!(
+        f,
+        "section {}: no SOI record for {:?} {}",
+        section_id, kind, path
+      ),
+      ValidationIssue::StreamingDataSizeMismatch {
+        section_id,
+        path,
+        kind,
+        expected,
+        actual,
+      } => write!(
+        f,
+        "section {}: {:?} {} has {} bytes of streaming data, expected {}",
+        section_id, kind, path, actual, expected
+      ),
+      ValidationIssue::StaticDataSizeMismatch {
+        section_id,
+        path,
+        expected,
+        actual,
+      } => write!(
+        f,
+        "section {}: static texture {} has {} bytes of mip data, expected {}",
+        section_id, path, actual, expected
+      ),
+    }
+  }
+}
+
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+  pub sections_checked: usize,
+  pub components_checked: usize,
+  pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+  pub fn is_ok(&self) -> bool {
+    self.issues.is_empty()
+  }
+}
+
+// What the SOI record of a component says about its streaming data.
+enum Expected {
+  Size(usize),
+  // the record doesn't give a size to check against
+  NoStreamingData,
+  NoRecord,
+}
+
+impl From<Option<usize>> for Expected {
+  fn from(size: Option<usize>) -> Self {
+    size.map_or(Expected::NoStreamingData, Expected::Size)
+  }
+}
+
+impl<
+    StreamingTH: BinRead<Args<'static> = ()> + StreamingDataSize,
+    StaticTH: BinRead<Args<'static> = ()> + StaticDataSize,
+    MH: BinRead<Args<'static> = ()> + StreamingDataSize,
+  > SoiSoup<StreamingTH, StaticTH, MH>
+{
+  /// Cross-checks the TOC, SOI and STR of a level and collects every inconsistency instead of
+  /// stopping at the first one. The SOI pages aren't checked, see `validate_pages`.
+  pub fn validate(&self, str: &Str) -> ValidationReport {
+    let mut report = ValidationReport::default();
+
+    for (id, section) in self.find_sections().iter().enumerate() {
+      self.validate_section(str, id as u32, section, &mut report);
+      report.sections_checked += 1;
+    }
+
+    report
+  }
+
+  /// Checks that the SOI pages sections are streamed into exist and fit the section's blocks. The
+  /// page fields of the TOC are read as indices into the SOI's page size lists, which isn't
+  /// verified against game files, so these issues can be false positives.
+  pub fn validate_pages(&self) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+
+    for (section_id, section) in self.find_sections().iter().enumerate() {
+      let section_id = section_id as u32;
+      let header = &section.header;
+      let uncached_size = header.uncached_data_size.max(0) as usize;
+      let cached_size = header.cached_data_size.max(0) as usize;
+
+      let pages = [
+        (
+          Block::Uncached,
+          header.uncached_page(),
+          self.soi().get_uncached_page_sizes(),
+          uncached_size,
+        ),
+        (
+          Block::Cached,
+          header.cached_page(),
+          self.soi().get_cached_page_sizes(),
+          cached_size,
+        ),
+      ];
+      for (block, page, page_sizes, block_size) in pages {
+        let Some(page) = page else {
+          continue;
+        };
+
+        match page_sizes.get(page as usize) {
+          Some(&page_size) if (page_size.max(0) as usize) < block_size => {
+            issues.push(ValidationIssue::PageTooSmall {
+              section_id,
+              block,
+              page,
+              page_size: page_size.max(0) as usize,
+              block_size,
+            })
+          }
+          Some(_) => {}
+          None => issues.push(ValidationIssue::PageOutOfRange {
+            section_id,
+            block,
+            page,
+            page_count: page_sizes.len(),
+          }),
+        }
+      }
+    }
+
+    issues
+  }
+
+  fn validate_section(
+    &self,
+    str: &Str,
+    section_id: u32,
+    section: &Section,
+    report: &mut ValidationReport,
+  ) {
+    let header = &section.header;
+    let uncached_size = header.uncached_data_size.max(0) as usize;
+    let cached_size = header.cached_data_size.max(0) as usize;
+
+    let (uncached_data, cached_data) = match str.read_unchecked_section_blocks(section) {
+      Ok(blocks) => blocks,
+      Err(error) => {
+        report.issues.push(ValidationIssue::SectionUnreadable {
+          section_id,
+          error: error.to_string(),
+        });
+        return;
+      }
+    };
+
+    let blocks = [
+      (
+        Block::Uncached,
+        &section.uncached_components,
+        uncached_data,
+        uncached_size,
+      ),
+      (
+        Block::Cached,
+        &section.cached_components,
+        cached_data,
+        cached_size,
+      ),
+    ];
+    for (block, components, data, expected_size) in blocks {
+      if data.len() != expected_size {
+        report.issues.push(ValidationIssue::BlockSizeMismatch {
+          section_id,
+          block,
+          expected: expected_size,
+          actual: data.len(),
+        });
+      }
+
+      for component in components {
+        self.validate_component(section_id, component, &data, report);
+        report.components_checked += 1;
+      }
+    }
+  }
+
+  fn validate_component(
+    &self,
+    section_id: u32,
+    component: &ComponentHeader,
+    data: &[u8],
+    report: &mut ValidationReport,
+  ) {
+    let path = component.path();
+    // shared components live in another section's block, which is validated on its own
+    let source = self.toc().component_source(section_id, component);
+    let streaming_data = if source == ComponentSource::Owned {
+      let offset = component.memory_entry.offset;
+      let size = component.memory_entry.size;
+      let end = offset as i64 + size as i64;
+      if offset < 0 || size < 0 || end > data.len() as i64 {
+        report.issues.push(ValidationIssue::ComponentOutOfBounds {
+          section_id,
+          path: path.clone(),
+          offset,
+          size,
+          block_size: data.len(),
+        });
+        None
+      } else {
+        Some(&data[offset as usize..end as usize])
+      }
+    } else {
+      None
+    };
+
+    let expected = match self.resolve(section_id, component) {
+      ResolvedComponent::Model(model) => {
+        Expected::from(model.streaming_model_header.streaming_data_size())
+      }
+      ResolvedComponent::StreamingTexture(texture) => {
+        Expected::from(texture.header.streaming_data_size())
+      }
+      ResolvedComponent::StaticTexture(texture) => {
+        let sizes = texture.static_texture_header.static_data_size();
+        if let Some((expected, actual)) = sizes.filter(|(expected, actual)| expected != actual) {
+          report.issues.push(ValidationIssue::StaticDataSizeMismatch {
+            section_id,
+            path: path.clone(),
+            expected,
+            actual,
+          });
+        }
+        Expected::NoStreamingData
+      }
+      ResolvedComponent::Collision(_)
+      | ResolvedComponent::MotionPack(_)
+      | ResolvedComponent::CollisionGrid
+      | ResolvedComponent::UserData => Expected::NoStreamingData,
+      ResolvedComponent::Missing { .. } => Expected::NoRecord,
+    };
+
+    match (expected, streaming_data) {
+      (Expected::NoRecord, _) => report.issues.push(ValidationIssue::MissingSoiRecord {
+        section_id,
+        path,
+        kind: component.kind,
+      }),
+      (Expected::Size(expected), Some(streaming_data)) if expected != streaming_data.len() => {
+        report
+          .issues
+          .push(ValidationIssue::StreamingDataSizeMismatch {
+            section_id,
+            path,
+            kind: component.kind,
+            expected,
+            actual: streaming_data.len(),
+          })
+      }
+      _ => {}
+    }
+  }
+}
@@ -0,0 +1,214 @@
+use std::collections::BTreeMap;
+use std::io::Cursor;
+
+use crate::error::{Error, Result};
+use crate::{
+  ComponentHeader, ComponentSource, ModelConvert, SoiSoup, StaticTextureConvert, Str,
+  TextureConvert,
+};
+
+/// One place a file is stored in the level. The same path can show up in several sections.
+#[derive(Debug, Clone, Copy)]
+pub struct VfsOccurrence<'a> {
+  pub section_id: u32,
+  /// Position of the component in its section's `components`.
+  pub position: usize,
+  pub component: &'a ComponentHeader,
+  pub source: ComponentSource,
+}
+
+#[derive(Debug)]
+pub struct VfsFile<'a> {
+  name: String,
+  occurrences: Vec<VfsOccurrence<'a>>,
+}
+
+impl<'a> VfsFile<'a> {
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  pub fn occurrences(&self) -> &[VfsOccurrence<'a>] {
+    &self.occurrences
+  }
+}
+
+#[derive(Debug, Default)]
+pub struct VfsDirectory<'a> {
+  name: String,
+  // keyed by lowercase name, lookups are case-insensitive like the game's
+  directories: BTreeMap<String, VfsDirectory<'a>>,
+  files: BTreeMap<String, VfsFile<'a>>,
+}
+
+impl<'a> VfsDirectory<'a> {
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  pub fn directories(&self) -> impl Iterator<Item = &VfsDirectory<'a>> {
+    self.directories.values()
+  }
+
+  pub fn files(&self) -> impl Iterator<Item = &VfsFile<'a>> {
+    self.files.values()
+  }
+}
+
+fn split_path(path: &str) -> Vec<String> {
+  path
+    .split(['/', '\\'])
+    .filter(|part| !part.is_empty())
+    .map(|part| part.to_ascii_lowercase())
+    .collect()
+}
+
+/// Directory tree of a level's components. Files are named after their component path plus the
+/// extension of the standalone file they convert to, e.g. `cars/red.dxg`.
+pub struct Vfs<'a, StreamingTH: TextureConvert, StaticTH: StaticTextureConvert, MH: ModelConvert> {
+  soup: &'a SoiSoup<StreamingTH, StaticTH, MH>,
+  root: VfsDirectory<'a>,
+}
+
+impl<'a, StreamingTH: TextureConvert, StaticTH: StaticTextureConvert, MH: ModelConvert>
+  Vfs<'a, StreamingTH, StaticTH, MH>
+{
+  /// Components without an SOI record can't be converted and are left out.
+  pub fn new(soup: &'a SoiSoup<StreamingTH, StaticTH, MH>) -> Self {
+    let mut root = VfsDirectory::default();
+
+    let components = soup
+      .find_sections()
+      .iter()
+      .enumerate()
+      .flat_map(|(section_id, section)| {
+        section
+          .components()
+          .enumerate()
+          .map(move |(position, component)| (section_id as u32, position, component))
+      });
+    for (section_id, position, component) in components {
+      let Some(extension) = soup.resolve(section_id, component).extension() else {
+        continue;
+      };
+      let source = soup.toc().component_source(section_id, component);
+
+      let path = component.path();
+      let mut parts: Vec<&str> = path.split('/').filter(|part| !part.is_empty()).collect();
+      let Some(name) = parts.pop() else {
+        continue;
+      };
+
+      let mut directory = &mut root;
+      for part in parts {
+        directory = directory
+          .directories
+          .entry(part.to_ascii_lowercase())
+          .or_insert_with(|| VfsDirectory {
+            name: part.to_owned(),
+            ..Default::default()
+          });
+      }
+
+      let name = format!("{}.{}", name, extension);
+      directory
+        .files
+        .entry(name.to_ascii_lowercase())
+        .or_insert_with(|| VfsFile {
+          name,
+          occurrences: Vec::new(),
+        })
+        .occurrences
+        .push(VfsOccurrence {
+          section_id,
+          position,
+          component,
+          source,
+        });
+    }
+
+    Self { soup, root }
+  }
+
+  pub fn root(&self) -> &VfsDirectory<'a> {
+    &self.root
+  }
+
+  /// Lists a directory, `None` if there's no directory at that path.
+  pub fn read_dir(&self, path: &str) -> Option<&VfsDirectory<'a>> {
+    split_path(path)
+      .iter()
+      .try_fold(&self.root, |directory, part| {
+        directory.directories.get(part)
+      })
+  }
+
+  pub fn file(&self, path: &str) -> Option<&VfsFile<'a>> {
+    let mut parts = split_path(path);
+    let name = parts.pop()?;
+
+    parts
+      .iter()
+      .try_fold(&self.root, |directory, part| {
+        directory.directories.get(part)
+      })?
+      .files
+      .get(&name)
+  }
+
+  /// Converts a file to its standalone form. For paths stored in several sections the first
+  /// occurrence is used, see `open_occurrence` to pick a specific one.
+  pub fn open(&self, str: &Str, path: &str) -> Result<Cursor<Vec<u8>>> {
+    let occurrence = self
+      .file(path)
+      .and_then(|file| file.occurrences.first())
+      .ok_or_else(|| Error::FileNotFound {
+        path: path.to_owned(),
+      })?;
+
+    self.open_occurrence(str, occurrence)
+  }
+
+  pub fn open_occurrence(&self, str: &Str, occurrence: &VfsOccurrence) -> Result<Cursor<Vec<u8>>> {
+    let component = occurrence.component;
+
+    // shared components are stored in the shared section's data
+    let (data_section_id, shared_id) = match occurrence.source {
+      ComponentSource::Owned => (occurrence.section_id, None),
+      ComponentSource::Shared {
+        section_id,
+        component_id,
+      } => (section_id, Some(component_id)),
+    };
+    let section = self
+      .soup
+      .toc()
+      .find_section(data_section_id)
+      .ok_or(Error::SectionNotFound {
+        section_id: data_section_id,
+      })?;
+    let position = match shared_id {
+      None => occurrence.position,
+      Some(component_id) => section
+        .components()
+        .position(|shared| shared.id as u32 == component_id)
+        .ok_or_else(|| Error::FileNotFound {
+          path: component.path(),
+        })?,
+    };
+
+    let mut output = Cursor::new(Vec::new());
+    str
+      .read_component_at(section, position)
+      .and_then(|data| {
+        self
+          .soup
+          .resolve(occurrence.section_id, component)
+          .write(&mut output, &data)
+      })
+      .map_err(|err| err.with_component(component.path()))?;
+    output.set_position(0);
+
+    Ok(output)
+  }
+}